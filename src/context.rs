@@ -0,0 +1,525 @@
+use std::ffi::CStr;
+use std::sync::{Arc, Mutex, MutexGuard};
+
+pub use ash::prelude::*;
+pub use ash::version::*;
+pub use ash::vk;
+
+pub use crate::error::*;
+
+use crate::device::Allocator;
+
+pub const ALLOC: Option<&vk::AllocationCallbacks> = None;
+
+pub mod ext {
+    pub use ash::extensions::ext::DebugUtils;
+    #[cfg(windows)]
+    pub use ash::extensions::khr::Win32Surface;
+    pub use ash::extensions::khr::{Surface, Swapchain};
+    #[cfg(unix)]
+    pub use ash::extensions::khr::{WaylandSurface, XcbSurface, XlibSurface};
+
+    use std::ffi::CStr;
+
+    #[derive(Copy, Clone, Debug, Eq, PartialEq)]
+    pub enum PlatformSurfaceKind {
+        #[cfg(windows)]
+        Win32,
+        #[cfg(unix)]
+        Xlib,
+        #[cfg(unix)]
+        Xcb,
+        #[cfg(unix)]
+        Wayland,
+    }
+
+    impl PlatformSurfaceKind {
+        pub fn extension_name(self) -> &'static CStr {
+            match self {
+                #[cfg(windows)]
+                Self::Win32 => Win32Surface::name(),
+                #[cfg(unix)]
+                Self::Xlib => XlibSurface::name(),
+                #[cfg(unix)]
+                Self::Xcb => XcbSurface::name(),
+                #[cfg(unix)]
+                Self::Wayland => WaylandSurface::name(),
+            }
+        }
+    }
+
+    /// The surface extension loader for the window system we are running under.
+    pub enum PlatformSurface {
+        #[cfg(windows)]
+        Win32(Win32Surface),
+        #[cfg(unix)]
+        Xlib(XlibSurface),
+        #[cfg(unix)]
+        Xcb(XcbSurface),
+        #[cfg(unix)]
+        Wayland(WaylandSurface),
+    }
+
+    impl PlatformSurface {
+        pub fn new(
+            kind: PlatformSurfaceKind,
+            entry: &ash::Entry,
+            instance: &ash::Instance,
+        ) -> Self {
+            match kind {
+                #[cfg(windows)]
+                PlatformSurfaceKind::Win32 => Self::Win32(Win32Surface::new(entry, instance)),
+                #[cfg(unix)]
+                PlatformSurfaceKind::Xlib => Self::Xlib(XlibSurface::new(entry, instance)),
+                #[cfg(unix)]
+                PlatformSurfaceKind::Xcb => Self::Xcb(XcbSurface::new(entry, instance)),
+                #[cfg(unix)]
+                PlatformSurfaceKind::Wayland => Self::Wayland(WaylandSurface::new(entry, instance)),
+            }
+        }
+    }
+}
+
+macro_rules! names {
+    ($($ident: ident),* $(,)?) => {
+        &[
+            $( concat!(stringify!($ident), "\0").as_ptr().cast::<std::os::raw::c_char>() ,)*
+        ]
+    };
+}
+
+pub fn supports_instance_extension(entry: &ash::Entry, name: &CStr) -> Result<bool> {
+    Ok(entry
+        .enumerate_instance_extension_properties()?
+        .iter()
+        .any(|props| unsafe { CStr::from_ptr(props.extension_name.as_ptr()) } == name))
+}
+
+/// The Vulkan instance, with the loaders for the instance extensions in use.
+pub struct Instance {
+    /// Keeps the Vulkan library loaded for as long as the instance.
+    _entry: ash::Entry,
+    pub instance: ash::Instance,
+    debug_utils: Option<(ext::DebugUtils, vk::DebugUtilsMessengerEXT)>,
+    pub surface: Option<ext::Surface>,
+    pub platform_surface: Option<ext::PlatformSurface>,
+    /// Needed to query memory budgets.
+    pub properties2: Option<vk::KhrGetPhysicalDeviceProperties2Fn>,
+}
+
+impl Instance {
+    /// Creates the instance, with surface extensions only if `surface_kind` is provided,
+    /// e.g. `None` for headless rendering.
+    pub fn create(
+        entry: ash::Entry,
+        surface_kind: Option<ext::PlatformSurfaceKind>,
+        validation: bool,
+    ) -> Result<Arc<Self>> {
+        let mut extension_names = vec![];
+        let layer_names: &[*const std::os::raw::c_char] = if validation {
+            extension_names.push(ext::DebugUtils::name().as_ptr());
+            names![
+                VK_LAYER_KHRONOS_validation,
+                VK_LAYER_LUNARG_standard_validation,
+            ]
+        } else {
+            &[]
+        };
+        let properties2 = vk::KhrGetPhysicalDeviceProperties2Fn::name();
+        let has_properties2 = supports_instance_extension(&entry, properties2)?;
+        if has_properties2 {
+            extension_names.push(properties2.as_ptr());
+        }
+        if let Some(surface_kind) = surface_kind {
+            extension_names.push(ext::Surface::name().as_ptr());
+            extension_names.push(surface_kind.extension_name().as_ptr());
+            // Adds the HDR color spaces, if the surface supports any.
+            let colorspace = vk::ExtSwapchainColorspaceFn::name();
+            if supports_instance_extension(&entry, colorspace)? {
+                extension_names.push(colorspace.as_ptr());
+            }
+        }
+        let instance = unsafe {
+            entry.create_instance(
+                &vk::InstanceCreateInfo::builder()
+                    .enabled_layer_names(layer_names)
+                    .enabled_extension_names(&extension_names)
+                    .build(),
+                ALLOC,
+            )?
+        };
+
+        let debug_utils = if validation {
+            let loader = ext::DebugUtils::new(&entry, &instance);
+            let messenger = unsafe {
+                loader.create_debug_utils_messenger(
+                    &vk::DebugUtilsMessengerCreateInfoEXT::builder()
+                        .message_severity(vk::DebugUtilsMessageSeverityFlagsEXT::all())
+                        .message_type(vk::DebugUtilsMessageTypeFlagsEXT::all())
+                        .pfn_user_callback(Some(debug_callback))
+                        .build(),
+                    ALLOC,
+                )?
+            };
+            Some((loader, messenger))
+        } else {
+            None
+        };
+
+        let properties2 = if has_properties2 {
+            Some(vk::KhrGetPhysicalDeviceProperties2Fn::load(|name| unsafe {
+                std::mem::transmute(entry.get_instance_proc_addr(instance.handle(), name.as_ptr()))
+            }))
+        } else {
+            None
+        };
+
+        Ok(Arc::new(Self {
+            surface: surface_kind.map(|_| ext::Surface::new(&entry, &instance)),
+            platform_surface: surface_kind
+                .map(|kind| ext::PlatformSurface::new(kind, &entry, &instance)),
+            debug_utils,
+            properties2,
+            instance,
+            _entry: entry,
+        }))
+    }
+
+    fn surface_loader(&self) -> VkResult<&ext::Surface> {
+        self.surface
+            .as_ref()
+            .ok_or(vk::Result::ERROR_EXTENSION_NOT_PRESENT)
+    }
+}
+
+impl Drop for Instance {
+    fn drop(&mut self) {
+        unsafe {
+            if let Some((loader, messenger)) = &self.debug_utils {
+                loader.destroy_debug_utils_messenger(*messenger, ALLOC);
+            }
+            self.instance.destroy_instance(ALLOC);
+        }
+    }
+}
+
+/// A window surface, destroyed along with its instance.
+pub struct Surface {
+    instance: Arc<Instance>,
+    handle: vk::SurfaceKHR,
+}
+
+impl AsRef<vk::SurfaceKHR> for Surface {
+    fn as_ref(&self) -> &vk::SurfaceKHR {
+        &self.handle
+    }
+}
+
+impl Surface {
+    pub unsafe fn from_raw(instance: &Arc<Instance>, handle: vk::SurfaceKHR) -> Self {
+        Self {
+            instance: instance.clone(),
+            handle,
+        }
+    }
+}
+
+impl Drop for Surface {
+    fn drop(&mut self) {
+        if let Ok(loader) = self.instance.surface_loader() {
+            unsafe { loader.destroy_surface(self.handle, ALLOC) }
+        }
+    }
+}
+
+/// What a queue is used for. Devices may have dedicated queues for transfers and compute, which
+/// can run alongside graphics work.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum QueueKind {
+    Graphics,
+    /// Copies, e.g. uploads, on a transfer-only queue.
+    Transfer,
+    /// Compute work on a queue without graphics, for async compute.
+    Compute,
+}
+
+/// A device queue, which must be externally synchronized.
+pub struct Queue {
+    pub family_index: u32,
+    queue: Mutex<vk::Queue>,
+}
+
+impl Queue {
+    fn get(device: &ash::Device, family_index: u32) -> Self {
+        Self {
+            family_index,
+            queue: Mutex::new(unsafe { device.get_device_queue(family_index, 0) }),
+        }
+    }
+
+    /// Locks the queue for submission or presentation.
+    pub fn lock(&self) -> MutexGuard<'_, vk::Queue> {
+        self.queue.lock().unwrap()
+    }
+}
+
+/// A logical device and the physical device properties and extension loaders needed to use it.
+///
+/// Objects created on the device keep an `Arc` to the context, so it outlives them all, and it
+/// can be shared between threads.
+pub struct Context {
+    pub instance: Arc<Instance>,
+    pub physical_device: vk::PhysicalDevice,
+    pub memory_properties: vk::PhysicalDeviceMemoryProperties,
+    pub limits: vk::PhysicalDeviceLimits,
+    /// The optional features enabled on the device.
+    pub features: vk::PhysicalDeviceFeatures,
+    pub device: ash::Device,
+    pub swapchain: Option<ext::Swapchain>,
+    /// Whether `VK_EXT_memory_budget` is enabled, so heap budgets can be queried.
+    pub memory_budget: bool,
+    pub graphics_queue: Queue,
+    pub transfer_queue: Option<Queue>,
+    pub compute_queue: Option<Queue>,
+    pub(crate) allocator: Mutex<Allocator>,
+}
+
+impl Context {
+    /// Creates a device on the first physical device with a graphics queue, which must also be
+    /// able to present to `surface` if provided, and dedicated transfer and compute queues if it
+    /// has them.
+    pub fn create(instance: &Arc<Instance>, surface: Option<vk::SurfaceKHR>) -> Result<Arc<Self>> {
+        let (physical_device, graphics_family_index) =
+            select_physical_device_and_graphics_queue(instance, surface)?
+                .ok_or(Error::VkSupport)?;
+        let queue_families = unsafe {
+            instance
+                .instance
+                .get_physical_device_queue_family_properties(physical_device)
+        };
+        // Transfer-only families are usually DMA engines, which copy while the graphics and
+        // compute units are busy.
+        let transfer_family_index = find_queue_family(
+            &queue_families,
+            vk::QueueFlags::TRANSFER,
+            vk::QueueFlags::GRAPHICS | vk::QueueFlags::COMPUTE,
+        );
+        let compute_family_index = find_queue_family(
+            &queue_families,
+            vk::QueueFlags::COMPUTE,
+            vk::QueueFlags::GRAPHICS,
+        );
+        let queue_create_infos = std::iter::once(graphics_family_index)
+            .chain(transfer_family_index)
+            .chain(compute_family_index)
+            .map(|family_index| {
+                vk::DeviceQueueCreateInfo::builder()
+                    .queue_family_index(family_index)
+                    .queue_priorities(&[1.0])
+                    .build()
+            })
+            .collect::<Vec<_>>();
+
+        let (memory_properties, properties, supported_features, supported) = unsafe {
+            (
+                instance
+                    .instance
+                    .get_physical_device_memory_properties(physical_device),
+                instance
+                    .instance
+                    .get_physical_device_properties(physical_device),
+                instance
+                    .instance
+                    .get_physical_device_features(physical_device),
+                instance
+                    .instance
+                    .enumerate_device_extension_properties(physical_device)?,
+            )
+        };
+        let supports = |name: &CStr| {
+            supported
+                .iter()
+                .any(|props| unsafe { CStr::from_ptr(props.extension_name.as_ptr()) } == name)
+        };
+        let memory_budget =
+            instance.properties2.is_some() && supports(vk::ExtMemoryBudgetFn::name());
+
+        // Materials can draw wireframes and clamp depth bias where supported.
+        let features = vk::PhysicalDeviceFeatures::builder()
+            .sampler_anisotropy(true)
+            .fill_mode_non_solid(supported_features.fill_mode_non_solid == vk::TRUE)
+            .depth_bias_clamp(supported_features.depth_bias_clamp == vk::TRUE)
+            .build();
+
+        let mut extension_names = vec![];
+        if surface.is_some() {
+            extension_names.push(ext::Swapchain::name().as_ptr());
+        }
+        if memory_budget {
+            extension_names.push(vk::ExtMemoryBudgetFn::name().as_ptr());
+        }
+        let device = unsafe {
+            instance.instance.create_device(
+                physical_device,
+                &vk::DeviceCreateInfo::builder()
+                    .enabled_extension_names(&extension_names)
+                    .queue_create_infos(&queue_create_infos)
+                    .enabled_features(&features)
+                    .build(),
+                ALLOC,
+            )?
+        };
+
+        Ok(Arc::new(Self {
+            instance: instance.clone(),
+            physical_device,
+            memory_properties,
+            limits: properties.limits,
+            features,
+            swapchain: surface.map(|_| ext::Swapchain::new(&instance.instance, &device)),
+            memory_budget,
+            graphics_queue: Queue::get(&device, graphics_family_index),
+            transfer_queue: transfer_family_index.map(|index| Queue::get(&device, index)),
+            compute_queue: compute_family_index.map(|index| Queue::get(&device, index)),
+            allocator: Mutex::new(Allocator::new()),
+            device,
+        }))
+    }
+
+    pub fn surface_loader(&self) -> VkResult<&ext::Surface> {
+        self.instance.surface_loader()
+    }
+
+    pub fn swapchain_loader(&self) -> VkResult<&ext::Swapchain> {
+        self.swapchain
+            .as_ref()
+            .ok_or(vk::Result::ERROR_EXTENSION_NOT_PRESENT)
+    }
+
+    /// The queue to submit `kind` work to, which is the graphics queue if the device has no
+    /// dedicated queue for it.
+    pub fn queue(&self, kind: QueueKind) -> &Queue {
+        match kind {
+            QueueKind::Graphics => None,
+            QueueKind::Transfer => self.transfer_queue.as_ref(),
+            QueueKind::Compute => self.compute_queue.as_ref(),
+        }
+        .unwrap_or(&self.graphics_queue)
+    }
+
+    /// Blocks until the device has finished all submitted work, e.g. before destroying
+    /// resources that frames in flight may still be using.
+    pub fn wait_idle(&self) -> VkResult<()> {
+        // Waiting for the device counts as using each of its queues.
+        let _queues = std::iter::once(&self.graphics_queue)
+            .chain(&self.transfer_queue)
+            .chain(&self.compute_queue)
+            .map(Queue::lock)
+            .collect::<Vec<_>>();
+        unsafe { self.device.device_wait_idle() }
+    }
+}
+
+impl Drop for Context {
+    fn drop(&mut self) {
+        unsafe {
+            self.allocator.get_mut().unwrap().destroy(&self.device);
+            self.device.destroy_device(ALLOC);
+        }
+    }
+}
+
+fn select_physical_device_and_graphics_queue(
+    instance: &Instance,
+    surface: Option<vk::SurfaceKHR>,
+) -> Result<Option<(vk::PhysicalDevice, u32)>> {
+    unsafe {
+        for pd in instance.instance.enumerate_physical_devices()? {
+            let qfps = instance
+                .instance
+                .get_physical_device_queue_family_properties(pd);
+            for (index, queue_family_props) in qfps.into_iter().enumerate() {
+                if !queue_family_props
+                    .queue_flags
+                    .contains(vk::QueueFlags::GRAPHICS)
+                {
+                    continue;
+                }
+                if let Some(surface) = surface {
+                    if !instance
+                        .surface_loader()?
+                        .get_physical_device_surface_support(pd, index as u32, surface)?
+                    {
+                        continue;
+                    }
+                }
+                return Ok(Some((pd, index as u32)));
+            }
+        }
+    }
+
+    Ok(None)
+}
+
+/// Returns the first queue family with all of `flags` and none of `excluded`.
+fn find_queue_family(
+    families: &[vk::QueueFamilyProperties],
+    flags: vk::QueueFlags,
+    excluded: vk::QueueFlags,
+) -> Option<u32> {
+    families
+        .iter()
+        .position(|family| {
+            family.queue_count > 0
+                && family.queue_flags.contains(flags)
+                && !family.queue_flags.intersects(excluded)
+        })
+        .map(|index| index as u32)
+}
+
+unsafe extern "system" fn debug_callback(
+    message_severity: vk::DebugUtilsMessageSeverityFlagsEXT,
+    message_types: vk::DebugUtilsMessageTypeFlagsEXT,
+    p_callback_data: *const vk::DebugUtilsMessengerCallbackDataEXT,
+    _p_user_data: *mut std::ffi::c_void,
+) -> vk::Bool32 {
+    let data = &*p_callback_data;
+    let message = CStr::from_ptr(data.p_message).to_string_lossy();
+    if message.starts_with("Device Extension: ") {
+        return vk::FALSE;
+    }
+
+    eprintln!(
+        "{:?} {:?}: [{}: {}] {}",
+        message_severity,
+        message_types,
+        CStr::from_ptr(data.p_message_id_name).to_string_lossy(),
+        data.message_id_number,
+        message,
+    );
+
+    if message_severity.intersects(
+        vk::DebugUtilsMessageSeverityFlagsEXT::WARNING
+            | vk::DebugUtilsMessageSeverityFlagsEXT::ERROR,
+    ) {
+        debug_break();
+    }
+
+    vk::FALSE
+}
+
+#[cfg(windows)]
+unsafe fn debug_break() {
+    extern "system" {
+        fn IsDebuggerPresent() -> bool;
+        fn DebugBreak();
+    }
+    if IsDebuggerPresent() {
+        DebugBreak();
+    } else {
+        // std::process::exit(1);
+    }
+}
+
+#[cfg(not(windows))]
+unsafe fn debug_break() {}
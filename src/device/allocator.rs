@@ -0,0 +1,780 @@
+//! Sub-allocation of device memory, so resources don't each need a `vkAllocateMemory`,
+//! of which there can be as few as 4096.
+//!
+//! Each memory type has a pool of blocks for linear resources (buffers) and another for
+//! optimally tiled images, which keeps them `bufferImageGranularity` apart without padding.
+//! Blocks are split with a buddy scheme: allocations are rounded up to a power of two, and are
+//! aligned to their size, which satisfies any alignment up to that size. Allocations larger than
+//! half a block get dedicated memory.
+//!
+//! Host visible memory stays mapped for as long as it's allocated. Writes to memory that isn't
+//! host coherent are flushed when a [`MappedSlice`] is dropped, and reads invalidate first.
+//! Both are rounded out to `nonCoherentAtomSize`, which is at most 256 bytes, so they never
+//! touch another allocation.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use std::marker::PhantomData;
+use std::ops::{Deref, DerefMut};
+
+use super::*;
+
+const MAX_BLOCK_SIZE: vk::DeviceSize = 64 << 20;
+const MIN_ALLOCATION_SIZE: vk::DeviceSize = 256;
+
+/// Whether a resource is linear or optimally tiled, which must not share a
+/// `bufferImageGranularity` sized page.
+#[derive(Copy, Clone, Debug, Ord, PartialOrd, Eq, PartialEq)]
+pub enum ResourceKind {
+    /// Buffers and linear images.
+    Linear,
+    Optimal,
+}
+
+/// Memory bound to a single resource, returned to its pool when dropped.
+pub struct Allocation {
+    context: Arc<Context>,
+    memory: vk::DeviceMemory,
+    offset: vk::DeviceSize,
+    size: vk::DeviceSize,
+    type_index: MemoryTypeIndex,
+    purpose: MemoryPurpose,
+    /// The size of `memory`, to which flushed ranges are clamped.
+    memory_size: vk::DeviceSize,
+    /// The start of the allocation in the persistent mapping of its memory, if host visible.
+    mapped: *mut c_void,
+    source: AllocationSource,
+}
+
+// Mapped memory is only accessed through `&mut self` or copies out of it, which callers
+// synchronize with device use of the memory, as with any other Vulkan object.
+unsafe impl Send for Allocation {}
+unsafe impl Sync for Allocation {}
+
+enum AllocationSource {
+    Block {
+        kind: ResourceKind,
+        block: u64,
+        order: u32,
+    },
+    Dedicated(Memory),
+}
+
+impl AsRef<vk::DeviceMemory> for Allocation {
+    fn as_ref(&self) -> &vk::DeviceMemory {
+        &self.memory
+    }
+}
+
+impl Drop for Allocation {
+    fn drop(&mut self) {
+        let mut allocator = self.context.allocator.lock().unwrap();
+        let stats = allocator
+            .purposes
+            .entry((self.type_index, self.purpose))
+            .or_default();
+        stats.0 -= 1;
+        stats.1 -= self.size;
+        match &self.source {
+            &AllocationSource::Block { kind, block, order } => {
+                let pool = allocator
+                    .pools
+                    .get_mut(&(self.type_index, kind))
+                    .expect("allocation from unknown pool");
+                if let Some(block) = pool.free(block, self.offset, order) {
+                    unsafe { block.destroy(&self.context.device) };
+                }
+            }
+            AllocationSource::Dedicated(memory) => {
+                if !self.mapped.is_null() {
+                    unsafe { self.context.device.unmap_memory(memory.as_raw()) }
+                }
+                let stats = allocator.dedicated.entry(self.type_index).or_default();
+                stats.0 -= 1;
+                stats.1 -= self.size;
+            }
+        }
+    }
+}
+
+impl Allocation {
+    /// Allocates memory for a resource with `requirements` from the first memory type also in
+    /// `type_mask`. If all of those are host visible, coherent types are preferred, so that
+    /// writes don't need flushing. `purpose` is only used for statistics.
+    pub fn allocate(
+        context: &Arc<Context>,
+        requirements: &vk::MemoryRequirements,
+        type_mask: MemoryTypeMask,
+        kind: ResourceKind,
+        purpose: MemoryPurpose,
+    ) -> VkResult<Self> {
+        let allowed = MemoryTypeMask(requirements.memory_type_bits) & type_mask;
+        let coherent = allowed
+            & MemoryTypeMask::with_properties(
+                context,
+                vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
+            );
+        let type_index = if allowed & MemoryTypeMask::mappable(context) == allowed
+            && coherent != MemoryTypeMask::none()
+        {
+            coherent.first_index()
+        } else {
+            allowed.first_index()
+        };
+        if !type_index.is_valid(context) {
+            return Err(vk::Result::ERROR_OUT_OF_DEVICE_MEMORY);
+        }
+
+        let mut allocator = context.allocator.lock().unwrap();
+        let allocator = &mut *allocator;
+        let block_size = block_size(context, type_index);
+        let size = match sub_allocation_size(requirements, block_size) {
+            Some(size) => size,
+            None => {
+                let memory = Memory::allocate(context, requirements.size, type_index)?;
+                let mapped = map_whole(context, memory.as_raw(), type_index)?;
+                let stats = allocator.dedicated.entry(type_index).or_default();
+                stats.0 += 1;
+                stats.1 += requirements.size;
+                add_purpose(allocator, type_index, purpose, requirements.size);
+                return Ok(Self {
+                    context: context.clone(),
+                    memory: memory.as_raw(),
+                    offset: 0,
+                    size: requirements.size,
+                    type_index,
+                    purpose,
+                    memory_size: requirements.size,
+                    mapped,
+                    source: AllocationSource::Dedicated(memory),
+                });
+            }
+        };
+
+        let pool = allocator.pool(type_index, kind, block_size);
+        let (block, offset, order) =
+            pool.allocate(size, || Block::create(context, type_index, block_size))?;
+        let block_ref = &pool.blocks[&block];
+        let (memory, mapped) = (block_ref.memory, block_ref.mapped);
+        add_purpose(allocator, type_index, purpose, requirements.size);
+        Ok(Self {
+            context: context.clone(),
+            memory,
+            offset,
+            size: requirements.size,
+            type_index,
+            purpose,
+            memory_size: block_size,
+            mapped: if mapped.is_null() {
+                std::ptr::null_mut()
+            } else {
+                unsafe { mapped.add(offset as usize) }
+            },
+            source: AllocationSource::Block { kind, block, order },
+        })
+    }
+
+    /// The offset to bind the resource at.
+    pub fn offset(&self) -> vk::DeviceSize {
+        self.offset
+    }
+
+    pub fn size(&self) -> vk::DeviceSize {
+        self.size
+    }
+
+    pub fn type_index(&self) -> MemoryTypeIndex {
+        self.type_index
+    }
+
+    /// Whether writes must be flushed and reads invalidated.
+    pub fn is_coherent(&self) -> bool {
+        self.type_index
+            .property_flags(&self.context)
+            .contains(vk::MemoryPropertyFlags::HOST_COHERENT)
+    }
+
+    /// Returns a view of `len` elements at `offset` bytes from the start of the allocation,
+    /// which must be host visible. Writes through it are flushed when it's dropped.
+    pub fn slice_mut<T: Copy>(
+        &mut self,
+        offset: vk::DeviceSize,
+        len: usize,
+    ) -> VkResult<MappedSlice<'_, T>> {
+        let ptr = self.host_ptr::<T>(offset, len)?;
+        Ok(MappedSlice {
+            allocation: self,
+            offset,
+            ptr,
+            len,
+            _marker: PhantomData,
+        })
+    }
+
+    pub fn write<T: Copy>(&mut self, offset: vk::DeviceSize, source: &T) -> VkResult<()> {
+        self.slice_mut::<T>(offset, 1)?[0] = *source;
+        Ok(())
+    }
+
+    pub fn write_slice<T: Copy>(&mut self, offset: vk::DeviceSize, source: &[T]) -> VkResult<()> {
+        self.slice_mut::<T>(offset, source.len())?
+            .copy_from_slice(source);
+        Ok(())
+    }
+
+    /// Copies `len` elements at `offset` bytes from the start of the allocation, after making
+    /// device writes to them visible.
+    pub fn read_vec<T: Copy>(&self, offset: vk::DeviceSize, len: usize) -> VkResult<Vec<T>> {
+        let ptr = self.host_ptr::<T>(offset, len)?;
+        self.invalidate(offset, size_of::<T>() * len as vk::DeviceSize)?;
+        Ok(unsafe { std::slice::from_raw_parts(ptr, len) }.to_vec())
+    }
+
+    /// Makes host writes to `size` bytes at `offset` visible to the device.
+    pub fn flush(&self, offset: vk::DeviceSize, size: vk::DeviceSize) -> VkResult<()> {
+        if self.is_coherent() || size == 0 {
+            return Ok(());
+        }
+        unsafe {
+            self.context
+                .device
+                .flush_mapped_memory_ranges(&[self.mapped_range(offset, size)])
+        }
+    }
+
+    /// Makes device writes to `size` bytes at `offset` visible to the host.
+    pub fn invalidate(&self, offset: vk::DeviceSize, size: vk::DeviceSize) -> VkResult<()> {
+        if self.is_coherent() || size == 0 {
+            return Ok(());
+        }
+        unsafe {
+            self.context
+                .device
+                .invalidate_mapped_memory_ranges(&[self.mapped_range(offset, size)])
+        }
+    }
+
+    fn host_ptr<T>(&self, offset: vk::DeviceSize, len: usize) -> VkResult<*mut T> {
+        let size = size_of::<T>() * len as vk::DeviceSize;
+        assert!(
+            offset + size <= self.size,
+            "offset = {:#x}, size = {:#x}, allocation size = {:#x}",
+            offset,
+            size,
+            self.size,
+        );
+        if self.mapped.is_null() {
+            return Err(vk::Result::ERROR_MEMORY_MAP_FAILED);
+        }
+        let ptr = unsafe { self.mapped.add(offset as usize) }.cast::<T>();
+        assert!(ptr.is_aligned(), "misaligned offset = {:#x}", offset);
+        Ok(ptr)
+    }
+
+    /// The range of `memory` covering `size` bytes at `offset`, rounded out to whole atoms.
+    fn mapped_range(&self, offset: vk::DeviceSize, size: vk::DeviceSize) -> vk::MappedMemoryRange {
+        let atom = self.context.limits.non_coherent_atom_size;
+        let start = (self.offset + offset) / atom * atom;
+        let end = (self.offset + offset + size).div_ceil(atom) * atom;
+        vk::MappedMemoryRange::builder()
+            .memory(self.memory)
+            .offset(start)
+            .size(if end > self.memory_size {
+                vk::WHOLE_SIZE
+            } else {
+                end - start
+            })
+            .build()
+    }
+}
+
+/// A typed view of host visible memory, which flushes it when dropped.
+pub struct MappedSlice<'a, T: Copy> {
+    allocation: &'a Allocation,
+    offset: vk::DeviceSize,
+    ptr: *mut T,
+    len: usize,
+    _marker: PhantomData<&'a mut [T]>,
+}
+
+impl<T: Copy> Deref for MappedSlice<'_, T> {
+    type Target = [T];
+    fn deref(&self) -> &[T] {
+        unsafe { std::slice::from_raw_parts(self.ptr, self.len) }
+    }
+}
+
+impl<T: Copy> DerefMut for MappedSlice<'_, T> {
+    fn deref_mut(&mut self) -> &mut [T] {
+        unsafe { std::slice::from_raw_parts_mut(self.ptr, self.len) }
+    }
+}
+
+impl<T: Copy> Drop for MappedSlice<'_, T> {
+    fn drop(&mut self) {
+        let size = size_of::<T>() * self.len as vk::DeviceSize;
+        if let Err(err) = self.allocation.flush(self.offset, size) {
+            eprintln!("failed to flush mapped memory: {}", err);
+        }
+    }
+}
+
+fn add_purpose(
+    allocator: &mut Allocator,
+    type_index: MemoryTypeIndex,
+    purpose: MemoryPurpose,
+    size: vk::DeviceSize,
+) {
+    let stats = allocator.purposes.entry((type_index, purpose)).or_default();
+    stats.0 += 1;
+    stats.1 += size;
+}
+
+/// Maps the whole of `memory` if it's host visible, or returns null.
+fn map_whole(
+    context: &Context,
+    memory: vk::DeviceMemory,
+    type_index: MemoryTypeIndex,
+) -> VkResult<*mut c_void> {
+    if !type_index
+        .property_flags(context)
+        .contains(vk::MemoryPropertyFlags::HOST_VISIBLE)
+    {
+        return Ok(std::ptr::null_mut());
+    }
+    unsafe {
+        context
+            .device
+            .map_memory(memory, 0, vk::WHOLE_SIZE, vk::MemoryMapFlags::empty())
+    }
+}
+
+/// The size of a sub-allocation for a resource with `requirements`, a power of two which is also
+/// its alignment, or `None` if it's too large for a block and needs dedicated memory.
+fn sub_allocation_size(
+    requirements: &vk::MemoryRequirements,
+    block_size: vk::DeviceSize,
+) -> Option<vk::DeviceSize> {
+    let size = requirements
+        .size
+        .max(requirements.alignment)
+        .max(MIN_ALLOCATION_SIZE)
+        .next_power_of_two();
+    if size > block_size / 2 {
+        None
+    } else {
+        Some(size)
+    }
+}
+
+/// Smaller blocks for small heaps, so one block can't take a large part of them.
+fn block_size(context: &Context, type_index: MemoryTypeIndex) -> vk::DeviceSize {
+    let properties = &context.memory_properties;
+    let heap_index = properties.memory_types[type_index.0 as usize].heap_index;
+    let heap_size = properties.memory_heaps[heap_index as usize].size;
+    let limit = heap_size / 8;
+    // The largest power of two not above the limit.
+    let limit: vk::DeviceSize = 1 << (63 - limit.max(MIN_ALLOCATION_SIZE * 2).leading_zeros());
+    MAX_BLOCK_SIZE.min(limit)
+}
+
+/// The memory pools of a device context.
+pub(crate) struct Allocator {
+    pools: BTreeMap<(MemoryTypeIndex, ResourceKind), Pool>,
+    /// Count and bytes of dedicated allocations.
+    dedicated: BTreeMap<MemoryTypeIndex, (usize, vk::DeviceSize)>,
+    /// Count and bytes of all allocations, by what they're used for.
+    purposes: BTreeMap<(MemoryTypeIndex, MemoryPurpose), (usize, vk::DeviceSize)>,
+}
+
+impl Allocator {
+    pub(crate) fn new() -> Self {
+        Self {
+            pools: BTreeMap::new(),
+            dedicated: BTreeMap::new(),
+            purposes: BTreeMap::new(),
+        }
+    }
+
+    /// Linear and optimal resources have separate pools, so they never share a block and can't
+    /// be within `bufferImageGranularity` of each other.
+    fn pool(
+        &mut self,
+        type_index: MemoryTypeIndex,
+        kind: ResourceKind,
+        block_size: vk::DeviceSize,
+    ) -> &mut Pool {
+        self.pools
+            .entry((type_index, kind))
+            .or_insert_with(|| Pool::new(block_size))
+    }
+
+    /// Frees the blocks, which the allocations keep the context alive for, so there are none
+    /// left by the time the context is dropped.
+    pub(crate) unsafe fn destroy(&mut self, device: &ash::Device) {
+        for (_, pool) in std::mem::take(&mut self.pools) {
+            for (_, block) in pool.blocks {
+                block.destroy(device);
+            }
+        }
+    }
+}
+
+struct Pool {
+    block_size: vk::DeviceSize,
+    blocks: BTreeMap<u64, Block>,
+    next_block: u64,
+}
+
+/// Device memory owned by its pool, as the blocks can't keep the context alive like `Memory`.
+struct Block {
+    memory: vk::DeviceMemory,
+    /// The whole block is mapped while it exists, if host visible, as memory can't be mapped
+    /// more than once at a time.
+    mapped: *mut c_void,
+    /// Free offsets by order, where order 0 is `MIN_ALLOCATION_SIZE`.
+    free: Vec<BTreeSet<vk::DeviceSize>>,
+    allocations: usize,
+    allocated_bytes: vk::DeviceSize,
+}
+
+// The mapping is only used to hand out pointers to allocations.
+unsafe impl Send for Block {}
+
+fn order_size(order: u32) -> vk::DeviceSize {
+    MIN_ALLOCATION_SIZE << order
+}
+
+impl Block {
+    fn create(
+        context: &Context,
+        type_index: MemoryTypeIndex,
+        size: vk::DeviceSize,
+    ) -> VkResult<Self> {
+        let memory = unsafe {
+            vk::DeviceMemory::create(
+                context,
+                &vk::MemoryAllocateInfo::builder()
+                    .allocation_size(size)
+                    .memory_type_index(type_index.0)
+                    .build(),
+            )?
+        };
+        let mapped = match map_whole(context, memory, type_index) {
+            Ok(mapped) => mapped,
+            Err(err) => {
+                unsafe { memory.destroy(context) };
+                return Err(err);
+            }
+        };
+        Ok(Self::with_memory(memory, mapped, size))
+    }
+
+    /// A block with all of `memory` free.
+    fn with_memory(memory: vk::DeviceMemory, mapped: *mut c_void, size: vk::DeviceSize) -> Self {
+        let orders = (size / MIN_ALLOCATION_SIZE).trailing_zeros() as usize + 1;
+        let mut free = vec![BTreeSet::new(); orders];
+        free[orders - 1].insert(0);
+        Self {
+            memory,
+            mapped,
+            free,
+            allocations: 0,
+            allocated_bytes: 0,
+        }
+    }
+
+    unsafe fn destroy(self, device: &ash::Device) {
+        if !self.mapped.is_null() {
+            device.unmap_memory(self.memory);
+        }
+        device.free_memory(self.memory, ALLOC);
+    }
+
+    fn allocate(&mut self, order: u32) -> Option<vk::DeviceSize> {
+        let available = (order as usize..self.free.len()).find(|&o| !self.free[o].is_empty())?;
+        let offset = self.free[available].pop_first().unwrap();
+        // Split, keeping the first half each time.
+        for split in (order as usize..available).rev() {
+            self.free[split].insert(offset + order_size(split as u32));
+        }
+        self.allocations += 1;
+        self.allocated_bytes += order_size(order);
+        Some(offset)
+    }
+
+    fn free(&mut self, mut offset: vk::DeviceSize, mut order: u32) {
+        self.allocations -= 1;
+        self.allocated_bytes -= order_size(order);
+        // Merge with the buddy while it's free.
+        while (order as usize) < self.free.len() - 1 {
+            let buddy = offset ^ order_size(order);
+            if !self.free[order as usize].remove(&buddy) {
+                break;
+            }
+            offset = offset.min(buddy);
+            order += 1;
+        }
+        self.free[order as usize].insert(offset);
+    }
+}
+
+impl Pool {
+    fn new(block_size: vk::DeviceSize) -> Self {
+        Self {
+            block_size,
+            blocks: BTreeMap::new(),
+            next_block: 0,
+        }
+    }
+
+    /// Returns the block, offset and order of a new allocation of `size`, a power of two,
+    /// calling `create_block` if none of the blocks have room.
+    fn allocate(
+        &mut self,
+        size: vk::DeviceSize,
+        create_block: impl FnOnce() -> VkResult<Block>,
+    ) -> VkResult<(u64, vk::DeviceSize, u32)> {
+        let order = (size / MIN_ALLOCATION_SIZE).trailing_zeros();
+        for (&id, block) in &mut self.blocks {
+            if let Some(offset) = block.allocate(order) {
+                return Ok((id, offset, order));
+            }
+        }
+        let mut block = create_block()?;
+        let offset = block.allocate(order).unwrap();
+        let id = self.next_block;
+        self.next_block += 1;
+        self.blocks.insert(id, block);
+        Ok((id, offset, order))
+    }
+
+    /// Returns the block if it's left empty and should be destroyed.
+    #[must_use]
+    fn free(&mut self, id: u64, offset: vk::DeviceSize, order: u32) -> Option<Block> {
+        let block = self
+            .blocks
+            .get_mut(&id)
+            .expect("allocation from unknown block");
+        block.free(offset, order);
+        // Keep the last block around to avoid reallocating it for every short-lived allocation.
+        if block.allocations == 0 && self.blocks.len() > 1 {
+            self.blocks.remove(&id)
+        } else {
+            None
+        }
+    }
+}
+
+/// Memory use of a memory type.
+#[derive(Default, Debug, Clone)]
+pub struct MemoryStatistics {
+    pub blocks: usize,
+    pub block_bytes: vk::DeviceSize,
+    /// Sub-allocations from the blocks, and their size rounded up to a power of two.
+    pub allocations: usize,
+    pub allocated_bytes: vk::DeviceSize,
+    pub dedicated_allocations: usize,
+    pub dedicated_bytes: vk::DeviceSize,
+    /// Count and requested bytes of all allocations, by purpose.
+    pub purposes: BTreeMap<MemoryPurpose, (usize, vk::DeviceSize)>,
+}
+
+impl MemoryStatistics {
+    /// Device memory allocations, which are limited to `maxMemoryAllocationCount`.
+    pub fn device_allocations(&self) -> usize {
+        self.blocks + self.dedicated_allocations
+    }
+
+    pub fn device_bytes(&self) -> vk::DeviceSize {
+        self.block_bytes + self.dedicated_bytes
+    }
+}
+
+impl fmt::Display for MemoryStatistics {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        const MIB: f64 = (1 << 20) as f64;
+        write!(
+            f,
+            "{} allocations in {} blocks ({:.1}/{:.1} MiB used), {} dedicated ({:.1} MiB)",
+            self.allocations,
+            self.blocks,
+            self.allocated_bytes as f64 / MIB,
+            self.block_bytes as f64 / MIB,
+            self.dedicated_allocations,
+            self.dedicated_bytes as f64 / MIB,
+        )
+    }
+}
+
+/// Returns the memory use of each memory type with any allocations.
+pub fn memory_statistics(context: &Context) -> BTreeMap<MemoryTypeIndex, MemoryStatistics> {
+    let allocator = context.allocator.lock().unwrap();
+    let mut result = BTreeMap::<MemoryTypeIndex, MemoryStatistics>::new();
+    for (&(type_index, _), pool) in &allocator.pools {
+        let stats = result.entry(type_index).or_default();
+        for block in pool.blocks.values() {
+            stats.blocks += 1;
+            stats.block_bytes += pool.block_size;
+            stats.allocations += block.allocations;
+            stats.allocated_bytes += block.allocated_bytes;
+        }
+    }
+    for (&type_index, &(count, bytes)) in &allocator.dedicated {
+        if count > 0 {
+            let stats = result.entry(type_index).or_default();
+            stats.dedicated_allocations += count;
+            stats.dedicated_bytes += bytes;
+        }
+    }
+    for (&(type_index, purpose), &(count, bytes)) in &allocator.purposes {
+        if count > 0 {
+            result
+                .entry(type_index)
+                .or_default()
+                .purposes
+                .insert(purpose, (count, bytes));
+        }
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BLOCK_SIZE: vk::DeviceSize = 4096;
+
+    fn block() -> Block {
+        Block::with_memory(vk::DeviceMemory::null(), std::ptr::null_mut(), BLOCK_SIZE)
+    }
+
+    fn requirements(size: vk::DeviceSize, alignment: vk::DeviceSize) -> vk::MemoryRequirements {
+        vk::MemoryRequirements {
+            size,
+            alignment,
+            memory_type_bits: !0,
+        }
+    }
+
+    fn set(offsets: &[vk::DeviceSize]) -> BTreeSet<vk::DeviceSize> {
+        offsets.iter().copied().collect()
+    }
+
+    fn is_empty(block: &Block) -> bool {
+        let top = block.free.len() - 1;
+        block.allocations == 0
+            && block.allocated_bytes == 0
+            && block.free[top] == set(&[0])
+            && block.free[..top].iter().all(BTreeSet::is_empty)
+    }
+
+    #[test]
+    fn splits_and_merges_buddies() {
+        let mut block = block();
+        assert_eq!(block.allocate(0), Some(0));
+        // Splitting the block left a free buddy at each order below it.
+        assert_eq!(block.free[0], set(&[256]));
+        assert_eq!(block.free[1], set(&[512]));
+        assert_eq!(block.free[2], set(&[1024]));
+        assert_eq!(block.free[3], set(&[2048]));
+        assert!(block.free[4].is_empty());
+
+        assert_eq!(block.allocate(0), Some(256));
+        assert_eq!(block.allocate(1), Some(512));
+        assert_eq!(block.allocate(3), Some(2048));
+        assert_eq!(block.allocate(3), None);
+        assert_eq!((block.allocations, block.allocated_bytes), (4, 3072));
+
+        // Merging only happens once both buddies are free.
+        block.free(0, 0);
+        assert_eq!(block.free[0], set(&[0]));
+        block.free(256, 0);
+        assert!(block.free[0].is_empty());
+        assert_eq!(block.free[1], set(&[0]));
+        block.free(512, 1);
+        block.free(2048, 3);
+        assert!(is_empty(&block));
+    }
+
+    #[test]
+    fn offsets_are_aligned_to_their_size() {
+        let mut block = block();
+        for &order in &[0, 2, 0, 1, 0, 2, 1] {
+            let offset = block.allocate(order).unwrap();
+            assert_eq!(offset % order_size(order), 0, "order {}", order);
+        }
+    }
+
+    #[test]
+    fn rounds_sizes_up_to_alignment_and_powers_of_two() {
+        assert_eq!(
+            sub_allocation_size(&requirements(1, 1), BLOCK_SIZE),
+            Some(256)
+        );
+        assert_eq!(
+            sub_allocation_size(&requirements(300, 4), BLOCK_SIZE),
+            Some(512)
+        );
+        assert_eq!(
+            sub_allocation_size(&requirements(300, 1024), BLOCK_SIZE),
+            Some(1024)
+        );
+    }
+
+    #[test]
+    fn uses_dedicated_memory_above_half_a_block() {
+        let half = BLOCK_SIZE / 2;
+        assert_eq!(
+            sub_allocation_size(&requirements(half, 1), BLOCK_SIZE),
+            Some(half)
+        );
+        assert_eq!(
+            sub_allocation_size(&requirements(half + 1, 1), BLOCK_SIZE),
+            None
+        );
+        assert_eq!(
+            sub_allocation_size(&requirements(256, BLOCK_SIZE), BLOCK_SIZE),
+            None
+        );
+    }
+
+    #[test]
+    fn linear_and_optimal_resources_never_share_a_block() {
+        let mut allocator = Allocator::new();
+        let type_index = MemoryTypeIndex(0);
+        let linear = allocator.pool(type_index, ResourceKind::Linear, BLOCK_SIZE);
+        linear.allocate(256, || Ok(block())).unwrap();
+        assert_eq!(linear.blocks.len(), 1);
+
+        // The linear block has room, but optimal images need a block of their own.
+        let mut created = false;
+        let optimal = allocator.pool(type_index, ResourceKind::Optimal, BLOCK_SIZE);
+        optimal
+            .allocate(256, || {
+                created = true;
+                Ok(block())
+            })
+            .unwrap();
+        assert!(created);
+        assert_eq!(allocator.pools.len(), 2);
+    }
+
+    #[test]
+    fn frees_empty_blocks_except_the_last() {
+        let mut pool = Pool::new(BLOCK_SIZE);
+        let (first, offset, order) = pool.allocate(BLOCK_SIZE, || Ok(block())).unwrap();
+        let (second, ..) = pool.allocate(256, || Ok(block())).unwrap();
+        assert_ne!(first, second);
+
+        let freed = pool
+            .free(first, offset, order)
+            .expect("empty block wasn't freed");
+        assert!(is_empty(&freed));
+        assert_eq!(pool.blocks.len(), 1);
+
+        // The last block is kept for reuse once it's empty.
+        assert!(pool.free(second, 0, 0).is_none());
+        assert!(is_empty(&pool.blocks[&second]));
+    }
+}
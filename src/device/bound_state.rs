@@ -0,0 +1,265 @@
+use std::fmt;
+use std::ops::AddAssign;
+
+use super::*;
+
+/// The state bound in a render pass, used to skip commands that would bind it again.
+#[derive(Default)]
+pub(super) struct BoundState {
+    pipeline: Option<vk::Pipeline>,
+    /// The layout and descriptor set bound at each set index.
+    descriptor_sets: Vec<Option<(vk::PipelineLayout, vk::DescriptorSet)>>,
+    /// The last constants pushed at each offset and stage.
+    push_constants: Vec<PushConstants>,
+    vertex_buffers: Vec<Option<vk::Buffer>>,
+    index_buffer: Option<(vk::Buffer, vk::IndexType)>,
+    pub stats: RecorderStats,
+}
+
+struct PushConstants {
+    layout: vk::PipelineLayout,
+    stage_flags: vk::ShaderStageFlags,
+    offset: u32,
+    data: Vec<u8>,
+}
+
+impl BoundState {
+    /// Returns whether the pipeline needs binding, recording it as bound.
+    pub fn bind_pipeline(&mut self, pipeline: vk::Pipeline) -> bool {
+        let changed = self.pipeline.replace(pipeline) != Some(pipeline);
+        self.stats.pipelines.count(changed)
+    }
+
+    /// Returns whether the descriptor set needs binding, recording it as bound.
+    pub fn bind_descriptor_set(
+        &mut self,
+        pipeline_layout: vk::PipelineLayout,
+        set_index: u32,
+        descriptor_set: vk::DescriptorSet,
+    ) -> bool {
+        let index = set_index as usize;
+        let bound = Some((pipeline_layout, descriptor_set));
+        let changed = self.descriptor_sets.get(index) != Some(&bound);
+        if changed {
+            // Binding with a different layout may disturb the sets after it.
+            for later in self.descriptor_sets.iter_mut().skip(index + 1) {
+                if matches!(later, Some((layout, _)) if *layout != pipeline_layout) {
+                    *later = None;
+                }
+            }
+            set(&mut self.descriptor_sets, index, bound);
+        }
+        self.stats.descriptor_sets.count(changed)
+    }
+
+    /// Returns whether the constants need pushing, recording them as pushed.
+    pub fn push(
+        &mut self,
+        layout: vk::PipelineLayout,
+        stage_flags: vk::ShaderStageFlags,
+        offset: u32,
+        data: &[u8],
+    ) -> bool {
+        let pushed = self
+            .push_constants
+            .iter_mut()
+            .find(|pushed| pushed.stage_flags == stage_flags && pushed.offset == offset);
+        let changed = match pushed {
+            Some(pushed) if pushed.layout == layout && pushed.data == data => false,
+            Some(pushed) => {
+                pushed.layout = layout;
+                pushed.data.clear();
+                pushed.data.extend_from_slice(data);
+                true
+            }
+            None => {
+                self.push_constants.push(PushConstants {
+                    layout,
+                    stage_flags,
+                    offset,
+                    data: data.to_vec(),
+                });
+                true
+            }
+        };
+        self.stats.push_constants.count(changed)
+    }
+
+    /// Returns whether the vertex buffer needs binding, recording it as bound.
+    pub fn bind_vertex_buffer(&mut self, binding: u32, buffer: vk::Buffer) -> bool {
+        let index = binding as usize;
+        let changed = self.vertex_buffers.get(index) != Some(&Some(buffer));
+        if changed {
+            set(&mut self.vertex_buffers, index, Some(buffer));
+        }
+        self.stats.vertex_buffers.count(changed)
+    }
+
+    /// Returns whether the index buffer needs binding, recording it as bound.
+    pub fn bind_index_buffer(&mut self, buffer: vk::Buffer, index_type: vk::IndexType) -> bool {
+        let bound = (buffer, index_type);
+        let changed = self.index_buffer.replace(bound) != Some(bound);
+        self.stats.index_buffers.count(changed)
+    }
+}
+
+/// Sets `items[index]`, growing `items` if needed.
+fn set<T: Clone>(items: &mut Vec<Option<T>>, index: usize, item: Option<T>) {
+    if items.len() <= index {
+        items.resize(index + 1, None);
+    }
+    items[index] = item;
+}
+
+/// The number of commands recorded and skipped as redundant.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct CommandCount {
+    pub issued: u32,
+    pub skipped: u32,
+}
+
+impl CommandCount {
+    fn count(&mut self, issued: bool) -> bool {
+        if issued {
+            self.issued += 1;
+        } else {
+            self.skipped += 1;
+        }
+        issued
+    }
+}
+
+impl AddAssign for CommandCount {
+    fn add_assign(&mut self, other: Self) {
+        self.issued += other.issued;
+        self.skipped += other.skipped;
+    }
+}
+
+/// The state commands recorded in a render pass, for profiling.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct RecorderStats {
+    pub pipelines: CommandCount,
+    pub descriptor_sets: CommandCount,
+    pub push_constants: CommandCount,
+    pub vertex_buffers: CommandCount,
+    pub index_buffers: CommandCount,
+    pub draws: u32,
+}
+
+impl AddAssign for RecorderStats {
+    fn add_assign(&mut self, other: Self) {
+        self.pipelines += other.pipelines;
+        self.descriptor_sets += other.descriptor_sets;
+        self.push_constants += other.push_constants;
+        self.vertex_buffers += other.vertex_buffers;
+        self.index_buffers += other.index_buffers;
+        self.draws += other.draws;
+    }
+}
+
+impl fmt::Display for RecorderStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{:<16}{:>10}{:>10}", "command", "issued", "skipped")?;
+        for (name, count) in &[
+            ("pipeline", self.pipelines),
+            ("descriptor set", self.descriptor_sets),
+            ("push constants", self.push_constants),
+            ("vertex buffer", self.vertex_buffers),
+            ("index buffer", self.index_buffers),
+        ] {
+            writeln!(f, "{:<16}{:>10}{:>10}", name, count.issued, count.skipped)?;
+        }
+        writeln!(f, "{:<16}{:>10}", "draw", self.draws)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use vk::Handle;
+
+    #[test]
+    fn descriptor_sets_survive_pipelines_with_the_same_layout() {
+        let layout = vk::PipelineLayout::from_raw(1);
+        let (view, material) = (
+            vk::DescriptorSet::from_raw(1),
+            vk::DescriptorSet::from_raw(2),
+        );
+        let mut bound = BoundState::default();
+        assert!(bound.bind_pipeline(vk::Pipeline::from_raw(1)));
+        assert!(bound.bind_descriptor_set(layout, 0, view));
+        assert!(bound.bind_descriptor_set(layout, 1, material));
+
+        assert!(bound.bind_pipeline(vk::Pipeline::from_raw(2)));
+        assert!(!bound.bind_descriptor_set(layout, 0, view));
+        assert!(!bound.bind_descriptor_set(layout, 1, material));
+    }
+
+    #[test]
+    fn pipelines_with_another_layout_rebind_incompatible_sets() {
+        let (a, b) = (
+            vk::PipelineLayout::from_raw(1),
+            vk::PipelineLayout::from_raw(2),
+        );
+        let (view, material) = (
+            vk::DescriptorSet::from_raw(1),
+            vk::DescriptorSet::from_raw(2),
+        );
+        let mut bound = BoundState::default();
+        bound.bind_pipeline(vk::Pipeline::from_raw(1));
+        bound.bind_descriptor_set(a, 0, view);
+        bound.bind_descriptor_set(a, 1, material);
+
+        // The same sets are bound again with the new layout, which disturbs the later set.
+        assert!(bound.bind_pipeline(vk::Pipeline::from_raw(2)));
+        assert!(bound.bind_descriptor_set(b, 0, view));
+        assert!(bound.bind_pipeline(vk::Pipeline::from_raw(1)));
+        assert!(bound.bind_descriptor_set(a, 0, view));
+        assert!(bound.bind_descriptor_set(a, 1, material));
+    }
+
+    #[test]
+    fn rebinding_an_earlier_set_keeps_later_sets_of_the_same_layout() {
+        let layout = vk::PipelineLayout::from_raw(1);
+        let mut bound = BoundState::default();
+        bound.bind_descriptor_set(layout, 0, vk::DescriptorSet::from_raw(1));
+        bound.bind_descriptor_set(layout, 1, vk::DescriptorSet::from_raw(2));
+
+        assert!(bound.bind_descriptor_set(layout, 0, vk::DescriptorSet::from_raw(3)));
+        assert!(!bound.bind_descriptor_set(layout, 1, vk::DescriptorSet::from_raw(2)));
+    }
+
+    #[test]
+    fn counts_issued_and_skipped_commands() {
+        let layout = vk::PipelineLayout::from_raw(1);
+        let stages = vk::ShaderStageFlags::VERTEX;
+        let (buffer, other) = (vk::Buffer::from_raw(1), vk::Buffer::from_raw(2));
+        let mut bound = BoundState::default();
+        for pipeline in [1, 1, 2, 2, 1] {
+            bound.bind_pipeline(vk::Pipeline::from_raw(pipeline));
+        }
+        for set in [1, 1, 2] {
+            bound.bind_descriptor_set(layout, 0, vk::DescriptorSet::from_raw(set));
+        }
+        for data in [[1, 2], [1, 2], [3, 4]] {
+            bound.push(layout, stages, 0, &data);
+        }
+        // Each offset has constants of its own.
+        bound.push(layout, stages, 16, &[1, 2]);
+        bound.bind_vertex_buffer(0, buffer);
+        bound.bind_vertex_buffer(1, buffer);
+        bound.bind_vertex_buffer(0, buffer);
+        bound.bind_index_buffer(other, vk::IndexType::UINT32);
+        bound.bind_index_buffer(other, vk::IndexType::UINT16);
+        bound.bind_index_buffer(other, vk::IndexType::UINT16);
+
+        let counts = |count: CommandCount| (count.issued, count.skipped);
+        let stats = bound.stats;
+        assert_eq!(counts(stats.pipelines), (3, 2));
+        assert_eq!(counts(stats.descriptor_sets), (2, 1));
+        assert_eq!(counts(stats.push_constants), (3, 1));
+        assert_eq!(counts(stats.vertex_buffers), (2, 1));
+        assert_eq!(counts(stats.index_buffers), (2, 1));
+    }
+}
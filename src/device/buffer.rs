@@ -2,13 +2,13 @@ use super::*;
 
 impl RawHandle for vk::Buffer {}
 impl Create<&vk::BufferCreateInfo> for vk::Buffer {
-    unsafe fn create(info: &vk::BufferCreateInfo) -> VkResult<Self> {
-        DEVICE.create_buffer(info, ALLOC)
+    unsafe fn create(context: &Context, info: &vk::BufferCreateInfo) -> VkResult<Self> {
+        context.device.create_buffer(info, ALLOC)
     }
 }
 impl Destroy for vk::Buffer {
-    unsafe fn destroy(self) {
-        DEVICE.destroy_buffer(self, ALLOC)
+    unsafe fn destroy(self, context: &Context) {
+        context.device.destroy_buffer(self, ALLOC)
     }
 }
 
@@ -21,20 +21,29 @@ impl AsRef<vk::Buffer> for BufferObject {
 }
 
 impl BufferObject {
-    pub fn create(size: vk::DeviceSize, usage: vk::BufferUsageFlags) -> VkResult<Self> {
+    pub fn create(
+        context: &Arc<Context>,
+        size: vk::DeviceSize,
+        usage: vk::BufferUsageFlags,
+    ) -> VkResult<Self> {
         Ok(Self(unsafe {
             Owned::create(
+                context,
                 &vk::BufferCreateInfo::builder()
                     .size(size)
                     .usage(usage)
-                    .queue_family_indices(&[GRAPHICS_QUEUE_FAMILY_INDEX])
+                    .queue_family_indices(&[context.graphics_queue.family_index])
                     .build(),
             )?
         }))
     }
 
     pub fn memory_requirements(&self) -> vk::MemoryRequirements {
-        unsafe { DEVICE.get_buffer_memory_requirements(self.as_raw()) }
+        unsafe {
+            self.context()
+                .device
+                .get_buffer_memory_requirements(self.as_raw())
+        }
     }
 
     pub fn bind_memory(
@@ -42,13 +51,21 @@ impl BufferObject {
         memory: impl AsRawHandle<vk::DeviceMemory>,
         offset: vk::DeviceSize,
     ) -> VkResult<()> {
-        unsafe { DEVICE.bind_buffer_memory(self.as_raw(), memory.as_raw(), offset) }
+        unsafe {
+            self.context()
+                .device
+                .bind_buffer_memory(self.as_raw(), memory.as_raw(), offset)
+        }
+    }
+
+    pub fn context(&self) -> &Arc<Context> {
+        self.0.context()
     }
 }
 
 pub struct Buffer {
     pub object: BufferObject,
-    pub memory: Memory,
+    pub memory: Allocation,
 }
 
 impl AsRef<vk::DeviceMemory> for Buffer {
@@ -64,25 +81,47 @@ impl AsRef<vk::Buffer> for Buffer {
 }
 
 impl Buffer {
-    pub fn create_with<T: Copy + ?Sized>(
+    pub fn create_with<T: Copy>(
+        context: &Arc<Context>,
         usage: vk::BufferUsageFlags,
         source: &T,
     ) -> VkResult<Self> {
-        let buffer = Self::create(std::mem::size_of_val(source) as vk::DeviceSize, usage)?;
+        let mut buffer = Self::create(
+            context,
+            std::mem::size_of_val(source) as vk::DeviceSize,
+            usage,
+        )?;
         buffer.write(0, source)?;
         Ok(buffer)
     }
 
-    pub fn create(size: vk::DeviceSize, usage: vk::BufferUsageFlags) -> VkResult<Self> {
-        let buffer = BufferObject::create(size, usage)?;
+    /// Creates a buffer in host visible memory.
+    pub fn create(
+        context: &Arc<Context>,
+        size: vk::DeviceSize,
+        usage: vk::BufferUsageFlags,
+    ) -> VkResult<Self> {
+        Self::create_in(context, size, usage, MemoryTypeMask::mappable(context))
+    }
+
+    pub fn create_in(
+        context: &Arc<Context>,
+        size: vk::DeviceSize,
+        usage: vk::BufferUsageFlags,
+        memory_type_mask: MemoryTypeMask,
+    ) -> VkResult<Self> {
+        let buffer = BufferObject::create(context, size, usage)?;
         let memory_requirements = buffer.memory_requirements();
 
-        let memory = Memory::allocate_mappable(
-            memory_requirements.size,
-            MemoryTypeMask(memory_requirements.memory_type_bits),
+        let memory = Allocation::allocate(
+            context,
+            &memory_requirements,
+            memory_type_mask,
+            ResourceKind::Linear,
+            MemoryPurpose::of_buffer(usage),
         )?;
 
-        buffer.bind_memory(&memory, 0)?;
+        buffer.bind_memory(&memory, memory.offset())?;
 
         Ok(Self {
             object: buffer,
@@ -90,7 +129,11 @@ impl Buffer {
         })
     }
 
-    pub fn write<T: Copy + ?Sized>(&self, offset: vk::DeviceSize, source: &T) -> VkResult<()> {
+    pub fn write<T: Copy>(&mut self, offset: vk::DeviceSize, source: &T) -> VkResult<()> {
         self.memory.write(offset, source)
     }
+
+    pub fn write_slice<T: Copy>(&mut self, offset: vk::DeviceSize, source: &[T]) -> VkResult<()> {
+        self.memory.write_slice(offset, source)
+    }
 }
@@ -1,76 +1,119 @@
+use std::cell::RefCell;
+
+use super::bound_state::BoundState;
 use super::*;
 
 impl RawHandle for vk::CommandBuffer {}
 
-pub struct CommandBuffer(vk::CommandBuffer);
+pub struct CommandBuffer {
+    context: Arc<Context>,
+    /// The queue it's submitted to.
+    queue: QueueKind,
+    pool: vk::CommandPool,
+    buffer: vk::CommandBuffer,
+    /// The pool of a one-off buffer, destroyed after it.
+    own_pool: Option<CommandPool>,
+}
 
 impl Drop for CommandBuffer {
     fn drop(&mut self) {
-        unsafe { DEVICE.free_command_buffers(GRAPHICS_COMMAND_POOL, &[self.0]) };
+        unsafe {
+            self.context
+                .device
+                .free_command_buffers(self.pool, &[self.buffer])
+        };
     }
 }
 
 impl AsRef<vk::CommandBuffer> for CommandBuffer {
     fn as_ref(&self) -> &vk::CommandBuffer {
-        &self.0
+        &self.buffer
     }
 }
 
 impl CommandBuffer {
-    pub fn create() -> VkResult<CommandBufferRecorder> {
+    /// Allocates a one-off command buffer for `queue` and begins recording.
+    /// It has a pool of its own, so one-off buffers can be recorded on any thread.
+    pub fn create(context: &Arc<Context>, queue: QueueKind) -> VkResult<CommandBufferRecorder> {
+        let pool = CommandPool::create(context, queue)?;
+        unsafe { Self::allocate(context, queue, pool.as_raw(), Some(pool))?.begin() }
+    }
+
+    pub(super) unsafe fn allocate(
+        context: &Arc<Context>,
+        queue: QueueKind,
+        pool: vk::CommandPool,
+        own_pool: Option<CommandPool>,
+    ) -> VkResult<Self> {
+        let command_buffers = context.device.allocate_command_buffers(
+            &vk::CommandBufferAllocateInfo::builder()
+                .command_pool(pool)
+                .command_buffer_count(1)
+                .level(vk::CommandBufferLevel::PRIMARY)
+                .build(),
+        )?;
+        Ok(Self {
+            context: context.clone(),
+            queue,
+            pool,
+            buffer: command_buffers[0],
+            own_pool,
+        })
+    }
+
+    /// Begins recording, implicitly resetting the buffer if its pool hasn't been reset.
+    pub fn begin(self) -> VkResult<CommandBufferRecorder> {
         unsafe {
-            let command_buffers = DEVICE.allocate_command_buffers(
-                &vk::CommandBufferAllocateInfo::builder()
-                    .command_pool(GRAPHICS_COMMAND_POOL)
-                    .command_buffer_count(1)
-                    .level(vk::CommandBufferLevel::PRIMARY)
+            self.context.device.begin_command_buffer(
+                self.as_raw(),
+                &vk::CommandBufferBeginInfo::builder()
+                    .flags(vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT)
                     .build(),
             )?;
-            let result = Self(command_buffers[0]);
-            DEVICE.begin_command_buffer(result.as_raw(), &vk::CommandBufferBeginInfo::default())?;
-            Ok(CommandBufferRecorder(result))
         }
+        Ok(CommandBufferRecorder(self))
     }
 
     pub fn submit(self) -> VkResult<()> {
-        unsafe {
-            let submit_fence = Fence::create()?;
-
-            DEVICE.queue_submit(
-                GRAPHICS_QUEUE,
-                &[vk::SubmitInfo::builder()
-                    .command_buffers(&[self.as_raw()])
-                    .build()],
-                submit_fence.as_raw(),
-            )?;
-
-            submit_fence.wait()?;
-
-            Ok(())
-        }
+        self.submit_after(&[], &[])
     }
 
+    /// Submits once `wait_semaphores` are signalled, and waits for completion.
     pub fn submit_after(
         self,
-        wait_semaphore: vk::Semaphore,
-        wait_dst_stage_mask: vk::PipelineStageFlags,
+        wait_semaphores: &[vk::Semaphore],
+        wait_dst_stage_masks: &[vk::PipelineStageFlags],
     ) -> VkResult<()> {
-        unsafe {
-            let submit_fence = Fence::create()?;
+        let submit_fence = Fence::create(&self.context)?;
+        self.submit_signalling(
+            wait_semaphores,
+            wait_dst_stage_masks,
+            &[],
+            submit_fence.as_raw(),
+        )?;
+        submit_fence.wait()
+    }
 
-            DEVICE.queue_submit(
-                GRAPHICS_QUEUE,
+    /// Submits without waiting, signalling `signal_semaphores` and then `fence` on completion.
+    /// The buffer must be kept alive until the fence is signalled.
+    pub fn submit_signalling(
+        &self,
+        wait_semaphores: &[vk::Semaphore],
+        wait_dst_stage_masks: &[vk::PipelineStageFlags],
+        signal_semaphores: &[vk::Semaphore],
+        fence: vk::Fence,
+    ) -> VkResult<()> {
+        unsafe {
+            self.context.device.queue_submit(
+                *self.context.queue(self.queue).lock(),
                 &[vk::SubmitInfo::builder()
                     .command_buffers(&[self.as_raw()])
-                    .wait_semaphores(&[wait_semaphore])
-                    .wait_dst_stage_mask(&[wait_dst_stage_mask])
+                    .wait_semaphores(wait_semaphores)
+                    .wait_dst_stage_mask(wait_dst_stage_masks)
+                    .signal_semaphores(signal_semaphores)
                     .build()],
-                submit_fence.as_raw(),
-            )?;
-
-            submit_fence.wait()?;
-
-            Ok(())
+                fence,
+            )
         }
     }
 }
@@ -85,18 +128,30 @@ impl AsRef<vk::CommandBuffer> for CommandBufferRecorder {
 
 impl CommandBufferRecorder {
     pub fn end(self) -> VkResult<CommandBuffer> {
-        unsafe { DEVICE.end_command_buffer(self.as_raw()) }?;
+        unsafe { self.device().end_command_buffer(self.as_raw()) }?;
         Ok(self.0)
     }
 
+    pub fn context(&self) -> &Arc<Context> {
+        &self.0.context
+    }
+
+    fn device(&self) -> &ash::Device {
+        &self.0.context.device
+    }
+
     pub fn begin_render_pass(
         self,
         info: &vk::RenderPassBeginInfo,
     ) -> CommandBufferRenderPassRecorder {
         unsafe {
-            DEVICE.cmd_begin_render_pass(self.as_raw(), info, vk::SubpassContents::INLINE);
+            self.device()
+                .cmd_begin_render_pass(self.as_raw(), info, vk::SubpassContents::INLINE);
+        }
+        CommandBufferRenderPassRecorder {
+            recorder: self,
+            bound: Default::default(),
         }
-        CommandBufferRenderPassRecorder(self)
     }
 
     pub fn copy_buffer_to_image(
@@ -107,7 +162,7 @@ impl CommandBufferRecorder {
         regions: &[vk::BufferImageCopy],
     ) {
         unsafe {
-            DEVICE.cmd_copy_buffer_to_image(
+            self.device().cmd_copy_buffer_to_image(
                 self.as_raw(),
                 src_buffer,
                 dst_image,
@@ -117,6 +172,36 @@ impl CommandBufferRecorder {
         }
     }
 
+    pub fn copy_buffer(
+        &self,
+        src_buffer: vk::Buffer,
+        dst_buffer: vk::Buffer,
+        regions: &[vk::BufferCopy],
+    ) {
+        unsafe {
+            self.device()
+                .cmd_copy_buffer(self.as_raw(), src_buffer, dst_buffer, regions)
+        }
+    }
+
+    pub fn copy_image_to_buffer(
+        &self,
+        src_image: vk::Image,
+        src_image_layout: vk::ImageLayout,
+        dst_buffer: vk::Buffer,
+        regions: &[vk::BufferImageCopy],
+    ) {
+        unsafe {
+            self.device().cmd_copy_image_to_buffer(
+                self.as_raw(),
+                src_image,
+                src_image_layout,
+                dst_buffer,
+                regions,
+            );
+        }
+    }
+
     pub fn copy_image_to_image(
         &self,
         src_image: vk::Image,
@@ -126,7 +211,7 @@ impl CommandBufferRecorder {
         regions: &[vk::ImageCopy],
     ) {
         unsafe {
-            DEVICE.cmd_copy_image(
+            self.device().cmd_copy_image(
                 self.as_raw(),
                 src_image,
                 src_image_layout,
@@ -147,7 +232,7 @@ impl CommandBufferRecorder {
         filter: vk::Filter,
     ) {
         unsafe {
-            DEVICE.cmd_blit_image(
+            self.device().cmd_blit_image(
                 self.as_raw(),
                 src_image,
                 src_image_layout,
@@ -166,7 +251,7 @@ impl CommandBufferRecorder {
         barriers: &[vk::ImageMemoryBarrier],
     ) {
         unsafe {
-            DEVICE.cmd_pipeline_barrier(
+            self.device().cmd_pipeline_barrier(
                 self.as_raw(),
                 src_stage_mask,
                 dst_stage_mask,
@@ -178,9 +263,51 @@ impl CommandBufferRecorder {
         }
     }
 
+    pub fn memory_barrier(
+        &self,
+        src_stage_mask: vk::PipelineStageFlags,
+        dst_stage_mask: vk::PipelineStageFlags,
+        src_access_mask: vk::AccessFlags,
+        dst_access_mask: vk::AccessFlags,
+    ) {
+        unsafe {
+            self.device().cmd_pipeline_barrier(
+                self.as_raw(),
+                src_stage_mask,
+                dst_stage_mask,
+                vk::DependencyFlags::empty(),
+                &[vk::MemoryBarrier::builder()
+                    .src_access_mask(src_access_mask)
+                    .dst_access_mask(dst_access_mask)
+                    .build()],
+                &[],
+                &[],
+            )
+        }
+    }
+
+    pub fn buffer_barrier(
+        &self,
+        src_stage_mask: vk::PipelineStageFlags,
+        dst_stage_mask: vk::PipelineStageFlags,
+        barriers: &[vk::BufferMemoryBarrier],
+    ) {
+        unsafe {
+            self.device().cmd_pipeline_barrier(
+                self.as_raw(),
+                src_stage_mask,
+                dst_stage_mask,
+                vk::DependencyFlags::empty(),
+                &[],
+                barriers,
+                &[],
+            )
+        }
+    }
+
     pub fn set_viewport_scissor(&self, (width, height): (u32, u32)) {
         unsafe {
-            DEVICE.cmd_set_viewport(
+            self.device().cmd_set_viewport(
                 self.as_raw(),
                 0,
                 &[vk::Viewport {
@@ -192,7 +319,7 @@ impl CommandBufferRecorder {
                     max_depth: 1.0,
                 }],
             );
-            DEVICE.cmd_set_scissor(
+            self.device().cmd_set_scissor(
                 self.as_raw(),
                 0,
                 &[vk::Rect2D {
@@ -204,23 +331,43 @@ impl CommandBufferRecorder {
     }
 }
 
-pub struct CommandBufferRenderPassRecorder(CommandBufferRecorder);
+/// Records commands in a render pass, skipping those that bind state that's already bound.
+pub struct CommandBufferRenderPassRecorder {
+    recorder: CommandBufferRecorder,
+    bound: RefCell<BoundState>,
+}
 
 impl AsRef<vk::CommandBuffer> for CommandBufferRenderPassRecorder {
     fn as_ref(&self) -> &vk::CommandBuffer {
-        self.0.as_ref()
+        self.recorder.as_ref()
     }
 }
 
 impl CommandBufferRenderPassRecorder {
+    fn device(&self) -> &ash::Device {
+        self.recorder.device()
+    }
+
     pub fn end_render_pass(self) -> CommandBufferRecorder {
-        unsafe { DEVICE.cmd_end_render_pass(self.as_raw()) };
-        self.0
+        unsafe { self.device().cmd_end_render_pass(self.as_raw()) };
+        self.recorder
+    }
+
+    /// The commands recorded and skipped so far in this render pass.
+    pub fn stats(&self) -> RecorderStats {
+        self.bound.borrow().stats
     }
 
     pub fn bind_pipeline(&self, pipeline: vk::Pipeline) {
+        if !self.bound.borrow_mut().bind_pipeline(pipeline) {
+            return;
+        }
         unsafe {
-            DEVICE.cmd_bind_pipeline(self.as_raw(), vk::PipelineBindPoint::GRAPHICS, pipeline);
+            self.device().cmd_bind_pipeline(
+                self.as_raw(),
+                vk::PipelineBindPoint::GRAPHICS,
+                pipeline,
+            );
         }
     }
 
@@ -230,8 +377,12 @@ impl CommandBufferRenderPassRecorder {
         set_index: u32,
         descriptor_set: vk::DescriptorSet,
     ) {
+        let mut bound = self.bound.borrow_mut();
+        if !bound.bind_descriptor_set(pipeline_layout, set_index, descriptor_set) {
+            return;
+        }
         unsafe {
-            DEVICE.cmd_bind_descriptor_sets(
+            self.device().cmd_bind_descriptor_sets(
                 self.as_raw(),
                 vk::PipelineBindPoint::GRAPHICS,
                 pipeline_layout,
@@ -242,40 +393,63 @@ impl CommandBufferRenderPassRecorder {
         }
     }
 
-    pub fn push<T: ?Sized + Copy>(
+    pub fn push<T: Copy>(
         &self,
         layout: vk::PipelineLayout,
         stage_flags: vk::ShaderStageFlags,
         offset: u32,
         data: &T,
     ) {
+        let data = unsafe {
+            std::slice::from_raw_parts((data as *const T).cast::<u8>(), std::mem::size_of_val(data))
+        };
+        if !self
+            .bound
+            .borrow_mut()
+            .push(layout, stage_flags, offset, data)
+        {
+            return;
+        }
         unsafe {
-            DEVICE.cmd_push_constants(
-                self.as_raw(),
-                layout,
-                stage_flags,
-                offset,
-                std::slice::from_raw_parts(
-                    std::mem::transmute::<&T, *const u8>(data),
-                    std::mem::size_of_val(data),
-                ),
-            )
+            self.device()
+                .cmd_push_constants(self.as_raw(), layout, stage_flags, offset, data)
         }
     }
 
     pub fn bind_vertex_buffer(&self, binding: u32, buffer: vk::Buffer) {
-        unsafe { DEVICE.cmd_bind_vertex_buffers(self.as_raw(), binding, &[buffer], &[0]) };
+        if !self.bound.borrow_mut().bind_vertex_buffer(binding, buffer) {
+            return;
+        }
+        unsafe {
+            self.device()
+                .cmd_bind_vertex_buffers(self.as_raw(), binding, &[buffer], &[0])
+        };
     }
 
     pub fn bind_index_buffer(&self, buffer: vk::Buffer, index_type: vk::IndexType) {
-        unsafe { DEVICE.cmd_bind_index_buffer(self.as_raw(), buffer, 0, index_type) };
+        if !self
+            .bound
+            .borrow_mut()
+            .bind_index_buffer(buffer, index_type)
+        {
+            return;
+        }
+        unsafe {
+            self.device()
+                .cmd_bind_index_buffer(self.as_raw(), buffer, 0, index_type)
+        };
     }
 
     pub fn draw(&self, vertex_count: u32) {
-        unsafe { DEVICE.cmd_draw(self.as_raw(), vertex_count, 1, 0, 0) };
+        self.bound.borrow_mut().stats.draws += 1;
+        unsafe { self.device().cmd_draw(self.as_raw(), vertex_count, 1, 0, 0) };
     }
 
     pub fn draw_indexed(&self, index_count: u32) {
-        unsafe { DEVICE.cmd_draw_indexed(self.as_raw(), index_count, 1, 0, 0, 0) };
+        self.bound.borrow_mut().stats.draws += 1;
+        unsafe {
+            self.device()
+                .cmd_draw_indexed(self.as_raw(), index_count, 1, 0, 0, 0)
+        };
     }
 }
@@ -0,0 +1,55 @@
+use super::*;
+
+impl RawHandle for vk::CommandPool {}
+impl Create<&vk::CommandPoolCreateInfo> for vk::CommandPool {
+    unsafe fn create(context: &Context, info: &vk::CommandPoolCreateInfo) -> VkResult<Self> {
+        context.device.create_command_pool(info, ALLOC)
+    }
+}
+impl Destroy for vk::CommandPool {
+    unsafe fn destroy(self, context: &Context) {
+        context.device.destroy_command_pool(self, ALLOC)
+    }
+}
+
+/// A command pool for one kind of queue, whose buffers are all recycled together with
+/// [`CommandPool::reset`]. Like the buffers allocated from it, it must only be used by one
+/// thread at a time.
+pub struct CommandPool(Owned<vk::CommandPool>, QueueKind);
+
+impl AsRef<vk::CommandPool> for CommandPool {
+    fn as_ref(&self) -> &vk::CommandPool {
+        self.0.as_ref()
+    }
+}
+
+impl CommandPool {
+    pub fn create(context: &Arc<Context>, queue: QueueKind) -> VkResult<Self> {
+        let pool = unsafe {
+            Owned::create(
+                context,
+                &vk::CommandPoolCreateInfo::builder()
+                    .flags(vk::CommandPoolCreateFlags::TRANSIENT)
+                    .queue_family_index(context.queue(queue).family_index)
+                    .build(),
+            )?
+        };
+        Ok(Self(pool, queue))
+    }
+
+    /// Allocates a primary command buffer, which must be dropped before the pool.
+    pub fn allocate(&self) -> VkResult<CommandBuffer> {
+        unsafe { CommandBuffer::allocate(self.0.context(), self.1, self.as_raw(), None) }
+    }
+
+    /// Returns all buffers allocated from the pool to the initial state.
+    /// None of them may still be executing.
+    pub fn reset(&self) -> VkResult<()> {
+        unsafe {
+            self.0
+                .context()
+                .device
+                .reset_command_pool(self.as_raw(), vk::CommandPoolResetFlags::empty())
+        }
+    }
+}
@@ -2,13 +2,13 @@ use super::*;
 
 impl RawHandle for vk::DescriptorPool {}
 impl Create<&vk::DescriptorPoolCreateInfo> for vk::DescriptorPool {
-    unsafe fn create(info: &vk::DescriptorPoolCreateInfo) -> VkResult<Self> {
-        DEVICE.create_descriptor_pool(info, ALLOC)
+    unsafe fn create(context: &Context, info: &vk::DescriptorPoolCreateInfo) -> VkResult<Self> {
+        context.device.create_descriptor_pool(info, ALLOC)
     }
 }
 impl Destroy for vk::DescriptorPool {
-    unsafe fn destroy(self) {
-        DEVICE.destroy_descriptor_pool(self, ALLOC)
+    unsafe fn destroy(self, context: &Context) {
+        context.device.destroy_descriptor_pool(self, ALLOC)
     }
 }
 
@@ -22,11 +22,13 @@ impl AsRef<vk::DescriptorPool> for DescriptorPool {
 
 impl DescriptorPool {
     pub fn create(
+        context: &Arc<Context>,
         max_sets: u32,
         pool_sizes: &[vk::DescriptorPoolSize],
     ) -> VkResult<DescriptorPool> {
         unsafe {
             let owned = Owned::create(
+                context,
                 &vk::DescriptorPoolCreateInfo::builder()
                     .max_sets(max_sets)
                     .pool_sizes(pool_sizes)
@@ -38,24 +40,31 @@ impl DescriptorPool {
 
     pub fn allocate(&self, layout: vk::DescriptorSetLayout) -> VkResult<DescriptorSet> {
         unsafe {
-            let sets = DEVICE.allocate_descriptor_sets(
+            let sets = self.0.context().device.allocate_descriptor_sets(
                 &vk::DescriptorSetAllocateInfo::builder()
                     .descriptor_pool(self.as_raw())
                     .set_layouts(&[layout])
                     .build(),
             )?;
-            Ok(DescriptorSet(sets[0]))
+            Ok(DescriptorSet {
+                context: self.0.context().clone(),
+                set: sets[0],
+            })
         }
     }
 }
 
 impl RawHandle for vk::DescriptorSet {}
 
-pub struct DescriptorSet(vk::DescriptorSet);
+/// A set freed along with its pool.
+pub struct DescriptorSet {
+    context: Arc<Context>,
+    set: vk::DescriptorSet,
+}
 
 impl AsRef<vk::DescriptorSet> for DescriptorSet {
     fn as_ref(&self) -> &vk::DescriptorSet {
-        &self.0
+        &self.set
     }
 }
 
@@ -69,7 +78,7 @@ impl DescriptorSet {
         size: vk::DeviceSize,
     ) {
         unsafe {
-            DEVICE.update_descriptor_sets(
+            self.context.device.update_descriptor_sets(
                 &[vk::WriteDescriptorSet::builder()
                     .dst_set(self.as_raw())
                     .dst_binding(binding)
@@ -130,7 +139,7 @@ impl DescriptorSet {
         image_layout: vk::ImageLayout,
     ) {
         unsafe {
-            DEVICE.update_descriptor_sets(
+            self.context.device.update_descriptor_sets(
                 &[vk::WriteDescriptorSet::builder()
                     .dst_set(self.as_raw())
                     .dst_binding(binding)
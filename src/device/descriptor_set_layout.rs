@@ -3,31 +3,36 @@ use std::marker::PhantomData;
 
 impl RawHandle for vk::DescriptorSetLayout {}
 impl Create<&vk::DescriptorSetLayoutCreateInfo> for vk::DescriptorSetLayout {
-    unsafe fn create(info: &vk::DescriptorSetLayoutCreateInfo) -> VkResult<Self> {
-        DEVICE.create_descriptor_set_layout(info, ALLOC)
+    unsafe fn create(
+        context: &Context,
+        info: &vk::DescriptorSetLayoutCreateInfo,
+    ) -> VkResult<Self> {
+        context.device.create_descriptor_set_layout(info, ALLOC)
     }
 }
 impl Destroy for vk::DescriptorSetLayout {
-    unsafe fn destroy(self) {
-        DEVICE.destroy_descriptor_set_layout(self, ALLOC)
+    unsafe fn destroy(self, context: &Context) {
+        context.device.destroy_descriptor_set_layout(self, ALLOC)
     }
 }
 
 pub struct DescriptorSetLayoutBuilder<'a> {
+    context: &'a Arc<Context>,
     bindings: Vec<vk::DescriptorSetLayoutBinding>,
     lifetime: PhantomData<&'a ()>,
 }
 
 impl<'a> DescriptorSetLayoutBuilder<'a> {
-    pub fn new() -> Self {
+    pub fn new(context: &'a Arc<Context>) -> Self {
         Self {
+            context,
             bindings: vec![],
             lifetime: PhantomData,
         }
     }
 
     pub fn build(self) -> VkResult<DescriptorSetLayout> {
-        DescriptorSetLayout::create(&self.bindings)
+        DescriptorSetLayout::create(self.context, &self.bindings)
     }
 
     pub fn add_uniform_buffer(self, binding: u32, stages: vk::ShaderStageFlags) -> Self {
@@ -69,13 +74,17 @@ impl AsRef<vk::DescriptorSetLayout> for DescriptorSetLayout {
 }
 
 impl DescriptorSetLayout {
-    pub fn builder<'a>() -> DescriptorSetLayoutBuilder<'a> {
-        DescriptorSetLayoutBuilder::new()
+    pub fn builder(context: &Arc<Context>) -> DescriptorSetLayoutBuilder<'_> {
+        DescriptorSetLayoutBuilder::new(context)
     }
 
-    pub fn create(bindings: &[vk::DescriptorSetLayoutBinding]) -> VkResult<Self> {
+    pub fn create(
+        context: &Arc<Context>,
+        bindings: &[vk::DescriptorSetLayoutBinding],
+    ) -> VkResult<Self> {
         unsafe {
             let owned = Owned::create(
+                context,
                 &vk::DescriptorSetLayoutCreateInfo::builder()
                     .bindings(bindings)
                     .build(),
@@ -2,13 +2,13 @@ use super::*;
 
 impl RawHandle for vk::Fence {}
 impl Create<&vk::FenceCreateInfo> for vk::Fence {
-    unsafe fn create(info: &vk::FenceCreateInfo) -> VkResult<Self> {
-        DEVICE.create_fence(info, ALLOC)
+    unsafe fn create(context: &Context, info: &vk::FenceCreateInfo) -> VkResult<Self> {
+        context.device.create_fence(info, ALLOC)
     }
 }
 impl Destroy for vk::Fence {
-    unsafe fn destroy(self) {
-        DEVICE.destroy_fence(self, ALLOC)
+    unsafe fn destroy(self, context: &Context) {
+        context.device.destroy_fence(self, ALLOC)
     }
 }
 
@@ -21,15 +21,16 @@ impl AsRef<vk::Fence> for Fence {
 }
 
 impl Fence {
-    pub fn create() -> VkResult<Self> {
+    pub fn create(context: &Arc<Context>) -> VkResult<Self> {
         Ok(Self(unsafe {
-            Owned::create(&vk::FenceCreateInfo::default())?
+            Owned::create(context, &vk::FenceCreateInfo::default())?
         }))
     }
 
-    pub fn create_signalled() -> VkResult<Self> {
+    pub fn create_signalled(context: &Arc<Context>) -> VkResult<Self> {
         Ok(Self(unsafe {
             Owned::create(
+                context,
                 &vk::FenceCreateInfo::builder()
                     .flags(vk::FenceCreateFlags::SIGNALED)
                     .build(),
@@ -38,14 +39,18 @@ impl Fence {
     }
 
     pub fn is_signalled(&self) -> VkResult<bool> {
-        unsafe { DEVICE.get_fence_status(self.as_raw()) }
+        unsafe { self.device().get_fence_status(self.as_raw()) }
     }
 
     pub fn wait(&self) -> VkResult<()> {
-        unsafe { DEVICE.wait_for_fences(&[self.as_raw()], false, !0) }
+        unsafe { self.device().wait_for_fences(&[self.as_raw()], false, !0) }
     }
 
     pub fn reset(&self) -> VkResult<()> {
-        unsafe { DEVICE.reset_fences(&[self.as_raw()]) }
+        unsafe { self.device().reset_fences(&[self.as_raw()]) }
+    }
+
+    fn device(&self) -> &ash::Device {
+        &self.0.context().device
     }
 }
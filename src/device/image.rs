@@ -2,13 +2,13 @@ use super::*;
 
 impl RawHandle for vk::Image {}
 impl Create<&vk::ImageCreateInfo> for vk::Image {
-    unsafe fn create(info: &vk::ImageCreateInfo) -> VkResult<Self> {
-        DEVICE.create_image(info, ALLOC)
+    unsafe fn create(context: &Context, info: &vk::ImageCreateInfo) -> VkResult<Self> {
+        context.device.create_image(info, ALLOC)
     }
 }
 impl Destroy for vk::Image {
-    unsafe fn destroy(self) {
-        DEVICE.destroy_image(self, ALLOC)
+    unsafe fn destroy(self, context: &Context) {
+        context.device.destroy_image(self, ALLOC)
     }
 }
 
@@ -34,6 +34,7 @@ pub fn mip_levels((width, height): (u32, u32)) -> u32 {
 
 impl ImageObject {
     pub fn create_2d(
+        context: &Arc<Context>,
         (width, height): (u32, u32),
         mip_levels: u32,
         format: vk::Format,
@@ -42,6 +43,7 @@ impl ImageObject {
     ) -> VkResult<Self> {
         unsafe {
             let owned = Owned::create(
+                context,
                 &vk::ImageCreateInfo::builder()
                     .image_type(vk::ImageType::TYPE_2D)
                     .format(format)
@@ -55,7 +57,7 @@ impl ImageObject {
                     .samples(samples)
                     .tiling(vk::ImageTiling::OPTIMAL)
                     .usage(usage)
-                    .queue_family_indices(&[GRAPHICS_QUEUE_FAMILY_INDEX])
+                    .queue_family_indices(&[context.graphics_queue.family_index])
                     .initial_layout(vk::ImageLayout::UNDEFINED)
                     .build(),
             )?;
@@ -65,6 +67,7 @@ impl ImageObject {
     }
 
     pub fn create_cube(
+        context: &Arc<Context>,
         (width, height): (u32, u32),
         mip_levels: u32,
         format: vk::Format,
@@ -72,6 +75,7 @@ impl ImageObject {
     ) -> VkResult<Self> {
         unsafe {
             let owned = Owned::create(
+                context,
                 &vk::ImageCreateInfo::builder()
                     .flags(vk::ImageCreateFlags::CUBE_COMPATIBLE)
                     .image_type(vk::ImageType::TYPE_2D)
@@ -86,7 +90,7 @@ impl ImageObject {
                     .samples(vk::SampleCountFlags::TYPE_1)
                     .tiling(vk::ImageTiling::OPTIMAL)
                     .usage(usage)
-                    .queue_family_indices(&[GRAPHICS_QUEUE_FAMILY_INDEX])
+                    .queue_family_indices(&[context.graphics_queue.family_index])
                     .initial_layout(vk::ImageLayout::UNDEFINED)
                     .build(),
             )?;
@@ -96,21 +100,38 @@ impl ImageObject {
     }
 
     pub fn memory_requirements(&self) -> vk::MemoryRequirements {
-        unsafe { DEVICE.get_image_memory_requirements(self.as_raw()) }
+        unsafe {
+            self.context()
+                .device
+                .get_image_memory_requirements(self.as_raw())
+        }
     }
 
-    pub fn bind_memory(&self, memory: vk::DeviceMemory) -> VkResult<()> {
-        unsafe { DEVICE.bind_image_memory(self.as_raw(), memory, 0) }
+    pub fn bind_memory(
+        &self,
+        memory: impl AsRawHandle<vk::DeviceMemory>,
+        offset: vk::DeviceSize,
+    ) -> VkResult<()> {
+        unsafe {
+            self.context()
+                .device
+                .bind_image_memory(self.as_raw(), memory.as_raw(), offset)
+        }
+    }
+
+    pub fn context(&self) -> &Arc<Context> {
+        self.0.context()
     }
 }
 
 pub struct Image {
     pub object: ImageObject,
-    pub memory: Memory,
+    pub memory: Allocation,
 }
 
 impl Image {
     pub fn create_2d(
+        context: &Arc<Context>,
         size: (u32, u32),
         mip_levels: u32,
         format: vk::Format,
@@ -119,21 +140,100 @@ impl Image {
         memory_type_mask: MemoryTypeMask,
     ) -> VkResult<Self> {
         Self::create(
-            ImageObject::create_2d(size, mip_levels, format, samples, usage)?,
+            ImageObject::create_2d(context, size, mip_levels, format, samples, usage)?,
             memory_type_mask,
+            MemoryPurpose::of_image(usage),
         )
     }
 
-    pub fn create(object: ImageObject, memory_type_mask: MemoryTypeMask) -> VkResult<Self> {
+    pub fn create(
+        object: ImageObject,
+        memory_type_mask: MemoryTypeMask,
+        purpose: MemoryPurpose,
+    ) -> VkResult<Self> {
         let memory_requirements = object.memory_requirements();
 
-        let memory = Memory::allocate(
-            memory_requirements.size,
-            (MemoryTypeMask(memory_requirements.memory_type_bits) & memory_type_mask).first_index(),
+        let memory = Allocation::allocate(
+            object.context(),
+            &memory_requirements,
+            memory_type_mask,
+            ResourceKind::Optimal,
+            purpose,
         )?;
 
-        object.bind_memory(memory.as_raw())?;
+        object.bind_memory(&memory, memory.offset())?;
 
         Ok(Self { object, memory })
     }
+
+    /// Records `transfer` on the transfer queue, e.g. copies into the image, then hands the
+    /// image over to the graphics queue with the `handover` barrier, followed by `graphics`.
+    /// Waits for both to complete.
+    ///
+    /// With a dedicated transfer queue, `handover` releases ownership of its subresources from the
+    /// transfer queue family and acquires it on the graphics queue family once the transfer
+    /// submission's semaphore is signalled. Otherwise it's a plain barrier in a single submission.
+    pub fn upload(
+        &self,
+        handover: vk::ImageMemoryBarrier,
+        dst_stage_mask: vk::PipelineStageFlags,
+        transfer: impl FnOnce(&CommandBufferRecorder),
+        graphics: impl FnOnce(&CommandBufferRecorder),
+    ) -> VkResult<()> {
+        let context = self.object.context();
+        let src_family_index = context.queue(QueueKind::Transfer).family_index;
+        let dst_family_index = context.graphics_queue.family_index;
+        let handover = vk::ImageMemoryBarrier {
+            image: self.object.as_raw(),
+            ..handover
+        };
+
+        if src_family_index == dst_family_index {
+            let recorder = CommandBuffer::create(context, QueueKind::Graphics)?;
+            transfer(&recorder);
+            recorder.image_transition(
+                vk::PipelineStageFlags::TRANSFER,
+                dst_stage_mask,
+                &[handover],
+            );
+            graphics(&recorder);
+            return recorder.end()?.submit();
+        }
+
+        let handover = vk::ImageMemoryBarrier {
+            src_queue_family_index: src_family_index,
+            dst_queue_family_index: dst_family_index,
+            ..handover
+        };
+        // The release's destination access and the acquire's source access are ignored, the
+        // semaphore provides the dependency between them.
+        let release = CommandBuffer::create(context, QueueKind::Transfer)?;
+        transfer(&release);
+        release.image_transition(
+            vk::PipelineStageFlags::TRANSFER,
+            vk::PipelineStageFlags::BOTTOM_OF_PIPE,
+            &[vk::ImageMemoryBarrier {
+                dst_access_mask: vk::AccessFlags::empty(),
+                ..handover
+            }],
+        );
+        let acquire = CommandBuffer::create(context, QueueKind::Graphics)?;
+        acquire.image_transition(
+            dst_stage_mask,
+            dst_stage_mask,
+            &[vk::ImageMemoryBarrier {
+                src_access_mask: vk::AccessFlags::empty(),
+                ..handover
+            }],
+        );
+        graphics(&acquire);
+
+        let released = Semaphore::create(context)?;
+        // Kept alive until the acquire has completed, which waits for it.
+        let release = release.end()?;
+        release.submit_signalling(&[], &[], &[released.as_raw()], vk::Fence::null())?;
+        acquire
+            .end()?
+            .submit_after(&[released.as_raw()], &[dst_stage_mask])
+    }
 }
@@ -2,13 +2,13 @@ use super::*;
 
 impl RawHandle for vk::ImageView {}
 impl Create<&vk::ImageViewCreateInfo> for vk::ImageView {
-    unsafe fn create(info: &vk::ImageViewCreateInfo) -> VkResult<Self> {
-        DEVICE.create_image_view(info, ALLOC)
+    unsafe fn create(context: &Context, info: &vk::ImageViewCreateInfo) -> VkResult<Self> {
+        context.device.create_image_view(info, ALLOC)
     }
 }
 impl Destroy for vk::ImageView {
-    unsafe fn destroy(self) {
-        DEVICE.destroy_image_view(self, ALLOC)
+    unsafe fn destroy(self, context: &Context) {
+        context.device.destroy_image_view(self, ALLOC)
     }
 }
 
@@ -22,14 +22,22 @@ impl AsRef<vk::ImageView> for ImageView {
 
 impl ImageView {
     pub fn create_2d(
+        context: &Arc<Context>,
         image: vk::Image,
         format: vk::Format,
         aspect_mask: vk::ImageAspectFlags,
     ) -> VkResult<Self> {
-        Self::create(image, vk::ImageViewType::TYPE_2D, format, aspect_mask)
+        Self::create(
+            context,
+            image,
+            vk::ImageViewType::TYPE_2D,
+            format,
+            aspect_mask,
+        )
     }
 
     pub fn create(
+        context: &Arc<Context>,
         image: vk::Image,
         view_type: vk::ImageViewType,
         format: vk::Format,
@@ -37,6 +45,7 @@ impl ImageView {
     ) -> VkResult<Self> {
         let owned = unsafe {
             Owned::create(
+                context,
                 &vk::ImageViewCreateInfo::builder()
                     .image(image)
                     .view_type(view_type)
@@ -1,3 +1,6 @@
+use std::collections::BTreeMap;
+use std::fmt;
+
 use super::*;
 
 pub fn size_of<T>() -> vk::DeviceSize {
@@ -12,35 +15,25 @@ pub fn size_of_val<T: ?Sized>(val: &T) -> vk::DeviceSize {
 pub struct MemoryTypeIndex(pub u32);
 
 impl MemoryTypeIndex {
-    const ALL: MemoryTypeIndexAll = MemoryTypeIndexAll(0);
+    fn all(context: &Context) -> impl Iterator<Item = Self> {
+        (0..context.memory_properties.memory_type_count).map(Self)
+    }
 
-    pub fn is_valid(self) -> bool {
-        self.0 < unsafe { &MEMORY_PROPERTIES }.memory_type_count
+    pub fn is_valid(self, context: &Context) -> bool {
+        self.0 < context.memory_properties.memory_type_count
     }
 
-    pub fn property_flags(self) -> vk::MemoryPropertyFlags {
-        if self.is_valid() {
-            unsafe { &MEMORY_PROPERTIES }.memory_types[self.0 as usize].property_flags
+    pub fn property_flags(self, context: &Context) -> vk::MemoryPropertyFlags {
+        if self.is_valid(context) {
+            context.memory_properties.memory_types[self.0 as usize].property_flags
         } else {
             vk::MemoryPropertyFlags::empty()
         }
     }
-}
-
-#[derive(Copy, Clone)]
-struct MemoryTypeIndexAll(u32);
 
-impl Iterator for MemoryTypeIndexAll {
-    type Item = MemoryTypeIndex;
-
-    fn next(&mut self) -> Option<Self::Item> {
-        if self.0 < unsafe { &MEMORY_PROPERTIES }.memory_type_count {
-            let result = Some(MemoryTypeIndex(self.0));
-            self.0 += 1;
-            result
-        } else {
-            None
-        }
+    pub fn heap_index(self, context: &Context) -> u32 {
+        assert!(self.is_valid(context), "invalid memory type {}", self.0);
+        context.memory_properties.memory_types[self.0 as usize].heap_index
     }
 }
 
@@ -60,20 +53,18 @@ impl MemoryTypeMask {
         Self(1 << index.0)
     }
 
-    pub fn with_properties(flags: vk::MemoryPropertyFlags) -> Self {
+    pub fn with_properties(context: &Context, flags: vk::MemoryPropertyFlags) -> Self {
         let mut mask = Self::none();
-        for index in MemoryTypeIndex::ALL {
-            if index.property_flags().contains(flags) {
+        for index in MemoryTypeIndex::all(context) {
+            if index.property_flags(context).contains(flags) {
                 mask |= Self::from_index(index);
             }
         }
         mask
     }
 
-    pub fn mappable() -> MemoryTypeMask {
-        Self::with_properties(
-            vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
-        )
+    pub fn mappable(context: &Context) -> MemoryTypeMask {
+        Self::with_properties(context, vk::MemoryPropertyFlags::HOST_VISIBLE)
     }
 
     pub fn first_index(self) -> MemoryTypeIndex {
@@ -109,13 +100,13 @@ impl std::ops::BitOrAssign for MemoryTypeMask {
 
 impl RawHandle for vk::DeviceMemory {}
 impl Create<&vk::MemoryAllocateInfo> for vk::DeviceMemory {
-    unsafe fn create(info: &vk::MemoryAllocateInfo) -> VkResult<Self> {
-        DEVICE.allocate_memory(info, ALLOC)
+    unsafe fn create(context: &Context, info: &vk::MemoryAllocateInfo) -> VkResult<Self> {
+        context.device.allocate_memory(info, ALLOC)
     }
 }
 impl Destroy for vk::DeviceMemory {
-    unsafe fn destroy(self) {
-        DEVICE.free_memory(self, ALLOC)
+    unsafe fn destroy(self, context: &Context) {
+        context.device.free_memory(self, ALLOC)
     }
 }
 
@@ -128,13 +119,14 @@ impl AsRef<vk::DeviceMemory> for Memory {
 }
 
 impl Memory {
-    pub fn allocate_mappable(size: vk::DeviceSize, type_mask: MemoryTypeMask) -> VkResult<Memory> {
-        Self::allocate(size, (type_mask & MemoryTypeMask::mappable()).first_index())
-    }
-
-    pub fn allocate(size: vk::DeviceSize, type_index: MemoryTypeIndex) -> VkResult<Memory> {
+    pub fn allocate(
+        context: &Arc<Context>,
+        size: vk::DeviceSize,
+        type_index: MemoryTypeIndex,
+    ) -> VkResult<Memory> {
         let owner = unsafe {
             Owned::create(
+                context,
                 &vk::MemoryAllocateInfo::builder()
                     .allocation_size(size)
                     .memory_type_index(type_index.0)
@@ -143,76 +135,167 @@ impl Memory {
         };
         Ok(Self(owner))
     }
+}
 
-    pub fn map(&self, offset: vk::DeviceSize, size: usize) -> VkResult<MemoryMapping> {
-        unsafe {
-            let ptr = DEVICE.map_memory(
-                self.as_raw(),
-                offset,
-                size as vk::DeviceSize,
-                vk::MemoryMapFlags::empty(),
-            )?;
-            Ok(MemoryMapping {
-                ptr,
-                size,
-                device_memory: self.as_raw(),
-            })
+/// What an allocation is used for, to break down memory use.
+#[derive(Copy, Clone, Debug, Ord, PartialOrd, Eq, PartialEq)]
+pub enum MemoryPurpose {
+    Texture,
+    Attachment,
+    Mesh,
+    Uniform,
+    Staging,
+    Readback,
+    Other,
+}
+
+impl MemoryPurpose {
+    pub fn of_buffer(usage: vk::BufferUsageFlags) -> Self {
+        if usage
+            .intersects(vk::BufferUsageFlags::VERTEX_BUFFER | vk::BufferUsageFlags::INDEX_BUFFER)
+        {
+            Self::Mesh
+        } else if usage.contains(vk::BufferUsageFlags::UNIFORM_BUFFER) {
+            Self::Uniform
+        } else if usage.contains(vk::BufferUsageFlags::TRANSFER_SRC) {
+            Self::Staging
+        } else if usage.contains(vk::BufferUsageFlags::TRANSFER_DST) {
+            Self::Readback
+        } else {
+            Self::Other
         }
     }
 
-    pub fn write<T: Copy + ?Sized>(&self, offset: vk::DeviceSize, source: &T) -> VkResult<()> {
-        let mut mapping = self.map(offset, std::mem::size_of_val(source))?;
-        mapping.write(0, source);
-        Ok(())
+    pub fn of_image(usage: vk::ImageUsageFlags) -> Self {
+        if usage.intersects(
+            vk::ImageUsageFlags::COLOR_ATTACHMENT
+                | vk::ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT
+                | vk::ImageUsageFlags::INPUT_ATTACHMENT
+                | vk::ImageUsageFlags::TRANSIENT_ATTACHMENT,
+        ) {
+            Self::Attachment
+        } else if usage.contains(vk::ImageUsageFlags::SAMPLED) {
+            Self::Texture
+        } else {
+            Self::Other
+        }
     }
+}
 
-    pub fn write_slice<T: Copy>(&self, offset: vk::DeviceSize, source: &[T]) -> VkResult<()> {
-        let mut mapping = self.map(offset, std::mem::size_of_val(source))?;
-        mapping.write_slice(0, source);
-        Ok(())
+impl fmt::Display for MemoryPurpose {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Texture => "texture",
+            Self::Attachment => "attachment",
+            Self::Mesh => "mesh",
+            Self::Uniform => "uniform",
+            Self::Staging => "staging",
+            Self::Readback => "readback",
+            Self::Other => "other",
+        })
     }
 }
 
-pub struct MemoryMapping {
-    ptr: *mut c_void,
-    size: usize,
-    device_memory: vk::DeviceMemory,
+/// The memory use of the whole process in a heap, as reported by `VK_EXT_memory_budget`.
+#[derive(Default, Debug, Copy, Clone)]
+pub struct HeapBudget {
+    pub usage: vk::DeviceSize,
+    /// An estimate of how much the process can use before allocations fail or cause paging.
+    pub budget: vk::DeviceSize,
 }
 
-impl Drop for MemoryMapping {
-    fn drop(&mut self) {
-        unsafe { DEVICE.unmap_memory(self.device_memory) }
+/// Returns the budget for each heap, or `None` if `VK_EXT_memory_budget` isn't supported.
+pub fn heap_budgets(context: &Context) -> Option<Vec<HeapBudget>> {
+    unsafe {
+        if !context.memory_budget {
+            return None;
+        }
+        let properties2 = context.instance.properties2.as_ref()?;
+        let mut budget = vk::PhysicalDeviceMemoryBudgetPropertiesEXT::default();
+        let mut properties = vk::PhysicalDeviceMemoryProperties2::builder()
+            .push_next(&mut budget)
+            .build();
+        properties2
+            .get_physical_device_memory_properties2_khr(context.physical_device, &mut properties);
+        let heap_count = properties.memory_properties.memory_heap_count as usize;
+        Some(
+            (0..heap_count)
+                .map(|heap| HeapBudget {
+                    usage: budget.heap_usage[heap],
+                    budget: budget.heap_budget[heap],
+                })
+                .collect(),
+        )
     }
 }
 
-impl MemoryMapping {
-    pub fn write<T: Copy + ?Sized>(&mut self, offset: usize, src: &T) {
-        let len = std::mem::size_of_val(src);
-        assert!(
-            offset + len <= self.size,
-            "offset = {:#x}, len = {:#x}, size = {:#x}",
-            offset,
-            len,
-            self.size,
-        );
-        let ptr = unsafe { self.ptr.add(offset) };
-        unsafe { std::ptr::copy_nonoverlapping(src as *const T as *const c_void, ptr, len) };
-    }
+/// A snapshot of memory use by heap and memory type, with a breakdown by purpose.
+pub struct MemoryReport {
+    pub memory_properties: vk::PhysicalDeviceMemoryProperties,
+    pub types: BTreeMap<MemoryTypeIndex, MemoryStatistics>,
+    pub budgets: Option<Vec<HeapBudget>>,
+}
 
-    pub fn slice<T: Copy>(&mut self, offset: usize, len: usize) -> &mut [T] {
-        assert!(
-            offset + len <= self.size,
-            "offset = {:#x}, len = {:#x}, size = {:#x}",
-            offset,
-            len,
-            self.size,
-        );
-        let ptr = unsafe { self.ptr.add(offset) }.cast();
-        unsafe { std::slice::from_raw_parts_mut::<T>(ptr, len) }
+impl MemoryReport {
+    pub fn collect(context: &Context) -> Self {
+        Self {
+            memory_properties: context.memory_properties,
+            types: memory_statistics(context),
+            budgets: heap_budgets(context),
+        }
     }
+}
 
-    pub fn write_slice<T: Copy>(&mut self, offset: usize, source: &[T]) {
-        self.slice::<T>(offset, source.len())
-            .copy_from_slice(source);
+impl fmt::Display for MemoryReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        const MIB: f64 = (1 << 20) as f64;
+        let properties = &self.memory_properties;
+        for heap in 0..properties.memory_heap_count {
+            let types = self
+                .types
+                .iter()
+                .filter(|(type_index, _)| {
+                    properties.memory_types[type_index.0 as usize].heap_index == heap
+                })
+                .collect::<Vec<_>>();
+            let bytes: vk::DeviceSize = types.iter().map(|(_, stats)| stats.device_bytes()).sum();
+            let heap_properties = &properties.memory_heaps[heap as usize];
+            write!(
+                f,
+                "heap {} ({:?}): {:.1} MiB allocated of {:.1} MiB",
+                heap,
+                heap_properties.flags,
+                bytes as f64 / MIB,
+                heap_properties.size as f64 / MIB,
+            )?;
+            if let Some(budget) = self.budgets.as_ref().and_then(|b| b.get(heap as usize)) {
+                write!(
+                    f,
+                    ", process usage {:.1} MiB of {:.1} MiB budget",
+                    budget.usage as f64 / MIB,
+                    budget.budget as f64 / MIB,
+                )?;
+            }
+            writeln!(f)?;
+            for (type_index, stats) in types {
+                writeln!(
+                    f,
+                    "  type {} ({:?}): {}",
+                    type_index.0,
+                    properties.memory_types[type_index.0 as usize].property_flags,
+                    stats
+                )?;
+                for (purpose, &(count, bytes)) in &stats.purposes {
+                    writeln!(
+                        f,
+                        "    {}: {} allocations, {:.1} MiB",
+                        purpose,
+                        count,
+                        bytes as f64 / MIB
+                    )?;
+                }
+            }
+        }
+        Ok(())
     }
 }
@@ -1,9 +1,13 @@
 #![allow(dead_code)]
 
 use std::ffi::c_void;
+use std::sync::Arc;
 
+pub use allocator::*;
+pub use bound_state::RecorderStats;
 pub use buffer::*;
 pub use command_buffer::*;
+pub use command_pool::*;
 pub use descriptor_set::*;
 pub use descriptor_set_layout::*;
 pub use fence::*;
@@ -15,10 +19,13 @@ pub use raw_handle::*;
 pub use sampler::*;
 pub use semaphore::*;
 
-use crate::globals::*;
+use crate::context::*;
 
+mod allocator;
+mod bound_state;
 mod buffer;
 mod command_buffer;
+mod command_pool;
 mod descriptor_set;
 mod descriptor_set_layout;
 mod fence;
@@ -2,13 +2,13 @@ use super::*;
 
 impl RawHandle for vk::PipelineLayout {}
 impl Create<&vk::PipelineLayoutCreateInfo> for vk::PipelineLayout {
-    unsafe fn create(info: &vk::PipelineLayoutCreateInfo) -> VkResult<Self> {
-        DEVICE.create_pipeline_layout(info, ALLOC)
+    unsafe fn create(context: &Context, info: &vk::PipelineLayoutCreateInfo) -> VkResult<Self> {
+        context.device.create_pipeline_layout(info, ALLOC)
     }
 }
 impl Destroy for vk::PipelineLayout {
-    unsafe fn destroy(self) {
-        DEVICE.destroy_pipeline_layout(self, ALLOC)
+    unsafe fn destroy(self, context: &Context) {
+        context.device.destroy_pipeline_layout(self, ALLOC)
     }
 }
 pub struct PipelineLayout(Owned<vk::PipelineLayout>);
@@ -21,11 +21,13 @@ impl AsRef<vk::PipelineLayout> for PipelineLayout {
 
 impl PipelineLayout {
     pub fn create(
+        context: &Arc<Context>,
         set_layouts: &[vk::DescriptorSetLayout],
         push_constant_ranges: &[vk::PushConstantRange],
     ) -> VkResult<Self> {
         let owned = unsafe {
             Owned::create(
+                context,
                 &vk::PipelineLayoutCreateInfo::builder()
                     .set_layouts(set_layouts)
                     .push_constant_ranges(push_constant_ranges)
@@ -38,13 +40,13 @@ impl PipelineLayout {
 
 impl RawHandle for vk::PipelineCache {}
 impl Create<&vk::PipelineCacheCreateInfo> for vk::PipelineCache {
-    unsafe fn create(info: &vk::PipelineCacheCreateInfo) -> VkResult<Self> {
-        DEVICE.create_pipeline_cache(info, ALLOC)
+    unsafe fn create(context: &Context, info: &vk::PipelineCacheCreateInfo) -> VkResult<Self> {
+        context.device.create_pipeline_cache(info, ALLOC)
     }
 }
 impl Destroy for vk::PipelineCache {
-    unsafe fn destroy(self) {
-        DEVICE.destroy_pipeline_cache(self, ALLOC)
+    unsafe fn destroy(self, context: &Context) {
+        context.device.destroy_pipeline_cache(self, ALLOC)
     }
 }
 pub struct PipelineCache(Owned<vk::PipelineCache>);
@@ -56,42 +58,52 @@ impl AsRef<vk::PipelineCache> for PipelineCache {
 }
 
 impl PipelineCache {
-    pub fn create() -> VkResult<Self> {
-        let owned = unsafe { Owned::create(&vk::PipelineCacheCreateInfo::default()) }?;
+    pub fn create(context: &Arc<Context>) -> VkResult<Self> {
+        let owned = unsafe { Owned::create(context, &vk::PipelineCacheCreateInfo::default()) }?;
         Ok(Self(owned))
     }
 
     pub fn create_pipeline(&self, infos: &vk::GraphicsPipelineCreateInfo) -> VkResult<Pipeline> {
+        let context = self.0.context();
         match unsafe {
-            DEVICE.create_graphics_pipelines(self.as_raw(), std::slice::from_ref(infos), ALLOC)
+            context.device.create_graphics_pipelines(
+                self.as_raw(),
+                std::slice::from_ref(infos),
+                ALLOC,
+            )
         } {
-            Ok(results) => Ok(Pipeline(unsafe { Owned::from_raw(results[0]) })),
+            Ok(results) => Ok(Pipeline(unsafe { Owned::from_raw(context, results[0]) })),
             Err((_results, err)) => Err(err),
         }
     }
+
+    pub fn context(&self) -> &Arc<Context> {
+        self.0.context()
+    }
 }
 
 impl RawHandle for vk::Pipeline {}
 impl Destroy for vk::Pipeline {
-    unsafe fn destroy(self) {
-        DEVICE.destroy_pipeline(self, ALLOC)
+    unsafe fn destroy(self, context: &Context) {
+        context.device.destroy_pipeline(self, ALLOC)
     }
 }
 pub struct Pipeline(Owned<vk::Pipeline>);
 
 impl Pipeline {
     pub fn create(
+        context: &Arc<Context>,
         cache: Option<vk::PipelineCache>,
         infos: &vk::GraphicsPipelineCreateInfo,
     ) -> VkResult<Pipeline> {
         match unsafe {
-            DEVICE.create_graphics_pipelines(
+            context.device.create_graphics_pipelines(
                 cache.unwrap_or(vk::PipelineCache::null()),
                 std::slice::from_ref(infos),
                 ALLOC,
             )
         } {
-            Ok(results) => Ok(Pipeline(unsafe { Owned::from_raw(results[0]) })),
+            Ok(results) => Ok(Pipeline(unsafe { Owned::from_raw(context, results[0]) })),
             Err((_results, err)) => Err(err),
         }
     }
@@ -1,14 +1,16 @@
-use crate::globals::*;
+use std::sync::Arc;
+
+use crate::context::*;
 
 pub trait Create<Info>: Sized {
-    unsafe fn create(info: Info) -> VkResult<Self>;
+    unsafe fn create(context: &Context, info: Info) -> VkResult<Self>;
 }
 pub trait CreateVec<Info>: Sized {
-    unsafe fn create_vec(info: Info) -> VkResult<Vec<Self>>;
+    unsafe fn create_vec(context: &Context, info: Info) -> VkResult<Vec<Self>>;
 }
 
 pub trait Destroy: Sized + Copy {
-    unsafe fn destroy(self);
+    unsafe fn destroy(self, context: &Context);
 }
 
 pub trait RawHandle: Copy {}
@@ -23,134 +25,148 @@ impl<T: RawHandle, R: AsRef<T>> AsRawHandle<T> for R {
     }
 }
 
-pub struct Owned<T: Destroy>(T);
+/// A handle destroyed when dropped, which keeps its device context alive until then.
+pub struct Owned<T: Destroy> {
+    handle: T,
+    context: Arc<Context>,
+}
 
 impl<T: Destroy> AsRef<T> for Owned<T> {
     fn as_ref(&self) -> &T {
-        &self.0
+        &self.handle
     }
 }
 
 impl<T: RawHandle + Destroy> Owned<T> {
-    pub unsafe fn from_raw(handle: T) -> Self {
-        Self(handle)
+    pub unsafe fn from_raw(context: &Arc<Context>, handle: T) -> Self {
+        Self {
+            handle,
+            context: context.clone(),
+        }
     }
 
     pub unsafe fn into_raw(self) -> T {
-        let raw = self.0;
+        let raw = self.handle;
+        // Drops the context reference without destroying the handle.
+        let _context = std::ptr::read(&self.context);
         std::mem::forget(self);
         raw
     }
 }
 
 impl<T: Destroy> Owned<T> {
-    pub unsafe fn create<I>(info: I) -> VkResult<Self>
+    pub unsafe fn create<I>(context: &Arc<Context>, info: I) -> VkResult<Self>
     where
         T: Create<I>,
     {
-        Ok(Self(T::create(info)?))
+        Ok(Self {
+            handle: T::create(context, info)?,
+            context: context.clone(),
+        })
     }
 
-    pub unsafe fn create_vec<I>(info: I) -> VkResult<Vec<Self>>
+    pub unsafe fn create_vec<I>(context: &Arc<Context>, info: I) -> VkResult<Vec<Self>>
     where
         T: CreateVec<I>,
     {
-        let raw_vec = T::create_vec(info)?;
-        Ok(raw_vec.into_iter().map(Self).collect())
+        let raw_vec = T::create_vec(context, info)?;
+        Ok(raw_vec
+            .into_iter()
+            .map(|handle| Self {
+                handle,
+                context: context.clone(),
+            })
+            .collect())
+    }
+
+    pub fn context(&self) -> &Arc<Context> {
+        &self.context
     }
 }
 
 impl<T: Destroy> Drop for Owned<T> {
     fn drop(&mut self) {
-        unsafe { self.0.destroy() }
+        unsafe { self.handle.destroy(&self.context) }
     }
 }
 
 impl RawHandle for vk::RenderPass {}
 impl Create<&vk::RenderPassCreateInfo> for vk::RenderPass {
-    unsafe fn create(info: &vk::RenderPassCreateInfo) -> VkResult<Self> {
-        DEVICE.create_render_pass(info, ALLOC)
+    unsafe fn create(context: &Context, info: &vk::RenderPassCreateInfo) -> VkResult<Self> {
+        context.device.create_render_pass(info, ALLOC)
     }
 }
 impl Create<&vk::RenderPassCreateInfo2> for vk::RenderPass {
-    unsafe fn create(info: &vk::RenderPassCreateInfo2) -> VkResult<Self> {
-        DEVICE.create_render_pass2(info, ALLOC)
+    unsafe fn create(context: &Context, info: &vk::RenderPassCreateInfo2) -> VkResult<Self> {
+        context.device.create_render_pass2(info, ALLOC)
     }
 }
 impl Destroy for vk::RenderPass {
-    unsafe fn destroy(self) {
-        DEVICE.destroy_render_pass(self, ALLOC)
+    unsafe fn destroy(self, context: &Context) {
+        context.device.destroy_render_pass(self, ALLOC)
     }
 }
 
 impl RawHandle for vk::BufferView {}
 impl Create<&vk::BufferViewCreateInfo> for vk::BufferView {
-    unsafe fn create(info: &vk::BufferViewCreateInfo) -> VkResult<Self> {
-        DEVICE.create_buffer_view(info, ALLOC)
+    unsafe fn create(context: &Context, info: &vk::BufferViewCreateInfo) -> VkResult<Self> {
+        context.device.create_buffer_view(info, ALLOC)
     }
 }
 impl Destroy for vk::BufferView {
-    unsafe fn destroy(self) {
-        DEVICE.destroy_buffer_view(self, ALLOC)
+    unsafe fn destroy(self, context: &Context) {
+        context.device.destroy_buffer_view(self, ALLOC)
     }
 }
 
 impl RawHandle for vk::SurfaceKHR {}
-impl Create<&vk::Win32SurfaceCreateInfoKHR> for vk::SurfaceKHR {
-    unsafe fn create(info: &vk::Win32SurfaceCreateInfoKHR) -> VkResult<Self> {
-        ext::WIN32_SURFACE.create_win32_surface(info, ALLOC)
-    }
-}
-impl Destroy for vk::SurfaceKHR {
-    unsafe fn destroy(self) {
-        ext::SURFACE.destroy_surface(self, ALLOC)
-    }
-}
 
 impl RawHandle for vk::SwapchainKHR {}
 impl Create<&vk::SwapchainCreateInfoKHR> for vk::SwapchainKHR {
-    unsafe fn create(info: &vk::SwapchainCreateInfoKHR) -> VkResult<Self> {
-        ext::SWAPCHAIN.create_swapchain(info, ALLOC)
+    unsafe fn create(context: &Context, info: &vk::SwapchainCreateInfoKHR) -> VkResult<Self> {
+        context.swapchain_loader()?.create_swapchain(info, ALLOC)
     }
 }
 impl Destroy for vk::SwapchainKHR {
-    unsafe fn destroy(self) {
-        ext::SWAPCHAIN.destroy_swapchain(self, ALLOC)
+    unsafe fn destroy(self, context: &Context) {
+        if let Ok(loader) = context.swapchain_loader() {
+            loader.destroy_swapchain(self, ALLOC)
+        }
     }
 }
 
 impl RawHandle for vk::ShaderModule {}
 impl Create<&vk::ShaderModuleCreateInfo> for vk::ShaderModule {
-    unsafe fn create(info: &vk::ShaderModuleCreateInfo) -> VkResult<Self> {
-        DEVICE.create_shader_module(info, ALLOC)
+    unsafe fn create(context: &Context, info: &vk::ShaderModuleCreateInfo) -> VkResult<Self> {
+        context.device.create_shader_module(info, ALLOC)
     }
 }
 impl Destroy for vk::ShaderModule {
-    unsafe fn destroy(self) {
-        DEVICE.destroy_shader_module(self, ALLOC)
+    unsafe fn destroy(self, context: &Context) {
+        context.device.destroy_shader_module(self, ALLOC)
     }
 }
 
 impl RawHandle for vk::Framebuffer {}
 impl Create<&vk::FramebufferCreateInfo> for vk::Framebuffer {
-    unsafe fn create(info: &vk::FramebufferCreateInfo) -> VkResult<Self> {
-        DEVICE.create_framebuffer(info, ALLOC)
+    unsafe fn create(context: &Context, info: &vk::FramebufferCreateInfo) -> VkResult<Self> {
+        context.device.create_framebuffer(info, ALLOC)
     }
 }
 impl Destroy for vk::Framebuffer {
-    unsafe fn destroy(self) {
-        DEVICE.destroy_framebuffer(self, ALLOC)
+    unsafe fn destroy(self, context: &Context) {
+        context.device.destroy_framebuffer(self, ALLOC)
     }
 }
 
 impl RawHandle for vk::Sampler {}
 impl Create<&vk::SamplerCreateInfo> for vk::Sampler {
-    unsafe fn create(info: &vk::SamplerCreateInfo) -> VkResult<Self> {
-        DEVICE.create_sampler(info, ALLOC)
+    unsafe fn create(context: &Context, info: &vk::SamplerCreateInfo) -> VkResult<Self> {
+        context.device.create_sampler(info, ALLOC)
     }
 }
 impl Destroy for vk::Sampler {
-    unsafe fn destroy(self) {
-        DEVICE.destroy_sampler(self, ALLOC)
+    unsafe fn destroy(self, context: &Context) {
+        context.device.destroy_sampler(self, ALLOC)
     }
 }
@@ -9,17 +9,18 @@ impl AsRef<vk::Sampler> for Sampler {
 }
 
 impl Sampler {
-    pub fn create(info: &vk::SamplerCreateInfo) -> VkResult<Self> {
-        let owned = unsafe { Owned::create(info) }?;
+    pub fn create(context: &Arc<Context>, info: &vk::SamplerCreateInfo) -> VkResult<Self> {
+        let owned = unsafe { Owned::create(context, info) }?;
         Ok(Self(owned))
     }
 
-    pub fn nearest() -> VkResult<Self> {
-        Self::create(&vk::SamplerCreateInfo::builder())
+    pub fn nearest(context: &Arc<Context>) -> VkResult<Self> {
+        Self::create(context, &vk::SamplerCreateInfo::builder())
     }
 
-    pub fn linear() -> VkResult<Self> {
+    pub fn linear(context: &Arc<Context>) -> VkResult<Self> {
         Self::create(
+            context,
             &vk::SamplerCreateInfo::builder()
                 .min_filter(vk::Filter::LINEAR)
                 .mag_filter(vk::Filter::LINEAR)
@@ -2,13 +2,13 @@ use super::*;
 
 impl RawHandle for vk::Semaphore {}
 impl Create<&vk::SemaphoreCreateInfo> for vk::Semaphore {
-    unsafe fn create(info: &vk::SemaphoreCreateInfo) -> VkResult<Self> {
-        DEVICE.create_semaphore(info, ALLOC)
+    unsafe fn create(context: &Context, info: &vk::SemaphoreCreateInfo) -> VkResult<Self> {
+        context.device.create_semaphore(info, ALLOC)
     }
 }
 impl Destroy for vk::Semaphore {
-    unsafe fn destroy(self) {
-        DEVICE.destroy_semaphore(self, ALLOC)
+    unsafe fn destroy(self, context: &Context) {
+        context.device.destroy_semaphore(self, ALLOC)
     }
 }
 
@@ -21,9 +21,9 @@ impl AsRef<vk::Semaphore> for Semaphore {
 }
 
 impl Semaphore {
-    pub fn create() -> VkResult<Self> {
+    pub fn create(context: &Arc<Context>) -> VkResult<Self> {
         Ok(Self(unsafe {
-            Owned::create(&vk::SemaphoreCreateInfo::default())?
+            Owned::create(context, &vk::SemaphoreCreateInfo::default())?
         }))
     }
 }
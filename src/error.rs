@@ -7,11 +7,16 @@ pub enum Error {
     Window(winit::error::OsError),
     Io(std::io::Error),
     Parse(serde_yaml::Error),
+    Json(serde_json::Error),
+    Gltf(String),
+    Definition(String),
     Png(png::DecodingError),
+    PngEncode(png::EncodingError),
     Ktx,
     VkEntry(ash::LoadingError),
     VkInstance(ash::InstanceError),
     VkSupport,
+    Usage(String),
     Compiler(shaderc::Error),
     Vk(ash::vk::Result),
 }
@@ -28,12 +33,24 @@ impl From<serde_yaml::Error> for Error {
     }
 }
 
+impl From<serde_json::Error> for Error {
+    fn from(inner: serde_json::Error) -> Error {
+        Self::Json(inner)
+    }
+}
+
 impl From<png::DecodingError> for Error {
     fn from(inner: png::DecodingError) -> Error {
         Self::Png(inner)
     }
 }
 
+impl From<png::EncodingError> for Error {
+    fn from(inner: png::EncodingError) -> Error {
+        Self::PngEncode(inner)
+    }
+}
+
 impl From<shaderc::Error> for Error {
     fn from(inner: shaderc::Error) -> Error {
         Self::Compiler(inner)
@@ -64,12 +81,17 @@ impl fmt::Display for Error {
             Self::Window(inner) => fmt::Display::fmt(inner, f),
             Self::Io(inner) => fmt::Display::fmt(inner, f),
             Self::Parse(inner) => fmt::Display::fmt(inner, f),
+            Self::Json(inner) => fmt::Display::fmt(inner, f),
+            Self::Gltf(message) => write!(f, "failed to load glTF file: {}", message),
+            Self::Definition(message) => write!(f, "invalid scene: {}", message),
             Self::Png(inner) => fmt::Display::fmt(inner, f),
+            Self::PngEncode(inner) => fmt::Display::fmt(inner, f),
             Self::Ktx => f.write_str("failed to parse ktx2 file"),
             Self::VkEntry(inner) => fmt::Display::fmt(inner, f),
             Self::VkInstance(inner) => fmt::Display::fmt(inner, f),
             Self::Vk(inner) => fmt::Display::fmt(inner, f),
             Self::VkSupport => f.write_str("Missing support"),
+            Self::Usage(message) => f.write_str(message),
             Self::Compiler(inner) => fmt::Display::fmt(inner, f),
         }
     }
@@ -81,12 +103,17 @@ impl std::error::Error for Error {
             Self::Window(inner) => Some(inner),
             Self::Io(inner) => Some(inner),
             Self::Parse(inner) => Some(inner),
+            Self::Json(inner) => Some(inner),
+            Self::Gltf(_) => None,
+            Self::Definition(_) => None,
             Self::Png(inner) => Some(inner),
+            Self::PngEncode(inner) => Some(inner),
             Self::Ktx => None,
             Self::VkEntry(inner) => Some(inner),
             Self::VkInstance(inner) => Some(inner),
             Self::Vk(inner) => Some(inner),
             Self::VkSupport => None,
+            Self::Usage(_) => None,
             Self::Compiler(inner) => Some(inner),
         }
     }
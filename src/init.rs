@@ -1,36 +1,136 @@
-use crate::device::{AsRawHandle, Owned};
-use crate::globals::*;
 use std::ffi::CStr;
+use std::sync::Arc;
+
+use crate::context::*;
+use crate::device::AsRawHandle;
+#[cfg(unix)]
+use winit::platform::unix::WindowExtUnix;
+#[cfg(windows)]
 use winit::platform::windows::WindowExtWindows;
 
-pub fn init(window: &winit::window::Window) -> Result<Owned<vk::SurfaceKHR>> {
-    unsafe {
-        init_instance()?;
+pub fn init(window: &winit::window::Window, validation: bool) -> Result<(Arc<Context>, Surface)> {
+    let entry = ash::Entry::new()?;
+    let surface_kind = select_surface_kind(&entry, window)?;
+    let instance = Instance::create(entry, Some(surface_kind), validation)?;
 
-        let surface = Owned::create(
-            &vk::Win32SurfaceCreateInfoKHR::builder()
-                .hinstance(window.hinstance())
-                .hwnd(window.hwnd())
-                .build(),
-        )?;
+    let surface = unsafe { create_surface(&instance, window)? };
 
-        if !select_physical_device_and_graphics_queue(surface.as_raw())? {
-            return Err(Error::VkSupport);
-        }
+    let context = Context::create(&instance, Some(surface.as_raw()))?;
+    print_device(&context);
+
+    // Call required to initialize the surface capabilities when initializing swapchain.
+    let _surface_capabilities = unsafe {
+        context
+            .surface_loader()?
+            .get_physical_device_surface_capabilities(context.physical_device, surface.as_raw())?
+    };
+
+    Ok((context, surface))
+}
 
-        let physical_device_props = INSTANCE.get_physical_device_properties(PHYSICAL_DEVICE);
-        println!(
-            "device: {:#x?} {:?}",
-            physical_device_props.device_type,
-            CStr::from_ptr(physical_device_props.device_name.as_ptr().cast()),
-        );
+pub fn init_headless(validation: bool) -> Result<Arc<Context>> {
+    let instance = Instance::create(ash::Entry::new()?, None, validation)?;
+    let context = Context::create(&instance, None)?;
+    print_device(&context);
+    Ok(context)
+}
 
-        // Call required to initialize the surface capabilities when initializing swapchain.
-        let _surface_capabilities = ext::SURFACE
-            .get_physical_device_surface_capabilities(PHYSICAL_DEVICE, surface.as_raw())?;
+fn print_device(context: &Context) {
+    let physical_device_props = unsafe {
+        context
+            .instance
+            .instance
+            .get_physical_device_properties(context.physical_device)
+    };
+    println!(
+        "device: {:#x?} {:?}",
+        physical_device_props.device_type,
+        unsafe { CStr::from_ptr(physical_device_props.device_name.as_ptr().cast()) },
+    );
+    // Kinds without a dedicated queue use the graphics queue.
+    println!(
+        "queue families: graphics {}, transfer {}, compute {}",
+        context.queue(QueueKind::Graphics).family_index,
+        context.queue(QueueKind::Transfer).family_index,
+        context.queue(QueueKind::Compute).family_index,
+    );
+}
+
+#[cfg(windows)]
+fn select_surface_kind(
+    _entry: &ash::Entry,
+    _window: &winit::window::Window,
+) -> Result<ext::PlatformSurfaceKind> {
+    Ok(ext::PlatformSurfaceKind::Win32)
+}
 
-        init_device()?;
+#[cfg(unix)]
+fn select_surface_kind(
+    entry: &ash::Entry,
+    window: &winit::window::Window,
+) -> Result<ext::PlatformSurfaceKind> {
+    use ext::PlatformSurfaceKind::*;
 
-        Ok(surface)
+    if window.wayland_surface().is_some() {
+        return Ok(Wayland);
     }
+    if window.xlib_window().is_some() {
+        // winit gives us both an Xlib display and an XCB connection for X11 windows,
+        // so use whichever the loader supports, preferring Xlib.
+        for &kind in &[Xlib, Xcb] {
+            if supports_instance_extension(entry, kind.extension_name())? {
+                return Ok(kind);
+            }
+        }
+    }
+    Err(Error::VkSupport)
+}
+
+#[cfg(windows)]
+unsafe fn create_surface(
+    instance: &Arc<Instance>,
+    window: &winit::window::Window,
+) -> Result<Surface> {
+    let ext::PlatformSurface::Win32(loader) =
+        instance.platform_surface.as_ref().ok_or(Error::VkSupport)?;
+    let handle = loader.create_win32_surface(
+        &vk::Win32SurfaceCreateInfoKHR::builder()
+            .hinstance(window.hinstance())
+            .hwnd(window.hwnd())
+            .build(),
+        ALLOC,
+    )?;
+    Ok(Surface::from_raw(instance, handle))
+}
+
+#[cfg(unix)]
+unsafe fn create_surface(
+    instance: &Arc<Instance>,
+    window: &winit::window::Window,
+) -> Result<Surface> {
+    let missing = || Error::VkSupport;
+    let handle = match instance.platform_surface.as_ref().ok_or_else(missing)? {
+        ext::PlatformSurface::Xlib(loader) => loader.create_xlib_surface(
+            &vk::XlibSurfaceCreateInfoKHR::builder()
+                .dpy(window.xlib_display().ok_or_else(missing)?.cast())
+                .window(window.xlib_window().ok_or_else(missing)?)
+                .build(),
+            ALLOC,
+        )?,
+        ext::PlatformSurface::Xcb(loader) => loader.create_xcb_surface(
+            &vk::XcbSurfaceCreateInfoKHR::builder()
+                .connection(window.xcb_connection().ok_or_else(missing)?)
+                .window(window.xlib_window().ok_or_else(missing)? as vk::xcb_window_t)
+                .build(),
+            ALLOC,
+        )?,
+        ext::PlatformSurface::Wayland(loader) => loader.create_wayland_surface(
+            &vk::WaylandSurfaceCreateInfoKHR::builder()
+                .display(window.wayland_display().ok_or_else(missing)?)
+                .surface(window.wayland_surface().ok_or_else(missing)?)
+                .build(),
+            ALLOC,
+        )?,
+    };
+    Ok(Surface::from_raw(instance, handle))
 }
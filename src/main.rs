@@ -1,20 +1,26 @@
-use std::time::Instant;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
 use ash::prelude::VkResult;
 use ash::vk;
+use winit::dpi::PhysicalSize;
 use winit::event::{Event, WindowEvent};
 use winit::event_loop::{ControlFlow, EventLoop};
-use winit::window::Window;
+use winit::window::WindowBuilder;
 
-use device::{AsRawHandle, CommandBuffer};
+use context::Context;
+use device::AsRawHandle;
 use error::*;
+use options::Options;
 
+mod context;
 mod device;
 // mod ecs;
 mod error;
-mod globals;
 mod init;
 mod math;
+mod options;
 // mod render_graph;
 mod renderer;
 mod resources;
@@ -26,17 +32,39 @@ fn main() {
         std::process::exit(3);
     }));
 
-    if let Err(err) = run() {
+    let result = Options::parse(std::env::args().skip(1)).and_then(|options| {
+        if options.headless {
+            run_headless(options)
+        } else {
+            run(options)
+        }
+    });
+
+    if let Err(err) = result {
         eprintln!("Failed: {}", err);
         std::process::exit(1);
     }
 }
 
-fn run() -> Result<()> {
+fn run(options: Options) -> Result<()> {
     let event_loop = EventLoop::new();
-    let window = Window::new(&event_loop).map_err(Error::Window)?;
-    let surface = init::init(&window)?;
-    let mut render_context = RenderContext::new(window.inner_size().into(), surface)?;
+    let mut window_builder = WindowBuilder::new();
+    if let Some((width, height)) = options.size {
+        window_builder = window_builder.with_inner_size(PhysicalSize::new(width, height));
+    }
+    let window = window_builder.build(&event_loop).map_err(Error::Window)?;
+    let (context, surface) = init::init(&window, options.validation)?;
+    let renderer = renderer::Renderer::create(
+        &context,
+        surface.as_raw(),
+        window.inner_size().into(),
+        options.samples,
+        &options.present_modes,
+        &options.color_spaces,
+        options.screenshot.is_some(),
+    )?;
+    let mut render_context = RenderContext::new(&context, renderer, Some(surface), &options)?;
+    let mut frame = 0;
 
     std::mem::forget(window);
 
@@ -46,38 +74,106 @@ fn run() -> Result<()> {
         }
         Event::WindowEvent { event, .. } => match event {
             WindowEvent::CloseRequested => {
+                // Only the last frame is captured, so render one more to save.
+                if options.screenshot.is_some() {
+                    render_context.renderer.capture_next_frame();
+                    render_context.render().unwrap();
+                }
                 *flow = ControlFlow::Exit;
             }
             WindowEvent::Resized(size) => {
                 render_context.resize(size.into()).unwrap();
             }
+            event if !options.still => render_context.scene.handle_window_event(&event),
             _ => {}
         },
+        Event::DeviceEvent { event, .. } if !options.still => {
+            render_context.scene.handle_device_event(&event);
+        }
         Event::MainEventsCleared => {
             render_context.update();
+            let last = Some(frame + 1) == options.frames;
+            if last && options.screenshot.is_some() {
+                render_context.renderer.capture_next_frame();
+            }
             render_context.render().unwrap();
+            frame += 1;
+            if last {
+                *flow = ControlFlow::Exit;
+            }
+        }
+        Event::LoopDestroyed => {
+            if let Some(path) = &options.screenshot {
+                if let Err(err) = render_context.save_screenshot(path) {
+                    eprintln!("Failed to save screenshot: {}", err);
+                }
+            }
         }
         _ => {}
     });
 }
 
+/// Renders offscreen at a fixed 60 fps timestep for `--frames` frames (default 1),
+/// saving the last frame if `--screenshot` is given.
+fn run_headless(options: Options) -> Result<()> {
+    let context = init::init_headless(options.validation)?;
+    let renderer = renderer::Renderer::create_offscreen(
+        &context,
+        options.size.unwrap_or((1024, 768)),
+        options.samples,
+    )?;
+    let mut render_context = RenderContext::new(&context, renderer, None, &options)?;
+
+    let frames = options.frames.unwrap_or(1);
+    for frame in 0..frames {
+        render_context.update_at(Duration::from_secs_f64(frame as f64 / 60.0));
+        if frame + 1 == frames && options.screenshot.is_some() {
+            render_context.renderer.capture_next_frame();
+        }
+        render_context.render()?;
+    }
+
+    if let Some(path) = &options.screenshot {
+        render_context.save_screenshot(path)?;
+    }
+
+    Ok(())
+}
+
 #[allow(dead_code)]
 struct RenderContext {
     renderer: renderer::Renderer,
-    surface: device::Owned<vk::SurfaceKHR>,
+    surface: Option<context::Surface>,
     start_time: Instant,
     scene: scene::SceneWatcher,
+    /// Holds the scene at time 0, see `--still`.
+    still: bool,
+    /// The commands recorded since the stats were last printed, if `--render-stats` is given.
+    render_stats: Option<RenderStats>,
+}
+
+struct RenderStats {
+    stats: device::RecorderStats,
+    frames: u32,
+    since: Instant,
 }
 
 impl RenderContext {
-    fn new(size: (u32, u32), surface: device::Owned<vk::SurfaceKHR>) -> Result<Self> {
-        let renderer = renderer::Renderer::create(surface.as_raw(), size)?;
+    fn new(
+        context: &Arc<Context>,
+        renderer: renderer::Renderer,
+        surface: Option<context::Surface>,
+        options: &Options,
+    ) -> Result<Self> {
         let mut scene = scene::SceneWatcher::create(
+            context,
             renderer.render_pass.as_raw(),
             renderer.samples,
-            std::path::Path::new("assets/scene.yaml"),
+            renderer.color_space,
+            &options.scene,
+            options.memory_report,
         )?;
-        scene.resize(size);
+        scene.resize(renderer.size);
 
         let start_time = Instant::now();
 
@@ -86,6 +182,16 @@ impl RenderContext {
             surface,
             start_time,
             scene,
+            still: options.still,
+            render_stats: if options.render_stats {
+                Some(RenderStats {
+                    stats: Default::default(),
+                    frames: 0,
+                    since: start_time,
+                })
+            } else {
+                None
+            },
         })
     }
 
@@ -95,16 +201,34 @@ impl RenderContext {
         Ok(())
     }
 
+    pub fn save_screenshot(&self, path: &Path) -> Result<()> {
+        self.renderer.save_png(path)?;
+        println!("saved {:?}", path);
+        Ok(())
+    }
+
     pub fn update(&mut self) {
-        self.scene.update(self.start_time.elapsed());
+        self.update_at(self.start_time.elapsed());
+    }
+
+    pub fn update_at(&mut self, elapsed: Duration) {
+        // The controller only moves the camera by the time since the last update.
+        let elapsed = if self.still { Duration::ZERO } else { elapsed };
+        self.scene.update(elapsed);
     }
 
     pub fn render(&mut self) -> Result<()> {
-        use globals::*;
-        let swapchain_item = self.renderer.acquire_image()?;
+        let size = self.renderer.size;
+        let (swapchain_item, recorder) = match self.renderer.begin_frame()? {
+            Some(frame) => frame,
+            None => return Ok(()),
+        };
+        // The swapchain may have been recreated at the surface's current size.
+        if self.renderer.size != size {
+            self.scene.resize(self.renderer.size);
+        }
         let (width, height) = self.renderer.size;
 
-        let recorder = CommandBuffer::create()?;
         recorder.set_viewport_scissor(self.renderer.size);
 
         let recorder = recorder.begin_render_pass(
@@ -119,7 +243,7 @@ impl RenderContext {
                     },
                     vk::ClearValue {
                         depth_stencil: vk::ClearDepthStencilValue {
-                            depth: 1.0,
+                            depth: self.scene.clear_depth(),
                             stencil: 0,
                         },
                     },
@@ -130,15 +254,20 @@ impl RenderContext {
                 }),
         );
 
-        self.scene.render(&recorder)?;
-
-        let command_buffer = recorder.end_render_pass().end()?;
-        command_buffer.submit_after(
-            self.renderer.image_acquire_semaphore.as_raw(),
-            vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
-        )?;
+        self.scene.render(&recorder, self.renderer.frame_index())?;
+        if let Some(render_stats) = &mut self.render_stats {
+            render_stats.stats += recorder.stats();
+            render_stats.frames += 1;
+            if render_stats.since.elapsed() >= Duration::from_secs(1) {
+                println!("{} frames:\n{}", render_stats.frames, render_stats.stats);
+                render_stats.stats = Default::default();
+                render_stats.frames = 0;
+                render_stats.since = Instant::now();
+            }
+        }
 
-        self.renderer.present(swapchain_item.index)?;
+        self.renderer
+            .submit(recorder.end_render_pass(), &swapchain_item)?;
 
         Ok(())
     }
@@ -207,6 +207,40 @@ impl Quaternion {
         let d = (x * x + y * y + z * z + w * w).sqrt();
         Self([x / d, y / d, z / d, w / d])
     }
+
+    pub fn dot(self, rhs: Self) -> f32 {
+        let [lx, ly, lz, lw] = self.0;
+        let [rx, ry, rz, rw] = rhs.0;
+        lx * rx + ly * ry + lz * rz + lw * rw
+    }
+
+    /// Spherical linear interpolation, taking the shortest path.
+    pub fn slerp(self, to: Self, t: f32) -> Self {
+        let mut to = to.0;
+        let mut cos = self.dot(Self(to));
+        if cos < 0.0 {
+            cos = -cos;
+            to = [-to[0], -to[1], -to[2], -to[3]];
+        }
+
+        // Fall back to linear interpolation when nearly parallel to avoid dividing by ~0.
+        let (a, b) = if cos > 0.9995 {
+            (1.0 - t, t)
+        } else {
+            let angle = cos.acos();
+            let sin = angle.sin();
+            (((1.0 - t) * angle).sin() / sin, (t * angle).sin() / sin)
+        };
+
+        let from = self.0;
+        Self([
+            a * from[0] + b * to[0],
+            a * from[1] + b * to[1],
+            a * from[2] + b * to[2],
+            a * from[3] + b * to[3],
+        ])
+        .normalized()
+    }
 }
 
 impl Default for Quaternion {
@@ -221,6 +255,12 @@ impl From<(Vec3, f32)> for Quaternion {
     }
 }
 
+impl From<Quaternion> for [f32; 4] {
+    fn from(value: Quaternion) -> Self {
+        value.0
+    }
+}
+
 impl From<Quaternion> for (Vec3, f32) {
     fn from(value: Quaternion) -> Self {
         let [x, y, z, w] = value.0;
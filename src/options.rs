@@ -0,0 +1,163 @@
+use std::path::PathBuf;
+
+use ash::vk;
+
+use crate::error::*;
+use crate::renderer::ColorSpace;
+
+const USAGE: &str = "\
+usage: rust-vk [OPTIONS] [SCENE]
+
+    SCENE                   scene file to render (default: assets/scene.yaml)
+    --size WxH              window or image size
+    --samples N             MSAA sample count, at least 2 (default: highest supported)
+    --present-mode MODES    comma separated preference of fifo, mailbox, immediate and
+                            fifo-relaxed, falling back to fifo (default: fifo)
+    --color-space SPACES    comma separated preference of srgb, hdr10 and scrgb,
+                            falling back to srgb (default: srgb)
+    --no-validation         disable the Vulkan validation layers
+    --frames N              exit after rendering N frames
+    --screenshot PATH       save the last frame rendered before exiting as a PNG
+    --headless              render offscreen without creating a window
+    --still                 hold the scene at time 0 and ignore camera input, so each
+                            frame is the same
+    --memory-report         print device memory use whenever the scene is loaded
+                            (press M to print it at any time)
+    --render-stats          print the state commands recorded and skipped as redundant
+                            each second
+    --help                  show this message";
+
+pub struct Options {
+    pub scene: PathBuf,
+    pub size: Option<(u32, u32)>,
+    pub samples: Option<vk::SampleCountFlags>,
+    pub present_modes: Vec<vk::PresentModeKHR>,
+    pub color_spaces: Vec<ColorSpace>,
+    pub validation: bool,
+    pub frames: Option<u32>,
+    pub screenshot: Option<PathBuf>,
+    pub headless: bool,
+    pub still: bool,
+    pub memory_report: bool,
+    pub render_stats: bool,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self {
+            scene: PathBuf::from("assets/scene.yaml"),
+            size: None,
+            samples: None,
+            present_modes: vec![vk::PresentModeKHR::FIFO],
+            color_spaces: vec![ColorSpace::Srgb],
+            validation: true,
+            frames: None,
+            screenshot: None,
+            headless: false,
+            still: false,
+            memory_report: false,
+            render_stats: false,
+        }
+    }
+}
+
+impl Options {
+    pub fn parse(args: impl IntoIterator<Item = String>) -> Result<Self> {
+        let mut options = Self::default();
+        let mut scene = None;
+
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            let mut value = || {
+                args.next()
+                    .ok_or_else(|| usage(format!("missing value for {}", arg)))
+            };
+            match arg.as_str() {
+                "--size" => options.size = Some(parse_size(&value()?)?),
+                "--samples" => options.samples = Some(parse_samples(&value()?)?),
+                "--present-mode" => {
+                    options.present_modes = parse_list(&value()?, parse_present_mode)?
+                }
+                "--color-space" => options.color_spaces = parse_list(&value()?, parse_color_space)?,
+                "--no-validation" => options.validation = false,
+                "--frames" => options.frames = Some(parse_number(&value()?)?),
+                "--screenshot" => options.screenshot = Some(PathBuf::from(value()?)),
+                "--headless" => options.headless = true,
+                "--still" => options.still = true,
+                "--memory-report" => options.memory_report = true,
+                "--render-stats" => options.render_stats = true,
+                "--help" => {
+                    println!("{}", USAGE);
+                    std::process::exit(0);
+                }
+                _ if arg.starts_with("--") => {
+                    return Err(usage(format!("unknown option {}", arg)));
+                }
+                _ if scene.is_none() => scene = Some(PathBuf::from(arg)),
+                _ => return Err(usage(format!("unexpected argument {}", arg))),
+            }
+        }
+
+        if let Some(scene) = scene {
+            options.scene = scene;
+        }
+
+        Ok(options)
+    }
+}
+
+fn usage(message: String) -> Error {
+    Error::Usage(format!("{}\n\n{}", message, USAGE))
+}
+
+fn parse_number(value: &str) -> Result<u32> {
+    value
+        .parse()
+        .map_err(|_| usage(format!("expected a number, got {:?}", value)))
+}
+
+fn parse_size(value: &str) -> Result<(u32, u32)> {
+    let mut parts = value.splitn(2, 'x').map(str::parse::<u32>);
+    match (parts.next(), parts.next()) {
+        (Some(Ok(width)), Some(Ok(height))) if width != 0 && height != 0 => Ok((width, height)),
+        _ => Err(usage(format!(
+            "expected a size like 1024x768, got {:?}",
+            value
+        ))),
+    }
+}
+
+fn parse_samples(value: &str) -> Result<vk::SampleCountFlags> {
+    let samples = parse_number(value)?;
+    // The render pass always resolves, which needs a multisampled color attachment.
+    if !samples.is_power_of_two() || !(2..=64).contains(&samples) {
+        return Err(usage(format!(
+            "samples must be a power of two from 2 to 64, got {}",
+            samples
+        )));
+    }
+    Ok(vk::SampleCountFlags::from_raw(samples))
+}
+
+fn parse_present_mode(value: &str) -> Result<vk::PresentModeKHR> {
+    match value {
+        "fifo" => Ok(vk::PresentModeKHR::FIFO),
+        "mailbox" => Ok(vk::PresentModeKHR::MAILBOX),
+        "immediate" => Ok(vk::PresentModeKHR::IMMEDIATE),
+        "fifo-relaxed" => Ok(vk::PresentModeKHR::FIFO_RELAXED),
+        _ => Err(usage(format!("unknown present mode {:?}", value))),
+    }
+}
+
+fn parse_color_space(value: &str) -> Result<ColorSpace> {
+    match value {
+        "srgb" => Ok(ColorSpace::Srgb),
+        "hdr10" => Ok(ColorSpace::Hdr10),
+        "scrgb" => Ok(ColorSpace::ScRgb),
+        _ => Err(usage(format!("unknown color space {:?}", value))),
+    }
+}
+
+fn parse_list<T>(value: &str, parse: impl Fn(&str) -> Result<T>) -> Result<Vec<T>> {
+    value.split(',').map(parse).collect()
+}
@@ -1,163 +1,940 @@
-use crate::{device::*, globals::*};
+use std::path::Path;
+use std::sync::Arc;
+
+use crate::{context::*, device::*};
+
+/// How many frames may be recorded or executing at once.
+pub const FRAMES_IN_FLIGHT: usize = 2;
 
 pub struct Renderer {
-    pub surface: vk::SurfaceKHR,
+    pub context: Arc<Context>,
     pub render_pass: Owned<vk::RenderPass>,
     pub size: (u32, u32),
     pub color_format: vk::Format,
+    pub color_space: ColorSpace,
     pub samples: vk::SampleCountFlags,
-    pub image_acquire_semaphore: Semaphore,
-    swapchain: Swapchain,
+    frames: Vec<Frame>,
+    frame_index: usize,
+    target: RenderTarget,
+    readback_buffer: Option<Buffer>,
+    capture_next_frame: bool,
+}
+
+/// The resources of one frame in flight, reused once its previous submission has completed.
+struct Frame {
+    // Taken while recording. Declared before the pool it's freed to.
+    command_buffer: Option<CommandBuffer>,
+    command_pool: CommandPool,
+    /// Signalled when the frame's submission has completed.
+    fence: Fence,
+    image_available: Semaphore,
+    render_finished: Semaphore,
+}
+
+impl Frame {
+    fn create(context: &Arc<Context>) -> VkResult<Self> {
+        let command_pool = CommandPool::create(context, QueueKind::Graphics)?;
+        Ok(Self {
+            command_buffer: Some(command_pool.allocate()?),
+            command_pool,
+            fence: Fence::create_signalled(context)?,
+            image_available: Semaphore::create(context)?,
+            render_finished: Semaphore::create(context)?,
+        })
+    }
+}
+
+fn create_frames(context: &Arc<Context>) -> VkResult<Vec<Frame>> {
+    (0..FRAMES_IN_FLIGHT)
+        .map(|_| Frame::create(context))
+        .collect()
+}
+
+enum RenderTarget {
+    Swapchain {
+        surface: vk::SurfaceKHR,
+        surface_format: vk::SurfaceFormatKHR,
+        present_mode: vk::PresentModeKHR,
+        swapchain: Swapchain,
+        /// Set on resize or when the surface reports the swapchain out of date or suboptimal,
+        /// to recreate it before the next frame.
+        out_of_date: bool,
+    },
+    Offscreen(Offscreen),
+}
+
+/// The color space of the presented images, selected with `--color-space`.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum ColorSpace {
+    /// 8-bit sRGB encoded, which every surface supports.
+    Srgb,
+    /// 10-bit BT.2020 with the ST 2084 (PQ) transfer function. Shaders must encode their output,
+    /// and are compiled with `OUTPUT_HDR10` defined.
+    Hdr10,
+    /// 16-bit float linear sRGB, where values above 1 are brighter than SDR white.
+    /// Shaders are compiled with `OUTPUT_SCRGB` defined.
+    ScRgb,
 }
 
-fn get_format_and_samples(_surface: vk::SurfaceKHR) -> Result<(vk::Format, vk::SampleCountFlags)> {
+impl ColorSpace {
+    /// The surface color space and the formats that can present it, best first.
+    fn surface_formats(self) -> (vk::ColorSpaceKHR, &'static [vk::Format]) {
+        match self {
+            Self::Srgb => (
+                vk::ColorSpaceKHR::SRGB_NONLINEAR,
+                &[vk::Format::B8G8R8A8_SRGB, vk::Format::R8G8B8A8_SRGB],
+            ),
+            Self::Hdr10 => (
+                vk::ColorSpaceKHR::HDR10_ST2084_EXT,
+                &[
+                    vk::Format::A2B10G10R10_UNORM_PACK32,
+                    vk::Format::A2R10G10B10_UNORM_PACK32,
+                ],
+            ),
+            Self::ScRgb => (
+                vk::ColorSpaceKHR::EXTENDED_SRGB_LINEAR_EXT,
+                &[vk::Format::R16G16B16A16_SFLOAT],
+            ),
+        }
+    }
+
+    /// The macro shaders are compiled with, if any.
+    pub fn shader_macro(self) -> Option<&'static str> {
+        match self {
+            Self::Srgb => None,
+            Self::Hdr10 => Some("OUTPUT_HDR10"),
+            Self::ScRgb => Some("OUTPUT_SCRGB"),
+        }
+    }
+}
+
+/// Returns the first of the `preferred` color spaces the surface supports, falling back to sRGB.
+fn get_surface_format(
+    context: &Context,
+    surface: vk::SurfaceKHR,
+    preferred: &[ColorSpace],
+) -> Result<(ColorSpace, vk::SurfaceFormatKHR)> {
+    let supported = unsafe {
+        context
+            .surface_loader()?
+            .get_physical_device_surface_formats(context.physical_device, surface)?
+    };
+    for &color_space in preferred.iter().chain(&[ColorSpace::Srgb]) {
+        let (surface_color_space, formats) = color_space.surface_formats();
+        let format = formats.iter().find(|&&format| {
+            supported
+                .iter()
+                .any(|s| s.format == format && s.color_space == surface_color_space)
+        });
+        match format {
+            Some(&format) => {
+                return Ok((
+                    color_space,
+                    vk::SurfaceFormatKHR {
+                        format,
+                        color_space: surface_color_space,
+                    },
+                ))
+            }
+            None => println!("color space {:?} not supported", color_space),
+        }
+    }
+    eprintln!("supported surface formats: {:?}", supported);
+    Err(Error::VkSupport)
+}
+
+/// Returns `samples` if the format supports it, or the highest supported sample count if `None`.
+/// The render pass resolves the color attachment, so it must have more than one sample.
+fn get_samples(
+    context: &Context,
+    format: vk::Format,
+    samples: Option<vk::SampleCountFlags>,
+) -> Result<vk::SampleCountFlags> {
     unsafe {
-        // let surface_formats =
-        //     ext::SURFACE.get_physical_device_surface_formats(PHYSICAL_DEVICE, surface)?;
-        //
-        let format = vk::Format::B8G8R8A8_SRGB;
-
-        let props = INSTANCE.get_physical_device_image_format_properties(
-            PHYSICAL_DEVICE,
-            format,
-            vk::ImageType::TYPE_2D,
-            vk::ImageTiling::OPTIMAL,
-            vk::ImageUsageFlags::COLOR_ATTACHMENT,
-            vk::ImageCreateFlags::empty(),
-        )?;
+        let props = context
+            .instance
+            .instance
+            .get_physical_device_image_format_properties(
+                context.physical_device,
+                format,
+                vk::ImageType::TYPE_2D,
+                vk::ImageTiling::OPTIMAL,
+                vk::ImageUsageFlags::COLOR_ATTACHMENT,
+                vk::ImageCreateFlags::empty(),
+            )?;
 
-        let sample_count_flags =
-            vk::SampleCountFlags::from_raw(props.sample_counts.as_raw().next_power_of_two() >> 1);
-        return Ok((format, sample_count_flags));
+        match samples {
+            Some(samples) if props.sample_counts.contains(samples) => Ok(samples),
+            Some(samples) => {
+                eprintln!(
+                    "{:?} samples not supported, supported: {:?}",
+                    samples, props.sample_counts,
+                );
+                Err(Error::VkSupport)
+            }
+            None => match props.sample_counts.as_raw() {
+                counts if counts >= 2 => Ok(vk::SampleCountFlags::from_raw(
+                    1 << (31 - counts.leading_zeros()),
+                )),
+                _ => {
+                    eprintln!("multisampling not supported");
+                    Err(Error::VkSupport)
+                }
+            },
+        }
     }
-    // Err(Error::VkSupport)
 }
 
-impl Renderer {
-    pub fn create(surface: vk::SurfaceKHR, size: (u32, u32)) -> Result<Self> {
-        unsafe {
-            let (color_format, samples) = get_format_and_samples(surface)?;
-            println!("surface format: {:?}, samples: {:?}", color_format, samples);
+/// Returns the first of the `preferred` present modes the surface supports, falling back to FIFO.
+fn get_present_mode(
+    context: &Context,
+    surface: vk::SurfaceKHR,
+    preferred: &[vk::PresentModeKHR],
+) -> VkResult<vk::PresentModeKHR> {
+    let present_modes = unsafe {
+        context
+            .surface_loader()?
+            .get_physical_device_surface_present_modes(context.physical_device, surface)?
+    };
+    for &present_mode in preferred {
+        if present_modes.contains(&present_mode) {
+            return Ok(present_mode);
+        }
+        println!("present mode {:?} not supported", present_mode);
+    }
+    // FIFO is required to be supported.
+    Ok(vk::PresentModeKHR::FIFO)
+}
 
-            let render_pass = Owned::<vk::RenderPass>::create(
-                &vk::RenderPassCreateInfo::builder()
-                    .attachments(&[
-                        vk::AttachmentDescription::builder()
-                            .format(color_format)
-                            .samples(samples)
-                            .load_op(vk::AttachmentLoadOp::CLEAR)
-                            .store_op(vk::AttachmentStoreOp::STORE)
-                            .initial_layout(vk::ImageLayout::UNDEFINED)
-                            .final_layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL)
-                            .build(),
-                        vk::AttachmentDescription::builder()
-                            .format(vk::Format::D32_SFLOAT)
-                            .samples(samples)
-                            .load_op(vk::AttachmentLoadOp::CLEAR)
-                            .store_op(vk::AttachmentStoreOp::DONT_CARE)
-                            .initial_layout(vk::ImageLayout::UNDEFINED)
-                            .final_layout(vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL)
-                            .build(),
-                        vk::AttachmentDescription::builder()
-                            .format(color_format)
-                            .samples(vk::SampleCountFlags::TYPE_1)
-                            .load_op(vk::AttachmentLoadOp::DONT_CARE)
-                            .store_op(vk::AttachmentStoreOp::STORE)
-                            .initial_layout(vk::ImageLayout::UNDEFINED)
-                            .final_layout(vk::ImageLayout::PRESENT_SRC_KHR)
+/// Returns the usage of the swapchain images, which must be transfer sources to `capture` frames.
+fn get_image_usage(
+    context: &Context,
+    surface: vk::SurfaceKHR,
+    capture: bool,
+) -> Result<vk::ImageUsageFlags> {
+    let supported = unsafe {
+        context
+            .surface_loader()?
+            .get_physical_device_surface_capabilities(context.physical_device, surface)?
+    }
+    .supported_usage_flags;
+    let mut usage = vk::ImageUsageFlags::COLOR_ATTACHMENT;
+    if capture {
+        usage |= vk::ImageUsageFlags::TRANSFER_SRC;
+    }
+    if !supported.contains(usage) {
+        eprintln!(
+            "swapchain image usage {:?} not supported, supported: {:?}",
+            usage, supported
+        );
+        return Err(Error::VkSupport);
+    }
+    Ok(usage)
+}
+
+/// Returns the size the swapchain must have, or `size` clamped to the allowed extents if the
+/// surface size is determined by the swapchain.
+fn get_surface_size(
+    context: &Context,
+    surface: vk::SurfaceKHR,
+    size: (u32, u32),
+) -> VkResult<(u32, u32)> {
+    let capabilities = unsafe {
+        context
+            .surface_loader()?
+            .get_physical_device_surface_capabilities(context.physical_device, surface)?
+    };
+    Ok(match capabilities.current_extent {
+        vk::Extent2D {
+            width: u32::MAX,
+            height: u32::MAX,
+        } => {
+            let (min, max) = (capabilities.min_image_extent, capabilities.max_image_extent);
+            (
+                size.0.clamp(min.width, max.width),
+                size.1.clamp(min.height, max.height),
+            )
+        }
+        vk::Extent2D { width, height } => (width, height),
+    })
+}
+
+fn create_render_pass(
+    context: &Arc<Context>,
+    color_format: vk::Format,
+    samples: vk::SampleCountFlags,
+    resolve_final_layout: vk::ImageLayout,
+) -> VkResult<Owned<vk::RenderPass>> {
+    unsafe {
+        Owned::<vk::RenderPass>::create(
+            context,
+            &vk::RenderPassCreateInfo::builder()
+                .attachments(&[
+                    vk::AttachmentDescription::builder()
+                        .format(color_format)
+                        .samples(samples)
+                        .load_op(vk::AttachmentLoadOp::CLEAR)
+                        .store_op(vk::AttachmentStoreOp::STORE)
+                        .initial_layout(vk::ImageLayout::UNDEFINED)
+                        .final_layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL)
+                        .build(),
+                    vk::AttachmentDescription::builder()
+                        .format(vk::Format::D32_SFLOAT)
+                        .samples(samples)
+                        .load_op(vk::AttachmentLoadOp::CLEAR)
+                        .store_op(vk::AttachmentStoreOp::DONT_CARE)
+                        .initial_layout(vk::ImageLayout::UNDEFINED)
+                        .final_layout(vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL)
+                        .build(),
+                    vk::AttachmentDescription::builder()
+                        .format(color_format)
+                        .samples(vk::SampleCountFlags::TYPE_1)
+                        .load_op(vk::AttachmentLoadOp::DONT_CARE)
+                        .store_op(vk::AttachmentStoreOp::STORE)
+                        .initial_layout(vk::ImageLayout::UNDEFINED)
+                        .final_layout(resolve_final_layout)
+                        .build(),
+                ])
+                .subpasses(&[vk::SubpassDescription::builder()
+                    .pipeline_bind_point(vk::PipelineBindPoint::GRAPHICS)
+                    .color_attachments(&[vk::AttachmentReference::builder()
+                        .attachment(0)
+                        .layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL)
+                        .build()])
+                    .depth_stencil_attachment(
+                        &vk::AttachmentReference::builder()
+                            .attachment(1)
+                            .layout(vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL)
                             .build(),
-                    ])
-                    .subpasses(&[vk::SubpassDescription::builder()
-                        .pipeline_bind_point(vk::PipelineBindPoint::GRAPHICS)
-                        .color_attachments(&[vk::AttachmentReference::builder()
-                            .attachment(0)
-                            .layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL)
-                            .build()])
-                        .depth_stencil_attachment(
-                            &vk::AttachmentReference::builder()
-                                .attachment(1)
-                                .layout(vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL)
-                                .build(),
-                        )
-                        .resolve_attachments(&[vk::AttachmentReference::builder()
-                            .attachment(2)
-                            .layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL)
-                            .build()])
+                    )
+                    .resolve_attachments(&[vk::AttachmentReference::builder()
+                        .attachment(2)
+                        .layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL)
                         .build()])
-                    .build(),
-            )?;
+                    .build()])
+                // The attachments are shared by all frames in flight, so wait for the previous
+                // frame to finish writing them, and reading the resolve image for readback.
+                .dependencies(&[vk::SubpassDependency::builder()
+                    .src_subpass(vk::SUBPASS_EXTERNAL)
+                    .dst_subpass(0)
+                    .src_stage_mask(
+                        vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT
+                            | vk::PipelineStageFlags::LATE_FRAGMENT_TESTS
+                            | vk::PipelineStageFlags::TRANSFER,
+                    )
+                    .dst_stage_mask(
+                        vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT
+                            | vk::PipelineStageFlags::EARLY_FRAGMENT_TESTS,
+                    )
+                    .src_access_mask(
+                        vk::AccessFlags::COLOR_ATTACHMENT_WRITE
+                            | vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE,
+                    )
+                    .dst_access_mask(
+                        vk::AccessFlags::COLOR_ATTACHMENT_WRITE
+                            | vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_READ
+                            | vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE,
+                    )
+                    .build()])
+                .build(),
+        )
+    }
+}
 
-            let image_acquire_semaphore = Semaphore::create()?;
+impl Renderer {
+    /// Creates a renderer presenting to `surface`, using the first supported of the preferred
+    /// color spaces and present modes. Frames can only be captured if `capture` is set.
+    pub fn create(
+        context: &Arc<Context>,
+        surface: vk::SurfaceKHR,
+        size: (u32, u32),
+        samples: Option<vk::SampleCountFlags>,
+        present_modes: &[vk::PresentModeKHR],
+        color_spaces: &[ColorSpace],
+        capture: bool,
+    ) -> Result<Self> {
+        let (color_space, surface_format) = get_surface_format(context, surface, color_spaces)?;
+        let color_format = surface_format.format;
+        let samples = get_samples(context, color_format, samples)?;
+        let present_mode = get_present_mode(context, surface, present_modes)?;
+        let image_usage = get_image_usage(context, surface, capture)?;
+        let size = get_surface_size(context, surface, size)?;
+        println!(
+            "surface format: {:?}, color space: {:?}, present mode: {:?}, samples: {:?}",
+            color_format, surface_format.color_space, present_mode, samples
+        );
 
-            let swapchain = Swapchain::create(
-                vk::SwapchainKHR::null(),
-                surface,
-                render_pass.as_raw(),
-                size,
-                color_format,
-                samples,
-            )?;
+        let render_pass = create_render_pass(
+            context,
+            color_format,
+            samples,
+            vk::ImageLayout::PRESENT_SRC_KHR,
+        )?;
 
-            Ok(Self {
+        let swapchain = Swapchain::create(
+            context,
+            vk::SwapchainKHR::null(),
+            surface,
+            render_pass.as_raw(),
+            size,
+            surface_format,
+            samples,
+            present_mode,
+            image_usage,
+        )?;
+
+        Ok(Self {
+            context: context.clone(),
+            render_pass,
+            size,
+            color_format,
+            color_space,
+            samples,
+            frames: create_frames(context)?,
+            frame_index: 0,
+            target: RenderTarget::Swapchain {
                 surface,
-                render_pass,
-                size,
-                color_format,
-                samples,
-                image_acquire_semaphore,
+                surface_format,
+                present_mode,
                 swapchain,
-            })
-        }
+                out_of_date: false,
+            },
+            readback_buffer: None,
+            capture_next_frame: false,
+        })
+    }
+
+    /// Creates a renderer that draws to an offscreen image instead of a window.
+    pub fn create_offscreen(
+        context: &Arc<Context>,
+        size: (u32, u32),
+        samples: Option<vk::SampleCountFlags>,
+    ) -> Result<Self> {
+        let color_format = vk::Format::R8G8B8A8_SRGB;
+        let samples = get_samples(context, color_format, samples)?;
+        println!(
+            "offscreen format: {:?}, samples: {:?}",
+            color_format, samples
+        );
+
+        let render_pass = create_render_pass(
+            context,
+            color_format,
+            samples,
+            vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+        )?;
+
+        let offscreen =
+            Offscreen::create(context, render_pass.as_raw(), size, color_format, samples)?;
+
+        Ok(Self {
+            context: context.clone(),
+            render_pass,
+            size,
+            color_format,
+            color_space: ColorSpace::Srgb,
+            samples,
+            frames: create_frames(context)?,
+            frame_index: 0,
+            target: RenderTarget::Offscreen(offscreen),
+            readback_buffer: None,
+            capture_next_frame: false,
+        })
     }
 
     pub fn resize(&mut self, size: (u32, u32)) -> VkResult<()> {
         if self.size != size {
-            self.swapchain.update(
-                self.surface,
-                self.render_pass.as_raw(),
-                size,
-                self.color_format,
-                self.samples,
-            )?;
+            match &mut self.target {
+                // Recreated before the next frame, as resizes often come in bursts.
+                RenderTarget::Swapchain { out_of_date, .. } => *out_of_date = true,
+                RenderTarget::Offscreen(offscreen) => {
+                    // The framebuffer and readback buffer may still be in use.
+                    self.context.wait_idle()?;
+                    *offscreen = Offscreen::create(
+                        &self.context,
+                        self.render_pass.as_raw(),
+                        size,
+                        self.color_format,
+                        self.samples,
+                    )?;
+                    self.readback_buffer = None;
+                }
+            }
             self.size = size;
         }
         Ok(())
     }
 
-    pub fn acquire_image(&mut self) -> VkResult<SwapchainItem> {
-        self.swapchain.next(
-            u64::max_value(),
-            self.image_acquire_semaphore.as_raw(),
-            vk::Fence::null(),
-        )
+    /// Copies the next submitted frame to host memory, to be read with [`Renderer::read_pixels`].
+    pub fn capture_next_frame(&mut self) {
+        if let RenderTarget::Swapchain { swapchain, .. } = &self.target {
+            assert!(
+                swapchain
+                    .image_usage
+                    .contains(vk::ImageUsageFlags::TRANSFER_SRC),
+                "the renderer wasn't created to capture frames"
+            );
+        }
+        self.capture_next_frame = true;
     }
 
-    pub fn present(&mut self, index: u32) -> VkResult<()> {
-        unsafe {
-            let mut swapchain_results = [vk::Result::SUCCESS];
-            let _suboptimal = ext::SWAPCHAIN.queue_present(
-                GRAPHICS_QUEUE,
+    /// Recreates the swapchain at the surface's current size if it's out of date.
+    /// Returns `false` if there's nothing to render to, e.g. while the window is minimized.
+    fn update_swapchain(&mut self) -> VkResult<bool> {
+        let (surface, surface_format, present_mode, swapchain, out_of_date) = match &mut self.target
+        {
+            RenderTarget::Swapchain {
+                surface,
+                surface_format,
+                present_mode,
+                swapchain,
+                out_of_date: out_of_date @ true,
+            } => (
+                *surface,
+                *surface_format,
+                *present_mode,
+                swapchain,
+                out_of_date,
+            ),
+            _ => return Ok(true),
+        };
+
+        let size = get_surface_size(&self.context, surface, self.size)?;
+        if size.0 == 0 || size.1 == 0 {
+            return Ok(false);
+        }
+
+        // The framebuffers and readback buffer may still be in use.
+        self.context.wait_idle()?;
+        swapchain.update(
+            surface,
+            self.render_pass.as_raw(),
+            size,
+            surface_format,
+            self.samples,
+            present_mode,
+        )?;
+        *out_of_date = false;
+        // A failed present may leave its wait semaphore signalled.
+        for frame in &mut self.frames {
+            frame.image_available = Semaphore::create(&self.context)?;
+            frame.render_finished = Semaphore::create(&self.context)?;
+        }
+        self.size = size;
+        self.readback_buffer = None;
+        Ok(true)
+    }
+
+    /// The index of the frame in flight being recorded, for selecting per-frame resources.
+    pub fn frame_index(&self) -> usize {
+        self.frame_index
+    }
+
+    /// Waits until the current frame's previous submission has completed, then acquires an image
+    /// and begins recording the frame's command buffer.
+    /// Returns `None` if there's nothing to render to, e.g. while the window is minimized.
+    pub fn begin_frame(&mut self) -> VkResult<Option<(SwapchainItem, CommandBufferRecorder)>> {
+        self.frames[self.frame_index].fence.wait()?;
+        let item = loop {
+            if !self.update_swapchain()? {
+                return Ok(None);
+            }
+            let frame = &self.frames[self.frame_index];
+            match &mut self.target {
+                RenderTarget::Swapchain {
+                    swapchain,
+                    out_of_date,
+                    ..
+                } => match swapchain.next(
+                    u64::MAX,
+                    frame.image_available.as_raw(),
+                    vk::Fence::null(),
+                ) {
+                    // A suboptimal image can still be presented, so recreate after this frame.
+                    Ok((item, suboptimal)) => {
+                        *out_of_date = suboptimal;
+                        break item;
+                    }
+                    Err(vk::Result::ERROR_OUT_OF_DATE_KHR) => *out_of_date = true,
+                    Err(err) => return Err(err),
+                },
+                RenderTarget::Offscreen(offscreen) => break offscreen.item(),
+            }
+        };
+        let frame = &mut self.frames[self.frame_index];
+        frame.command_pool.reset()?;
+        let recorder = frame
+            .command_buffer
+            .take()
+            .expect("frame is already being recorded")
+            .begin()?;
+        Ok(Some((item, recorder)))
+    }
+
+    /// Submits the recorded frame, then presents it if we have a swapchain.
+    pub fn submit(
+        &mut self,
+        recorder: CommandBufferRecorder,
+        item: &SwapchainItem,
+    ) -> VkResult<()> {
+        if self.capture_next_frame {
+            if self.readback_buffer.is_none() {
+                let (width, height) = self.size;
+                self.readback_buffer = Some(Buffer::create(
+                    &self.context,
+                    width as vk::DeviceSize
+                        * height as vk::DeviceSize
+                        * bytes_per_pixel(self.color_format),
+                    vk::BufferUsageFlags::TRANSFER_DST,
+                )?);
+            }
+            let readback_buffer = self.readback_buffer.as_ref().unwrap();
+            let layout = match &self.target {
+                RenderTarget::Swapchain { .. } => vk::ImageLayout::PRESENT_SRC_KHR,
+                RenderTarget::Offscreen(_) => vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+            };
+            record_readback(
+                &recorder,
+                item.image,
+                layout,
+                readback_buffer.as_raw(),
+                self.size,
+            );
+            self.capture_next_frame = false;
+        }
+
+        let presenting = matches!(self.target, RenderTarget::Swapchain { .. });
+        let frame = &mut self.frames[self.frame_index];
+        self.frame_index = (self.frame_index + 1) % FRAMES_IN_FLIGHT;
+        let command_buffer = recorder.end()?;
+        // Only reset once it's about to be signalled, or the next wait on it would never return.
+        let result = frame.fence.reset().and_then(|()| {
+            if presenting {
+                command_buffer.submit_signalling(
+                    &[frame.image_available.as_raw()],
+                    &[vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT],
+                    &[frame.render_finished.as_raw()],
+                    frame.fence.as_raw(),
+                )
+            } else {
+                command_buffer.submit_signalling(&[], &[], &[], frame.fence.as_raw())
+            }
+        });
+        frame.command_buffer = Some(command_buffer);
+        if let Err(err) = result {
+            // Nothing will signal the reset fence, so replace it for the next wait on this frame.
+            frame.fence = Fence::create_signalled(&self.context)?;
+            return Err(err);
+        }
+
+        let render_finished = frame.render_finished.as_raw();
+        self.present(item.index, render_finished)
+    }
+
+    fn present(&mut self, index: u32, wait_semaphore: vk::Semaphore) -> VkResult<()> {
+        let (swapchain, out_of_date) = match &mut self.target {
+            RenderTarget::Swapchain {
+                swapchain,
+                out_of_date,
+                ..
+            } => (swapchain, out_of_date),
+            RenderTarget::Offscreen(_) => return Ok(()),
+        };
+        let result = unsafe {
+            self.context.swapchain_loader()?.queue_present(
+                *self.context.graphics_queue.lock(),
                 &vk::PresentInfoKHR::builder()
-                    .swapchains(&[self.swapchain.swapchain.as_raw()])
+                    .wait_semaphores(&[wait_semaphore])
+                    .swapchains(&[swapchain.swapchain.as_raw()])
                     .image_indices(&[index])
-                    .results(&mut swapchain_results)
                     .build(),
-            )?;
-            if swapchain_results[0] != vk::Result::SUCCESS {
-                return Err(swapchain_results[0]);
+            )
+        };
+        match result {
+            Ok(false) => Ok(()),
+            Ok(true) | Err(vk::Result::ERROR_OUT_OF_DATE_KHR) => {
+                *out_of_date = true;
+                Ok(())
             }
-            Ok(())
+            Err(err) => Err(err),
+        }
+    }
+
+    /// Returns the tightly packed RGBA pixels of the last captured frame.
+    pub fn read_pixels(&self) -> Result<Vec<u8>> {
+        let readback_buffer = self.readback_buffer.as_ref().ok_or(Error::VkSupport)?;
+        // PNGs are only saved from 8-bit formats for now.
+        if !matches!(
+            self.color_format,
+            vk::Format::R8G8B8A8_SRGB
+                | vk::Format::R8G8B8A8_UNORM
+                | vk::Format::B8G8R8A8_SRGB
+                | vk::Format::B8G8R8A8_UNORM
+        ) {
+            eprintln!("can't read back {:?} pixels", self.color_format);
+            return Err(Error::VkSupport);
         }
+        // The captured frame may still be in flight.
+        self.context.wait_idle()?;
+        let (width, height) = self.size;
+        let size = width as usize * height as usize * 4;
+        let mut pixels = readback_buffer.memory.read_vec::<u8>(0, size)?;
+        if matches!(
+            self.color_format,
+            vk::Format::B8G8R8A8_SRGB | vk::Format::B8G8R8A8_UNORM
+        ) {
+            for pixel in pixels.chunks_exact_mut(4) {
+                pixel.swap(0, 2);
+            }
+        }
+        Ok(pixels)
+    }
+
+    pub fn save_png(&self, path: &Path) -> Result<()> {
+        let pixels = self.read_pixels()?;
+        let (width, height) = self.size;
+        let file = std::io::BufWriter::new(std::fs::File::create(path)?);
+        let mut encoder = png::Encoder::new(file, width, height);
+        encoder.set_color(png::ColorType::RGBA);
+        encoder.set_depth(png::BitDepth::Eight);
+        encoder.write_header()?.write_image_data(&pixels)?;
+        Ok(())
     }
 }
 
+impl Drop for Renderer {
+    fn drop(&mut self) {
+        // Frames in flight may still use the frame resources, and whatever is dropped after us.
+        let _ = self.context.wait_idle();
+    }
+}
+
+fn bytes_per_pixel(format: vk::Format) -> vk::DeviceSize {
+    match format {
+        vk::Format::R16G16B16A16_SFLOAT => 8,
+        _ => 4,
+    }
+}
+
+/// Records a copy of `image` into `buffer`, which is left in `layout` after the render pass.
+fn record_readback(
+    recorder: &CommandBufferRecorder,
+    image: vk::Image,
+    layout: vk::ImageLayout,
+    buffer: vk::Buffer,
+    (width, height): (u32, u32),
+) {
+    let subresource_range = vk::ImageSubresourceRange::builder()
+        .aspect_mask(vk::ImageAspectFlags::COLOR)
+        .layer_count(1)
+        .level_count(1)
+        .build();
+
+    // Even if the image is already in TRANSFER_SRC_OPTIMAL,
+    // we still need to wait for the resolve to be written.
+    recorder.image_transition(
+        vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+        vk::PipelineStageFlags::TRANSFER,
+        &[vk::ImageMemoryBarrier::builder()
+            .image(image)
+            .src_access_mask(vk::AccessFlags::COLOR_ATTACHMENT_WRITE)
+            .dst_access_mask(vk::AccessFlags::TRANSFER_READ)
+            .old_layout(layout)
+            .new_layout(vk::ImageLayout::TRANSFER_SRC_OPTIMAL)
+            .subresource_range(subresource_range)
+            .build()],
+    );
+    recorder.copy_image_to_buffer(
+        image,
+        vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+        buffer,
+        &[vk::BufferImageCopy::builder()
+            .image_subresource(
+                vk::ImageSubresourceLayers::builder()
+                    .aspect_mask(vk::ImageAspectFlags::COLOR)
+                    .layer_count(1)
+                    .mip_level(0)
+                    .build(),
+            )
+            .image_extent(vk::Extent3D {
+                width,
+                height,
+                depth: 1,
+            })
+            .build()],
+    );
+    if layout != vk::ImageLayout::TRANSFER_SRC_OPTIMAL {
+        recorder.image_transition(
+            vk::PipelineStageFlags::TRANSFER,
+            vk::PipelineStageFlags::BOTTOM_OF_PIPE,
+            &[vk::ImageMemoryBarrier::builder()
+                .image(image)
+                .src_access_mask(vk::AccessFlags::TRANSFER_READ)
+                .dst_access_mask(vk::AccessFlags::empty())
+                .old_layout(vk::ImageLayout::TRANSFER_SRC_OPTIMAL)
+                .new_layout(layout)
+                .subresource_range(subresource_range)
+                .build()],
+        );
+    }
+    recorder.buffer_barrier(
+        vk::PipelineStageFlags::TRANSFER,
+        vk::PipelineStageFlags::HOST,
+        &[vk::BufferMemoryBarrier::builder()
+            .buffer(buffer)
+            .src_access_mask(vk::AccessFlags::TRANSFER_WRITE)
+            .dst_access_mask(vk::AccessFlags::HOST_READ)
+            .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .size(vk::WHOLE_SIZE)
+            .build()],
+    );
+}
+
+/// The multisampled color and depth attachments rendered to before resolving.
 #[allow(dead_code)]
-pub struct Swapchain {
-    swapchain: Owned<vk::SwapchainKHR>,
+struct Attachments {
     depth_image: Image,
     depth_image_view: ImageView,
     color_image: Image,
     color_image_view: ImageView,
+}
+
+impl Attachments {
+    fn create(
+        context: &Arc<Context>,
+        size: (u32, u32),
+        color_format: vk::Format,
+        samples: vk::SampleCountFlags,
+    ) -> VkResult<Self> {
+        let depth_image = Image::create_2d(
+            context,
+            size,
+            1,
+            vk::Format::D32_SFLOAT,
+            samples,
+            vk::ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT,
+            MemoryTypeMask::any(),
+        )?;
+        let depth_image_view = ImageView::create_2d(
+            context,
+            depth_image.object.as_raw(),
+            vk::Format::D32_SFLOAT,
+            vk::ImageAspectFlags::DEPTH,
+        )?;
+
+        let color_image = Image::create_2d(
+            context,
+            size,
+            1,
+            color_format,
+            samples,
+            vk::ImageUsageFlags::COLOR_ATTACHMENT,
+            MemoryTypeMask::any(),
+        )?;
+        let color_image_view = ImageView::create_2d(
+            context,
+            color_image.object.as_raw(),
+            color_format,
+            vk::ImageAspectFlags::COLOR,
+        )?;
+
+        Ok(Self {
+            depth_image,
+            depth_image_view,
+            color_image,
+            color_image_view,
+        })
+    }
+
+    fn create_framebuffer(
+        &self,
+        render_pass: vk::RenderPass,
+        resolve_image_view: vk::ImageView,
+        (width, height): (u32, u32),
+    ) -> VkResult<Owned<vk::Framebuffer>> {
+        unsafe {
+            Owned::create(
+                self.depth_image.object.context(),
+                &vk::FramebufferCreateInfo::builder()
+                    .render_pass(render_pass)
+                    .attachments(&[
+                        self.color_image_view.as_raw(),
+                        self.depth_image_view.as_raw(),
+                        resolve_image_view,
+                    ])
+                    .width(width)
+                    .height(height)
+                    .layers(1)
+                    .build(),
+            )
+        }
+    }
+}
+
+#[allow(dead_code)]
+pub struct Offscreen {
+    attachments: Attachments,
+    resolve_image: Image,
+    resolve_image_view: ImageView,
+    framebuffer: Owned<vk::Framebuffer>,
+}
+
+impl Offscreen {
+    pub fn create(
+        context: &Arc<Context>,
+        render_pass: vk::RenderPass,
+        (width, height): (u32, u32),
+        color_format: vk::Format,
+        samples: vk::SampleCountFlags,
+    ) -> VkResult<Self> {
+        let attachments = Attachments::create(context, (width, height), color_format, samples)?;
+
+        let resolve_image = Image::create_2d(
+            context,
+            (width, height),
+            1,
+            color_format,
+            vk::SampleCountFlags::TYPE_1,
+            vk::ImageUsageFlags::COLOR_ATTACHMENT | vk::ImageUsageFlags::TRANSFER_SRC,
+            MemoryTypeMask::any(),
+        )?;
+        let resolve_image_view = ImageView::create_2d(
+            context,
+            resolve_image.object.as_raw(),
+            color_format,
+            vk::ImageAspectFlags::COLOR,
+        )?;
+
+        let framebuffer = attachments.create_framebuffer(
+            render_pass,
+            resolve_image_view.as_raw(),
+            (width, height),
+        )?;
+
+        Ok(Self {
+            attachments,
+            resolve_image,
+            resolve_image_view,
+            framebuffer,
+        })
+    }
+
+    fn item(&self) -> SwapchainItem {
+        SwapchainItem {
+            index: 0,
+            image: self.resolve_image.object.as_raw(),
+            image_view: self.resolve_image_view.as_raw(),
+            framebuffer: self.framebuffer.as_raw(),
+        }
+    }
+}
+
+#[allow(dead_code)]
+pub struct Swapchain {
+    swapchain: Owned<vk::SwapchainKHR>,
+    image_usage: vk::ImageUsageFlags,
+    attachments: Attachments,
     images: Vec<vk::Image>,
     image_views: Vec<ImageView>,
     framebuffers: Vec<Owned<vk::Framebuffer>>,
@@ -171,96 +948,95 @@ pub struct SwapchainItem {
 }
 
 impl Swapchain {
+    #[allow(clippy::too_many_arguments)]
     pub fn create(
+        context: &Arc<Context>,
         old_swapchain: vk::SwapchainKHR,
         surface: vk::SurfaceKHR,
         render_pass: vk::RenderPass,
         (width, height): (u32, u32),
-        color_format: vk::Format,
+        surface_format: vk::SurfaceFormatKHR,
         samples: vk::SampleCountFlags,
+        present_mode: vk::PresentModeKHR,
+        image_usage: vk::ImageUsageFlags,
     ) -> VkResult<Self> {
+        let color_format = surface_format.format;
         unsafe {
+            let capabilities = context
+                .surface_loader()?
+                .get_physical_device_surface_capabilities(context.physical_device, surface)?;
+            // One more than the minimum, so we don't have to wait for the presentation engine
+            // to release an image before acquiring the next. A maximum of 0 means unlimited.
+            let mut min_image_count = capabilities.min_image_count + 1;
+            if capabilities.max_image_count != 0 {
+                min_image_count = min_image_count.min(capabilities.max_image_count);
+            }
+            // We don't rotate our rendering, so let the presentation engine do it if needed.
+            let pre_transform = if capabilities
+                .supported_transforms
+                .contains(vk::SurfaceTransformFlagsKHR::IDENTITY)
+            {
+                vk::SurfaceTransformFlagsKHR::IDENTITY
+            } else {
+                capabilities.current_transform
+            };
+            let composite_alpha = [
+                vk::CompositeAlphaFlagsKHR::OPAQUE,
+                vk::CompositeAlphaFlagsKHR::INHERIT,
+                vk::CompositeAlphaFlagsKHR::PRE_MULTIPLIED,
+                vk::CompositeAlphaFlagsKHR::POST_MULTIPLIED,
+            ]
+            .iter()
+            .copied()
+            .find(|&alpha| capabilities.supported_composite_alpha.contains(alpha))
+            .unwrap_or(vk::CompositeAlphaFlagsKHR::OPAQUE);
+
             let swapchain = Owned::create(
+                context,
                 &vk::SwapchainCreateInfoKHR::builder()
                     .surface(surface)
-                    .min_image_count(2)
+                    .min_image_count(min_image_count)
                     .image_format(color_format)
-                    .image_color_space(vk::ColorSpaceKHR::SRGB_NONLINEAR)
+                    .image_color_space(surface_format.color_space)
                     .image_extent(vk::Extent2D { width, height })
                     .image_array_layers(1)
-                    .image_usage(
-                        vk::ImageUsageFlags::COLOR_ATTACHMENT | vk::ImageUsageFlags::TRANSFER_DST,
-                    )
-                    .queue_family_indices(&[GRAPHICS_QUEUE_FAMILY_INDEX])
-                    .pre_transform(vk::SurfaceTransformFlagsKHR::IDENTITY)
-                    .composite_alpha(vk::CompositeAlphaFlagsKHR::OPAQUE)
-                    .present_mode(vk::PresentModeKHR::FIFO)
+                    .image_usage(image_usage)
+                    .queue_family_indices(&[context.graphics_queue.family_index])
+                    .pre_transform(pre_transform)
+                    .composite_alpha(composite_alpha)
+                    .present_mode(present_mode)
                     .old_swapchain(old_swapchain)
                     .build(),
             )?;
 
-            let depth_image = Image::create_2d(
-                (width, height),
-                1,
-                vk::Format::D32_SFLOAT,
-                samples,
-                vk::ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT,
-                MemoryTypeMask::any(),
-            )?;
-            let depth_image_view = ImageView::create_2d(
-                depth_image.object.as_raw(),
-                vk::Format::D32_SFLOAT,
-                vk::ImageAspectFlags::DEPTH,
-            )?;
+            let attachments = Attachments::create(context, (width, height), color_format, samples)?;
 
-            let color_image = Image::create_2d(
-                (width, height),
-                1,
-                color_format,
-                samples,
-                vk::ImageUsageFlags::COLOR_ATTACHMENT,
-                MemoryTypeMask::any(),
-            )?;
-            let color_image_view = ImageView::create_2d(
-                color_image.object.as_raw(),
-                color_format,
-                vk::ImageAspectFlags::COLOR,
-            )?;
-
-            let images = ext::SWAPCHAIN.get_swapchain_images(swapchain.as_raw())?;
+            let images = context
+                .swapchain_loader()?
+                .get_swapchain_images(swapchain.as_raw())?;
 
             let image_views = images
                 .iter()
                 .map(|&image| {
-                    ImageView::create_2d(image, color_format, vk::ImageAspectFlags::COLOR)
+                    ImageView::create_2d(context, image, color_format, vk::ImageAspectFlags::COLOR)
                 })
                 .collect::<VkResult<Vec<_>>>()?;
 
             let framebuffers = image_views
                 .iter()
                 .map(|image_view| {
-                    Owned::create(
-                        &vk::FramebufferCreateInfo::builder()
-                            .render_pass(render_pass)
-                            .attachments(&[
-                                color_image_view.as_raw(),
-                                depth_image_view.as_raw(),
-                                image_view.as_raw(),
-                            ])
-                            .width(width)
-                            .height(height)
-                            .layers(1)
-                            .build(),
+                    attachments.create_framebuffer(
+                        render_pass,
+                        image_view.as_raw(),
+                        (width, height),
                     )
                 })
                 .collect::<VkResult<Vec<_>>>()?;
 
             Ok(Self {
                 swapchain,
-                depth_image,
-                depth_image_view,
-                color_image,
-                color_image_view,
+                image_usage,
+                attachments,
                 images,
                 image_views,
                 framebuffers,
@@ -273,40 +1049,48 @@ impl Swapchain {
         surface: vk::SurfaceKHR,
         render_pass: vk::RenderPass,
         (width, height): (u32, u32),
-        color_format: vk::Format,
+        surface_format: vk::SurfaceFormatKHR,
         samples: vk::SampleCountFlags,
+        present_mode: vk::PresentModeKHR,
     ) -> VkResult<()> {
         *self = Self::create(
+            &self.swapchain.context().clone(),
             self.swapchain.as_raw(),
             surface,
             render_pass,
             (width, height),
-            color_format,
+            surface_format,
             samples,
+            present_mode,
+            self.image_usage,
         )?;
 
         Ok(())
     }
 
+    /// Acquires the next image, and whether the swapchain no longer matches the surface exactly
+    /// and should be recreated.
     pub fn next(
         &self,
         timeout: u64,
         semaphore: vk::Semaphore,
         fence: vk::Fence,
-    ) -> VkResult<SwapchainItem> {
+    ) -> VkResult<(SwapchainItem, bool)> {
         unsafe {
-            let (index, _suboptimal) = ext::SWAPCHAIN.acquire_next_image(
-                self.swapchain.as_raw(),
-                timeout,
-                semaphore,
-                fence,
-            )?;
-            Ok(SwapchainItem {
-                index,
-                image: self.images[index as usize],
-                image_view: self.image_views[index as usize].as_raw(),
-                framebuffer: self.framebuffers[index as usize].as_raw(),
-            })
+            let (index, suboptimal) = self
+                .swapchain
+                .context()
+                .swapchain_loader()?
+                .acquire_next_image(self.swapchain.as_raw(), timeout, semaphore, fence)?;
+            Ok((
+                SwapchainItem {
+                    index,
+                    image: self.images[index as usize],
+                    image_view: self.image_views[index as usize].as_raw(),
+                    framebuffer: self.framebuffers[index as usize].as_raw(),
+                },
+                suboptimal,
+            ))
         }
     }
 }
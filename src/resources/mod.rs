@@ -1,7 +1,9 @@
 pub use mesh::*;
 pub use shader::*;
 pub use texture::*;
+pub use upload::*;
 
 mod mesh;
 mod shader;
 mod texture;
+mod upload;
@@ -0,0 +1,21 @@
+// Encodes linear BT.709 colors for the output color space, see `ColorSpace::shader_macro`.
+#ifndef OUTPUT_GLSL
+#define OUTPUT_GLSL
+
+#ifdef OUTPUT_HDR10
+// BT.709 to BT.2020 primaries, with SDR white at 203 nits, then the ST 2084 (PQ) curve.
+vec3 encode_output(vec3 color) {
+  const mat3 bt709_to_bt2020 = mat3(
+    0.6274, 0.0691, 0.0164,
+    0.3293, 0.9195, 0.0880,
+    0.0433, 0.0114, 0.8956);
+  vec3 y = max(bt709_to_bt2020 * color, 0) * (203.0 / 10000.0);
+  vec3 p = pow(y, vec3(0.1593017578125));
+  return pow((0.8359375 + 18.8515625 * p) / (1 + 18.6875 * p), vec3(78.84375));
+}
+#else
+// sRGB and scRGB framebuffers take linear values.
+vec3 encode_output(vec3 color) { return color; }
+#endif
+
+#endif
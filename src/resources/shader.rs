@@ -1,11 +1,35 @@
+use std::sync::Arc;
+
+use crate::context::*;
 use crate::device::Owned;
-use crate::globals::*;
 
-pub struct Compiler(shaderc::Compiler);
+/// Built-in headers shaders can `#include <...>`.
+const INCLUDES: &[(&str, &str)] = &[("output.glsl", include_str!("output.glsl"))];
+
+pub struct Compiler(
+    shaderc::Compiler,
+    shaderc::CompileOptions<'static>,
+    Arc<Context>,
+);
 
 impl Compiler {
-    pub fn new() -> Self {
-        Self(shaderc::Compiler::new().unwrap())
+    pub fn new(context: &Arc<Context>) -> Self {
+        let mut options = shaderc::CompileOptions::new().unwrap();
+        options.set_include_callback(|name, _, _, _| {
+            match INCLUDES.iter().find(|&&(include, _)| include == name) {
+                Some(&(_, content)) => Ok(shaderc::ResolvedInclude {
+                    resolved_name: name.to_string(),
+                    content: content.to_string(),
+                }),
+                None => Err(format!("unknown include {:?}", name)),
+            }
+        });
+        Self(shaderc::Compiler::new().unwrap(), options, context.clone())
+    }
+
+    /// Defines `name` for all shaders compiled afterwards.
+    pub fn define(&mut self, name: &str) {
+        self.1.add_macro_definition(name, None);
     }
 
     pub fn compile_vertex(&mut self, source: &str) -> Result<Shader> {
@@ -30,10 +54,10 @@ impl Compiler {
         vk_stage: vk::ShaderStageFlags,
         source: &str,
     ) -> Result<Shader> {
-        let artifact = self
-            .0
-            .compile_into_spirv(source, compiler_type, "input", "main", None)?;
-        Ok(Shader::new(artifact.as_binary(), vk_stage)?)
+        let artifact =
+            self.0
+                .compile_into_spirv(source, compiler_type, "input", "main", Some(&self.1))?;
+        Ok(Shader::new(&self.2, artifact.as_binary(), vk_stage)?)
     }
 }
 
@@ -46,9 +70,17 @@ impl AsRef<vk::ShaderModule> for Shader {
 }
 
 impl Shader {
-    pub fn new(code: &[u32], stage: vk::ShaderStageFlags) -> VkResult<Self> {
-        let owned =
-            unsafe { Owned::create(&vk::ShaderModuleCreateInfo::builder().code(code).build())? };
+    pub fn new(
+        context: &Arc<Context>,
+        code: &[u32],
+        stage: vk::ShaderStageFlags,
+    ) -> VkResult<Self> {
+        let owned = unsafe {
+            Owned::create(
+                context,
+                &vk::ShaderModuleCreateInfo::builder().code(code).build(),
+            )?
+        };
         Ok(Self(owned, stage))
     }
 
@@ -1,3 +1,6 @@
+use std::sync::Arc;
+
+use crate::context::Context;
 use crate::device::{self, AsRawHandle};
 use ash::{prelude::*, vk};
 
@@ -10,8 +13,14 @@ pub struct Texture {
 }
 
 impl Texture {
-    pub fn create(width: u32, height: u32, format: vk::Format) -> VkResult<Texture> {
+    pub fn create(
+        context: &Arc<Context>,
+        width: u32,
+        height: u32,
+        format: vk::Format,
+    ) -> VkResult<Texture> {
         let image = device::Image::create_2d(
+            context,
             (width, height),
             device::mip_levels((width, height)),
             format,
@@ -23,12 +32,13 @@ impl Texture {
         )?;
 
         let image_view = device::ImageView::create_2d(
+            context,
             image.object.as_raw(),
             format,
             vk::ImageAspectFlags::COLOR,
         )?;
 
-        let sampler = device::Sampler::linear()?;
+        let sampler = device::Sampler::linear(context)?;
 
         Ok(Self {
             width,
@@ -39,113 +49,143 @@ impl Texture {
         })
     }
 
+    /// Copies the first mip level from `buffer` on the transfer queue, then generates the
+    /// remaining levels with blits on the graphics queue.
     pub fn copy_from(&self, buffer: vk::Buffer, offset: vk::DeviceSize) -> VkResult<()> {
-        let recording = device::CommandBuffer::create()?;
-        // Transition to be a transfer target
-        recording.image_transition(
-            vk::PipelineStageFlags::HOST,
-            vk::PipelineStageFlags::TRANSFER,
-            &[vk::ImageMemoryBarrier::builder()
-                .image(self.image.object.as_raw())
-                .src_access_mask(vk::AccessFlags::empty())
-                .dst_access_mask(vk::AccessFlags::TRANSFER_READ)
-                .old_layout(vk::ImageLayout::UNDEFINED)
-                .new_layout(vk::ImageLayout::TRANSFER_DST_OPTIMAL)
-                .subresource_range(
-                    vk::ImageSubresourceRange::builder()
-                        .aspect_mask(vk::ImageAspectFlags::COLOR)
-                        .layer_count(vk::REMAINING_ARRAY_LAYERS)
-                        .level_count(vk::REMAINING_MIP_LEVELS)
-                        .build(),
-                )
-                .build()],
-        );
-        // Copy image data from buffer to first mip level. Assume buffer data is packed.
-        recording.copy_buffer_to_image(
-            buffer,
-            self.image.object.as_raw(),
-            vk::ImageLayout::TRANSFER_DST_OPTIMAL,
-            &[vk::BufferImageCopy::builder()
-                .buffer_offset(offset)
-                .image_subresource(
-                    vk::ImageSubresourceLayers::builder()
-                        .aspect_mask(vk::ImageAspectFlags::COLOR)
-                        .layer_count(1)
-                        .mip_level(0)
-                        .build(),
-                )
-                .image_extent(vk::Extent3D {
-                    width: self.width,
-                    height: self.height,
-                    depth: 1,
-                })
-                .build()],
-        );
-        // Now transition first mip level to transfer source
-        recording.image_transition(
-            vk::PipelineStageFlags::TRANSFER,
-            vk::PipelineStageFlags::TRANSFER,
-            &[vk::ImageMemoryBarrier::builder()
-                .image(self.image.object.as_raw())
+        let first_level = vk::ImageSubresourceRange::builder()
+            .aspect_mask(vk::ImageAspectFlags::COLOR)
+            .layer_count(vk::REMAINING_ARRAY_LAYERS)
+            .level_count(1)
+            .build();
+        let other_levels = vk::ImageSubresourceRange::builder()
+            .aspect_mask(vk::ImageAspectFlags::COLOR)
+            .layer_count(vk::REMAINING_ARRAY_LAYERS)
+            .base_mip_level(1)
+            .level_count(vk::REMAINING_MIP_LEVELS)
+            .build();
+
+        self.image.upload(
+            // The first mip level is the blit source on the graphics queue.
+            vk::ImageMemoryBarrier::builder()
                 .src_access_mask(vk::AccessFlags::TRANSFER_WRITE)
                 .dst_access_mask(vk::AccessFlags::TRANSFER_READ)
                 .old_layout(vk::ImageLayout::TRANSFER_DST_OPTIMAL)
                 .new_layout(vk::ImageLayout::TRANSFER_SRC_OPTIMAL)
-                .subresource_range(
-                    vk::ImageSubresourceRange::builder()
-                        .aspect_mask(vk::ImageAspectFlags::COLOR)
-                        .layer_count(vk::REMAINING_ARRAY_LAYERS)
-                        .level_count(1)
-                        .build(),
-                )
-                .build()],
-        );
-
-        // And blit it to remaining mip levels
-        recording.blit_image(
-            self.image.object.as_raw(),
-            vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
-            self.image.object.as_raw(),
-            vk::ImageLayout::TRANSFER_DST_OPTIMAL,
-            &(1..device::mip_levels((self.width, self.height)))
-                .into_iter()
-                .map(|level| {
-                    vk::ImageBlit::builder()
-                        .src_offsets([
-                            vk::Offset3D::default(),
-                            vk::Offset3D {
-                                x: self.width as i32,
-                                y: self.height as i32,
-                                z: 1,
-                            },
-                        ])
-                        .src_subresource(
+                .subresource_range(first_level)
+                .build(),
+            vk::PipelineStageFlags::TRANSFER,
+            |recording| {
+                // Transition to be a transfer target
+                recording.image_transition(
+                    vk::PipelineStageFlags::HOST,
+                    vk::PipelineStageFlags::TRANSFER,
+                    &[vk::ImageMemoryBarrier::builder()
+                        .image(self.image.object.as_raw())
+                        .src_access_mask(vk::AccessFlags::empty())
+                        .dst_access_mask(vk::AccessFlags::TRANSFER_WRITE)
+                        .old_layout(vk::ImageLayout::UNDEFINED)
+                        .new_layout(vk::ImageLayout::TRANSFER_DST_OPTIMAL)
+                        .subresource_range(first_level)
+                        .build()],
+                );
+                // Copy image data from buffer to first mip level. Assume buffer data is packed.
+                recording.copy_buffer_to_image(
+                    buffer,
+                    self.image.object.as_raw(),
+                    vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                    &[vk::BufferImageCopy::builder()
+                        .buffer_offset(offset)
+                        .image_subresource(
                             vk::ImageSubresourceLayers::builder()
                                 .aspect_mask(vk::ImageAspectFlags::COLOR)
                                 .layer_count(1)
                                 .mip_level(0)
                                 .build(),
                         )
-                        .dst_offsets([
-                            vk::Offset3D::default(),
-                            vk::Offset3D {
-                                x: 1.max(self.width as i32 >> level),
-                                y: 1.max(self.height as i32 >> level),
-                                z: 1,
-                            },
-                        ])
-                        .dst_subresource(
-                            vk::ImageSubresourceLayers::builder()
-                                .aspect_mask(vk::ImageAspectFlags::COLOR)
-                                .layer_count(1)
-                                .mip_level(level)
-                                .build(),
-                        )
-                        .build()
-                })
-                .collect::<Vec<vk::ImageBlit>>(),
-            vk::Filter::LINEAR,
-        );
+                        .image_extent(vk::Extent3D {
+                            width: self.width,
+                            height: self.height,
+                            depth: 1,
+                        })
+                        .build()],
+                );
+            },
+            |recording| self.generate_mips(recording, first_level, other_levels),
+        )
+    }
+
+    /// Blits the first mip level, in `TRANSFER_SRC_OPTIMAL`, to the others, then transitions
+    /// them all to be sampled.
+    fn generate_mips(
+        &self,
+        recording: &device::CommandBufferRecorder,
+        first_level: vk::ImageSubresourceRange,
+        other_levels: vk::ImageSubresourceRange,
+    ) {
+        let mip_levels = device::mip_levels((self.width, self.height));
+        // Ranges starting past the last level are invalid, so only use the first if it's alone.
+        let transitioned = if mip_levels > 1 { 2 } else { 1 };
+
+        if mip_levels > 1 {
+            // Nothing of the other levels needs to be kept, so they don't need to be handed over.
+            recording.image_transition(
+                vk::PipelineStageFlags::TOP_OF_PIPE,
+                vk::PipelineStageFlags::TRANSFER,
+                &[vk::ImageMemoryBarrier::builder()
+                    .image(self.image.object.as_raw())
+                    .src_access_mask(vk::AccessFlags::empty())
+                    .dst_access_mask(vk::AccessFlags::TRANSFER_WRITE)
+                    .old_layout(vk::ImageLayout::UNDEFINED)
+                    .new_layout(vk::ImageLayout::TRANSFER_DST_OPTIMAL)
+                    .subresource_range(other_levels)
+                    .build()],
+            );
+
+            // And blit it to remaining mip levels
+            recording.blit_image(
+                self.image.object.as_raw(),
+                vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                self.image.object.as_raw(),
+                vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                &(1..mip_levels)
+                    .map(|level| {
+                        vk::ImageBlit::builder()
+                            .src_offsets([
+                                vk::Offset3D::default(),
+                                vk::Offset3D {
+                                    x: self.width as i32,
+                                    y: self.height as i32,
+                                    z: 1,
+                                },
+                            ])
+                            .src_subresource(
+                                vk::ImageSubresourceLayers::builder()
+                                    .aspect_mask(vk::ImageAspectFlags::COLOR)
+                                    .layer_count(1)
+                                    .mip_level(0)
+                                    .build(),
+                            )
+                            .dst_offsets([
+                                vk::Offset3D::default(),
+                                vk::Offset3D {
+                                    x: 1.max(self.width as i32 >> level),
+                                    y: 1.max(self.height as i32 >> level),
+                                    z: 1,
+                                },
+                            ])
+                            .dst_subresource(
+                                vk::ImageSubresourceLayers::builder()
+                                    .aspect_mask(vk::ImageAspectFlags::COLOR)
+                                    .layer_count(1)
+                                    .mip_level(level)
+                                    .build(),
+                            )
+                            .build()
+                    })
+                    .collect::<Vec<vk::ImageBlit>>(),
+                vk::Filter::LINEAR,
+            );
+        }
         recording.image_transition(
             vk::PipelineStageFlags::TRANSFER,
             vk::PipelineStageFlags::FRAGMENT_SHADER,
@@ -156,32 +196,17 @@ impl Texture {
                     .dst_access_mask(vk::AccessFlags::SHADER_READ)
                     .old_layout(vk::ImageLayout::TRANSFER_SRC_OPTIMAL)
                     .new_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
-                    .subresource_range(
-                        vk::ImageSubresourceRange::builder()
-                            .aspect_mask(vk::ImageAspectFlags::COLOR)
-                            .layer_count(vk::REMAINING_ARRAY_LAYERS)
-                            .level_count(1)
-                            .build(),
-                    )
+                    .subresource_range(first_level)
                     .build(),
                 vk::ImageMemoryBarrier::builder()
                     .image(self.image.object.as_raw())
-                    .src_access_mask(vk::AccessFlags::TRANSFER_READ)
+                    .src_access_mask(vk::AccessFlags::TRANSFER_WRITE)
                     .dst_access_mask(vk::AccessFlags::SHADER_READ)
                     .old_layout(vk::ImageLayout::TRANSFER_DST_OPTIMAL)
                     .new_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
-                    .subresource_range(
-                        vk::ImageSubresourceRange::builder()
-                            .aspect_mask(vk::ImageAspectFlags::COLOR)
-                            .layer_count(vk::REMAINING_ARRAY_LAYERS)
-                            .base_mip_level(1)
-                            .level_count(vk::REMAINING_MIP_LEVELS)
-                            .build(),
-                    )
+                    .subresource_range(other_levels)
                     .build(),
-            ],
+            ][..transitioned],
         );
-        recording.end()?.submit()?;
-        Ok(())
     }
 }
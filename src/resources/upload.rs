@@ -0,0 +1,258 @@
+use std::collections::VecDeque;
+use std::sync::Arc;
+
+use crate::context::{Context, QueueKind};
+use crate::device::{self, AsRawHandle};
+use crate::error::*;
+use ash::vk;
+
+/// The size of each staging buffer. Larger uploads are split across several of them.
+const STAGING_SIZE: vk::DeviceSize = 16 << 20;
+/// The most staging buffers with copies in flight, before waiting for the oldest to complete.
+const STAGING_BUFFERS: usize = 3;
+
+/// A host visible buffer that copies are recorded from.
+struct Staging {
+    buffer: device::Buffer,
+    /// Signalled when the copies from the buffer have completed.
+    fence: device::Fence,
+    /// Signalled when the transfer queue has released the copied ranges to the graphics queue.
+    released: device::Semaphore,
+    /// The submitted command buffers, kept alive until the fence is signalled.
+    submitted: Vec<device::CommandBuffer>,
+}
+
+/// The staging buffer being filled.
+struct Filling {
+    staging: Staging,
+    used: vk::DeviceSize,
+    recorder: device::CommandBufferRecorder,
+    /// The buffer, offset and size of each copy, to hand over to the graphics queue.
+    copies: Vec<(vk::Buffer, vk::DeviceSize, vk::DeviceSize)>,
+}
+
+/// Creates device local buffers, filling them through a ring of host visible staging buffers.
+///
+/// Copies from each staging buffer are recorded into one command buffer for the transfer queue,
+/// which is submitted when the staging buffer is full, while the next one is filled. A staging
+/// buffer is reused once the fence of its copies is signalled. [`Uploader::flush`] submits the
+/// remaining copies and must be called before the buffers are used.
+///
+/// With a dedicated transfer queue, the copied ranges are released from its queue family and
+/// acquired on the graphics queue family, after a semaphore signalled by the copies.
+/// On unified memory devices, where device local memory is host visible anyway, buffers are
+/// filled directly instead.
+pub struct Uploader {
+    context: Arc<Context>,
+    filling: Option<Filling>,
+    /// Staging buffers with submitted copies, oldest first.
+    submitted: VecDeque<Staging>,
+}
+
+impl Uploader {
+    pub fn new(context: &Arc<Context>) -> Self {
+        Self {
+            context: context.clone(),
+            filling: None,
+            submitted: VecDeque::new(),
+        }
+    }
+
+    /// Creates a buffer of `size` bytes, calling `fill` with consecutive chunks of its contents.
+    /// Buffers can't be empty, so `size` must not be zero.
+    pub fn create_buffer(
+        &mut self,
+        size: vk::DeviceSize,
+        usage: vk::BufferUsageFlags,
+        mut fill: impl FnMut(&mut [u8]) -> Result<()>,
+    ) -> Result<device::Buffer> {
+        if size == 0 {
+            return Err(Error::Definition(format!(
+                "empty buffer with usage {:?}",
+                usage
+            )));
+        }
+        let context = &self.context;
+        let direct = is_unified_memory(context);
+        let device_local =
+            device::MemoryTypeMask::with_properties(context, vk::MemoryPropertyFlags::DEVICE_LOCAL);
+        let mut buffer = device::Buffer::create_in(
+            context,
+            size,
+            usage | vk::BufferUsageFlags::TRANSFER_DST,
+            if direct {
+                device::MemoryTypeMask::mappable(context) & device_local
+            } else {
+                device_local
+            },
+        )?;
+
+        if direct {
+            fill(&mut buffer.memory.slice_mut(0, size as usize)?)?;
+            return Ok(buffer);
+        }
+
+        let mut offset = 0;
+        while offset < size {
+            if matches!(&self.filling, Some(filling) if filling.used == STAGING_SIZE) {
+                self.submit()?;
+            }
+            let filling = match &mut self.filling {
+                Some(filling) => filling,
+                filling => filling.insert(Filling {
+                    staging: Self::next_staging(&self.context, &mut self.submitted)?,
+                    used: 0,
+                    recorder: device::CommandBuffer::create(&self.context, QueueKind::Transfer)?,
+                    copies: Vec::new(),
+                }),
+            };
+            let chunk = (size - offset).min(STAGING_SIZE - filling.used);
+            let staging = &mut filling.staging.buffer;
+            fill(&mut staging.memory.slice_mut(filling.used, chunk as usize)?)?;
+            filling.recorder.copy_buffer(
+                staging.as_raw(),
+                buffer.as_raw(),
+                &[vk::BufferCopy {
+                    src_offset: filling.used,
+                    dst_offset: offset,
+                    size: chunk,
+                }],
+            );
+            filling.copies.push((buffer.as_raw(), offset, chunk));
+            filling.used += chunk;
+            offset += chunk;
+        }
+
+        Ok(buffer)
+    }
+
+    /// Submits the pending copies and waits for them to complete.
+    pub fn flush(&mut self) -> Result<()> {
+        self.submit()?;
+        for staging in &mut self.submitted {
+            staging.fence.wait()?;
+            staging.submitted.clear();
+        }
+        Ok(())
+    }
+
+    /// Submits the copies from the staging buffer being filled, if any.
+    fn submit(&mut self) -> Result<()> {
+        let Filling {
+            mut staging,
+            recorder,
+            copies,
+            ..
+        } = match self.filling.take() {
+            Some(filling) => filling,
+            None => return Ok(()),
+        };
+        let context = &self.context;
+        let dst_stage_mask = vk::PipelineStageFlags::VERTEX_INPUT
+            | vk::PipelineStageFlags::VERTEX_SHADER
+            | vk::PipelineStageFlags::FRAGMENT_SHADER;
+        let dst_access_mask = vk::AccessFlags::VERTEX_ATTRIBUTE_READ
+            | vk::AccessFlags::INDEX_READ
+            | vk::AccessFlags::UNIFORM_READ;
+        let src_family_index = context.queue(QueueKind::Transfer).family_index;
+        let dst_family_index = context.graphics_queue.family_index;
+
+        if src_family_index == dst_family_index {
+            recorder.memory_barrier(
+                vk::PipelineStageFlags::TRANSFER,
+                dst_stage_mask,
+                vk::AccessFlags::TRANSFER_WRITE,
+                dst_access_mask,
+            );
+            let recorded = recorder.end()?;
+            staging.fence.reset()?;
+            recorded.submit_signalling(&[], &[], &[], staging.fence.as_raw())?;
+            staging.submitted.push(recorded);
+        } else {
+            // The release's destination access and the acquire's source access are ignored, the
+            // semaphore provides the dependency between them.
+            let handover = |src_access_mask, dst_access_mask| {
+                copies
+                    .iter()
+                    .map(|&(buffer, offset, size)| vk::BufferMemoryBarrier {
+                        src_access_mask,
+                        dst_access_mask,
+                        src_queue_family_index: src_family_index,
+                        dst_queue_family_index: dst_family_index,
+                        buffer,
+                        offset,
+                        size,
+                        ..Default::default()
+                    })
+                    .collect::<Vec<_>>()
+            };
+            recorder.buffer_barrier(
+                vk::PipelineStageFlags::TRANSFER,
+                vk::PipelineStageFlags::BOTTOM_OF_PIPE,
+                &handover(vk::AccessFlags::TRANSFER_WRITE, vk::AccessFlags::empty()),
+            );
+            let acquire = device::CommandBuffer::create(context, QueueKind::Graphics)?;
+            acquire.buffer_barrier(
+                dst_stage_mask,
+                dst_stage_mask,
+                &handover(vk::AccessFlags::empty(), dst_access_mask),
+            );
+
+            let release = recorder.end()?;
+            release.submit_signalling(&[], &[], &[staging.released.as_raw()], vk::Fence::null())?;
+            staging.submitted.push(release);
+            let acquire = acquire.end()?;
+            staging.fence.reset()?;
+            acquire.submit_signalling(
+                &[staging.released.as_raw()],
+                &[dst_stage_mask],
+                &[],
+                staging.fence.as_raw(),
+            )?;
+            staging.submitted.push(acquire);
+        }
+        self.submitted.push_back(staging);
+        Ok(())
+    }
+
+    /// Takes the oldest staging buffer if its copies have completed, or if the ring is full once
+    /// they have, otherwise creates another.
+    fn next_staging(context: &Arc<Context>, submitted: &mut VecDeque<Staging>) -> Result<Staging> {
+        if let Some(oldest) = submitted.front() {
+            if submitted.len() >= STAGING_BUFFERS || oldest.fence.is_signalled()? {
+                oldest.fence.wait()?;
+                let mut staging = submitted.pop_front().unwrap();
+                staging.submitted.clear();
+                return Ok(staging);
+            }
+        }
+        Ok(Staging {
+            buffer: device::Buffer::create(
+                context,
+                STAGING_SIZE,
+                vk::BufferUsageFlags::TRANSFER_SRC,
+            )?,
+            fence: device::Fence::create(context)?,
+            released: device::Semaphore::create(context)?,
+            submitted: Vec::new(),
+        })
+    }
+}
+
+impl Drop for Uploader {
+    fn drop(&mut self) {
+        // The staging buffers mustn't be destroyed while copies from them may be running, e.g.
+        // when loading failed before the flush.
+        for staging in &self.submitted {
+            let _ = staging.fence.wait();
+        }
+    }
+}
+
+/// Whether all device local memory is also host visible, e.g. on integrated GPUs.
+fn is_unified_memory(context: &Context) -> bool {
+    let device_local =
+        device::MemoryTypeMask::with_properties(context, vk::MemoryPropertyFlags::DEVICE_LOCAL);
+    device_local != device::MemoryTypeMask::none()
+        && device_local & device::MemoryTypeMask::mappable(context) == device_local
+}
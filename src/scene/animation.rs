@@ -0,0 +1,265 @@
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+use crate::error::*;
+use crate::math::*;
+
+use super::definition::{self, AnimationPath, Interpolation};
+use super::Node;
+
+struct Channel {
+    node: usize,
+    path: AnimationPath,
+    interpolation: Interpolation,
+    times: Vec<f32>,
+    values: Vec<f32>,
+}
+
+/// Node transform animation, looping over the length of its longest channel.
+pub struct Animation {
+    channels: Vec<Channel>,
+    duration: f32,
+}
+
+impl Animation {
+    pub fn create(
+        definition: &definition::Animation,
+        node_indices: &BTreeMap<u32, usize>,
+        nodes: &[Node],
+    ) -> Result<Self> {
+        let invalid = |message: &str| {
+            Error::Definition(match &definition.name {
+                Some(name) => format!("animation {:?}: {}", name, message),
+                None => format!("animation: {}", message),
+            })
+        };
+
+        let mut channels = Vec::new();
+        let mut duration = 0.0f32;
+        for c in &definition.channels {
+            let &node = node_indices
+                .get(&c.node)
+                .ok_or_else(|| invalid(&format!("unknown node {}", c.node)))?;
+            if nodes[node].matrix.is_some() {
+                return Err(invalid(&format!(
+                    "node {} has a matrix transform and can't be animated",
+                    c.node
+                )));
+            }
+
+            if c.times.is_empty() || c.times.windows(2).any(|w| w[0] > w[1]) {
+                return Err(invalid("keyframe times must be non-empty and increasing"));
+            }
+            let keyframe_values = match c.interpolation {
+                Interpolation::CubicSpline => 3,
+                _ => 1,
+            };
+            if c.values.len() != c.times.len() * keyframe_values * c.path.components() {
+                return Err(invalid("keyframe values don't match the keyframe times"));
+            }
+
+            duration = duration.max(c.times[c.times.len() - 1]);
+            channels.push(Channel {
+                node,
+                path: c.path,
+                interpolation: c.interpolation,
+                times: c.times.clone(),
+                values: c.values.clone(),
+            });
+        }
+
+        Ok(Self { channels, duration })
+    }
+
+    pub fn apply(&self, elapsed: Duration, nodes: &mut [Node]) {
+        let time = if self.duration > 0.0 {
+            elapsed.as_secs_f32() % self.duration
+        } else {
+            0.0
+        };
+
+        for channel in &self.channels {
+            let [x, y, z, w] = channel.sample(time);
+            let node = &mut nodes[channel.node];
+            match channel.path {
+                AnimationPath::Translation => node.translation = [x, y, z].into(),
+                AnimationPath::Rotation => node.rotation = [x, y, z, w].into(),
+                AnimationPath::Scale => node.scale = [x, y, z].into(),
+            }
+        }
+    }
+}
+
+impl Channel {
+    fn sample(&self, time: f32) -> [f32; 4] {
+        let (in_tangent, value, out_tangent) = match self.interpolation {
+            Interpolation::CubicSpline => (0, 1, 2),
+            _ => (0, 0, 0),
+        };
+
+        // Hold the first and last keyframes outside the animated range.
+        let next = match self.times.iter().position(|&t| t > time) {
+            Some(0) => return self.key(0, value),
+            Some(next) => next,
+            None => return self.key(self.times.len() - 1, value),
+        };
+        let prev = next - 1;
+        let dt = self.times[next] - self.times[prev];
+        let t = (time - self.times[prev]) / dt;
+
+        match self.interpolation {
+            Interpolation::Step => self.key(prev, value),
+            Interpolation::Linear => {
+                let from = self.key(prev, value);
+                let to = self.key(next, value);
+                if let AnimationPath::Rotation = self.path {
+                    Quaternion::from(from).slerp(to.into(), t).into()
+                } else {
+                    let mut result = [0.0; 4];
+                    for (i, result) in result.iter_mut().enumerate() {
+                        *result = from[i] + (to[i] - from[i]) * t;
+                    }
+                    result
+                }
+            }
+            Interpolation::CubicSpline => {
+                // Hermite spline, see the glTF specification appendix C.
+                let v0 = self.key(prev, value);
+                let b0 = self.key(prev, out_tangent);
+                let a1 = self.key(next, in_tangent);
+                let v1 = self.key(next, value);
+                let t2 = t * t;
+                let t3 = t2 * t;
+                let mut result = [0.0; 4];
+                for (i, result) in result.iter_mut().enumerate() {
+                    *result = (2.0 * t3 - 3.0 * t2 + 1.0) * v0[i]
+                        + (t3 - 2.0 * t2 + t) * dt * b0[i]
+                        + (-2.0 * t3 + 3.0 * t2) * v1[i]
+                        + (t3 - t2) * dt * a1[i];
+                }
+                if let AnimationPath::Rotation = self.path {
+                    Quaternion::from(result).normalized().into()
+                } else {
+                    result
+                }
+            }
+        }
+    }
+
+    /// Returns element `offset` of `keyframe`, which is a value or tangent depending on the
+    /// interpolation.
+    fn key(&self, keyframe: usize, offset: usize) -> [f32; 4] {
+        let components = self.path.components();
+        let stride = match self.interpolation {
+            Interpolation::CubicSpline => 3 * components,
+            _ => components,
+        };
+        let start = keyframe * stride + offset * components;
+        let mut result = [0.0; 4];
+        result[..components].copy_from_slice(&self.values[start..start + components]);
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn channel(
+        path: AnimationPath,
+        interpolation: Interpolation,
+        times: &[f32],
+        values: &[f32],
+    ) -> Channel {
+        Channel {
+            node: 0,
+            path,
+            interpolation,
+            times: times.to_vec(),
+            values: values.to_vec(),
+        }
+    }
+
+    fn translation(interpolation: Interpolation, times: &[f32], x: &[f32]) -> Channel {
+        let values = x.iter().flat_map(|&x| [x, 0.0, 0.0]).collect::<Vec<_>>();
+        channel(AnimationPath::Translation, interpolation, times, &values)
+    }
+
+    fn assert_near(actual: [f32; 4], expected: [f32; 4]) {
+        assert!(
+            actual
+                .iter()
+                .zip(&expected)
+                .all(|(a, e)| (a - e).abs() < 1e-5),
+            "{:?} != {:?}",
+            actual,
+            expected
+        );
+    }
+
+    #[test]
+    fn step_holds_the_previous_keyframe() {
+        let channel = translation(Interpolation::Step, &[0.0, 1.0, 2.0], &[1.0, 2.0, 3.0]);
+        assert_near(channel.sample(0.0), [1.0, 0.0, 0.0, 0.0]);
+        assert_near(channel.sample(0.99), [1.0, 0.0, 0.0, 0.0]);
+        assert_near(channel.sample(1.0), [2.0, 0.0, 0.0, 0.0]);
+        assert_near(channel.sample(1.5), [2.0, 0.0, 0.0, 0.0]);
+    }
+
+    #[test]
+    fn linear_interpolates_between_keyframes() {
+        let channel = translation(Interpolation::Linear, &[0.0, 2.0, 3.0], &[0.0, 4.0, 2.0]);
+        assert_near(channel.sample(0.5), [1.0, 0.0, 0.0, 0.0]);
+        assert_near(channel.sample(2.0), [4.0, 0.0, 0.0, 0.0]);
+        assert_near(channel.sample(2.5), [3.0, 0.0, 0.0, 0.0]);
+    }
+
+    #[test]
+    fn linear_rotation_slerps() {
+        let half = std::f32::consts::FRAC_1_SQRT_2;
+        let channel = channel(
+            AnimationPath::Rotation,
+            Interpolation::Linear,
+            &[0.0, 1.0],
+            &[0.0, 0.0, 0.0, 1.0, 0.0, 0.0, half, half],
+        );
+        let angle = std::f32::consts::FRAC_PI_8;
+        assert_near(channel.sample(0.5), [0.0, 0.0, angle.sin(), angle.cos()]);
+    }
+
+    #[test]
+    fn cubic_spline_uses_tangents_scaled_by_keyframe_interval() {
+        // Each keyframe is an in-tangent, value and out-tangent.
+        let flat = translation(
+            Interpolation::CubicSpline,
+            &[0.0, 1.0],
+            &[0.0, 0.0, 0.0, 0.0, 1.0, 0.0],
+        );
+        assert_near(flat.sample(0.25), [0.15625, 0.0, 0.0, 0.0]);
+        assert_near(flat.sample(0.5), [0.5, 0.0, 0.0, 0.0]);
+
+        let sloped = translation(
+            Interpolation::CubicSpline,
+            &[0.0, 2.0],
+            &[0.0, 0.0, 1.0, 0.0, 0.0, 0.0],
+        );
+        assert_near(sloped.sample(1.0), [0.25, 0.0, 0.0, 0.0]);
+        assert_near(sloped.sample(2.0), [0.0, 0.0, 0.0, 0.0]);
+    }
+
+    #[test]
+    fn clamps_outside_the_keyframe_range() {
+        for interpolation in [Interpolation::Step, Interpolation::Linear] {
+            let channel = translation(interpolation, &[1.0, 2.0], &[5.0, 7.0]);
+            assert_near(channel.sample(0.0), [5.0, 0.0, 0.0, 0.0]);
+            assert_near(channel.sample(3.0), [7.0, 0.0, 0.0, 0.0]);
+        }
+        let cubic = translation(
+            Interpolation::CubicSpline,
+            &[1.0, 2.0],
+            &[1.0, 5.0, 1.0, 1.0, 7.0, 1.0],
+        );
+        assert_near(cubic.sample(0.0), [5.0, 0.0, 0.0, 0.0]);
+        assert_near(cubic.sample(3.0), [7.0, 0.0, 0.0, 0.0]);
+    }
+}
@@ -1,28 +1,58 @@
 use crate::math::*;
 
+use super::definition;
+
 #[derive(Copy, Clone)]
 pub struct OrthographicProjection {
-    pub width: f32,
+    /// Follows the aspect ratio when not set.
+    pub width: Option<f32>,
     pub height: f32,
     pub depth: f32,
+    pub aspect: f32,
+    pub reverse_z: bool,
 }
 
 impl Default for OrthographicProjection {
     fn default() -> Self {
         Self {
-            width: 1.0,
+            width: None,
             height: 1.0,
             depth: 1.0,
+            aspect: 1.0,
+            reverse_z: false,
         }
     }
 }
 
+impl OrthographicProjection {
+    /// Maps a box `width` by `height` centered in front of the camera and `depth` deep to
+    /// clip space.
+    pub fn matrix(&self) -> Mat4 {
+        let width = self.width.unwrap_or(self.height * self.aspect);
+        let (z, w) = if self.reverse_z {
+            (1.0 / self.depth, 1.0)
+        } else {
+            (-1.0 / self.depth, 0.0)
+        };
+        Mat4::from([
+            Vec4::from([2.0 / width, 0.0, 0.0, 0.0]),
+            Vec4::from([0.0, -2.0 / self.height, 0.0, 0.0]),
+            Vec4::from([0.0, 0.0, z, 0.0]),
+            Vec4::from([0.0, 0.0, w, 1.0]),
+        ])
+    }
+}
+
 #[derive(Copy, Clone)]
 pub struct PerspectiveProjection {
     pub aspect: f32,
     pub fov_deg_height: f32,
     pub near: f32,
-    pub far: f32,
+    /// Infinitely far when not set.
+    pub far: Option<f32>,
+    /// Maps the near plane to depth 1 and the far plane to 0, which spreads floating point
+    /// depth precision much more evenly.
+    pub reverse_z: bool,
 }
 
 impl Default for PerspectiveProjection {
@@ -31,33 +61,25 @@ impl Default for PerspectiveProjection {
             aspect: 1.0,
             fov_deg_height: 60.0,
             near: 0.01,
-            far: 1000.0,
+            far: Some(1000.0),
+            reverse_z: false,
         }
     }
 }
 
-pub trait Projection {
-    fn matrix(&self) -> Mat4;
-}
-
-impl Projection for OrthographicProjection {
-    fn matrix(&self) -> Mat4 {
-        Mat4::scale(Vec3::from([
-            1.0 / self.width,
-            1.0 / self.height,
-            1.0 / self.depth,
-        ]))
-    }
-}
-
-impl Projection for PerspectiveProjection {
-    fn matrix(&self) -> Mat4 {
+impl PerspectiveProjection {
+    pub fn matrix(&self) -> Mat4 {
         let r = (self.fov_deg_height * std::f32::consts::PI / 360.0).tan();
         let t = r * self.aspect;
         let x = 1.0 / t;
         let y = 1.0 / r;
-        let z = self.far / (self.near - self.far);
-        let w = self.far * self.near / (self.near - self.far);
+        let (near, far) = (self.near, self.far);
+        let (z, w) = match (self.reverse_z, far) {
+            (false, Some(far)) => (far / (near - far), far * near / (near - far)),
+            (false, None) => (-1.0, -near),
+            (true, Some(far)) => (near / (far - near), far * near / (far - near)),
+            (true, None) => (0.0, near),
+        };
         Mat4::from([
             Vec4::from([x, 0.0, 0.0, 0.0]),
             Vec4::from([0.0, -y, 0.0, 0.0]),
@@ -67,6 +89,72 @@ impl Projection for PerspectiveProjection {
     }
 }
 
+#[derive(Copy, Clone)]
+pub enum Projection {
+    Perspective(PerspectiveProjection),
+    Orthographic(OrthographicProjection),
+}
+
+impl Projection {
+    pub fn matrix(&self) -> Mat4 {
+        match self {
+            Self::Perspective(projection) => projection.matrix(),
+            Self::Orthographic(projection) => projection.matrix(),
+        }
+    }
+
+    pub fn reverse_z(&self) -> bool {
+        match self {
+            Self::Perspective(projection) => projection.reverse_z,
+            Self::Orthographic(projection) => projection.reverse_z,
+        }
+    }
+
+    /// The depth buffer clear value, i.e. the far plane.
+    pub fn clear_depth(&self) -> f32 {
+        if self.reverse_z() {
+            0.0
+        } else {
+            1.0
+        }
+    }
+
+    pub fn set_aspect(&mut self, aspect: f32) {
+        match self {
+            Self::Perspective(projection) => projection.aspect = aspect,
+            Self::Orthographic(projection) => projection.aspect = aspect,
+        }
+    }
+}
+
+impl From<&definition::Camera> for Projection {
+    fn from(value: &definition::Camera) -> Self {
+        let reverse_z = value.reverse_z;
+        match value.projection {
+            definition::CameraProjection::Perspective { fov, near, far } => {
+                Self::Perspective(PerspectiveProjection {
+                    fov_deg_height: fov,
+                    near,
+                    far,
+                    reverse_z,
+                    ..Default::default()
+                })
+            }
+            definition::CameraProjection::Orthographic {
+                width,
+                height,
+                depth,
+            } => Self::Orthographic(OrthographicProjection {
+                width,
+                height,
+                depth,
+                reverse_z,
+                ..Default::default()
+            }),
+        }
+    }
+}
+
 #[derive(Copy, Clone, Default)]
 pub struct Transform {
     pub position: Vec3,
@@ -79,13 +167,13 @@ impl Transform {
     }
 }
 
-#[derive(Copy, Clone, Default)]
-pub struct Camera<P: Projection> {
+#[derive(Copy, Clone)]
+pub struct Camera {
     pub transform: Transform,
-    pub projection: P,
+    pub projection: Projection,
 }
 
-impl<P: Projection> Camera<P> {
+impl Camera {
     pub fn matrix(&self) -> Mat4 {
         self.projection.matrix() * self.transform.matrix()
     }
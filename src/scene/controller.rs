@@ -0,0 +1,208 @@
+//! Interactive camera controllers.
+//!
+//! - `1` orbit: drag to rotate around the target, scroll to zoom.
+//! - `2` fly: WASD to move, Q/E to move down/up, drag to look around, scroll to change speed.
+//! - `3` pan/zoom: drag to move the target, scroll to zoom.
+//!
+//! `C` switches to the next camera defined in the scene, which the controller then moves.
+
+use std::time::Duration;
+
+use winit::event::{
+    DeviceEvent, ElementState, KeyboardInput, MouseButton, MouseScrollDelta, VirtualKeyCode,
+    WindowEvent,
+};
+
+use crate::math::*;
+
+use super::camera;
+use super::definition::{self, ControllerMode};
+
+const LOOK_RADIANS_PER_PIXEL: f32 = 0.005;
+const PAN_DISTANCE_PER_PIXEL: f32 = 0.002;
+const ZOOM_PER_LINE: f32 = 0.9;
+const PIXELS_PER_LINE: f32 = 20.0;
+
+pub struct Controller {
+    mode: ControllerMode,
+    target: Vec3,
+    distance: f32,
+    position: Vec3,
+    yaw: f32,
+    pitch: f32,
+    speed: f32,
+    spin: f32,
+    dragging: bool,
+    // Held movement keys: right, up, back.
+    movement: [(bool, bool); 3],
+    last_update: Option<Duration>,
+}
+
+impl Controller {
+    pub fn new(definition: &definition::CameraController) -> Self {
+        let mut controller = Self {
+            mode: definition.mode,
+            target: definition.target.into(),
+            distance: definition.distance,
+            position: Vec3::ZERO,
+            yaw: definition.yaw.to_radians(),
+            pitch: definition.pitch.to_radians(),
+            speed: definition.speed.unwrap_or(definition.distance),
+            spin: definition.spin.to_radians(),
+            dragging: false,
+            movement: Default::default(),
+            last_update: None,
+        };
+        controller.position = controller.target
+            + controller
+                .rotation()
+                .rotate(Vec3::Z_POS * controller.distance);
+        controller
+    }
+
+    /// Moves the camera to `position`, facing and orbiting `target`, and stops any spin.
+    pub fn look_at(&mut self, position: Vec3, target: Vec3) {
+        let offset = position + -target;
+        let distance = offset.len();
+        if distance == 0.0 {
+            return;
+        }
+        let direction = offset / distance;
+        let [x, y, z]: [f32; 3] = direction.into();
+        self.position = position;
+        self.target = target;
+        self.distance = distance;
+        self.yaw = x.atan2(z);
+        self.pitch = (-y).asin();
+        self.spin = 0.0;
+    }
+
+    fn rotation(&self) -> Quaternion {
+        Quaternion::axis_angle(Vec3::Y_POS, self.yaw)
+            * Quaternion::axis_angle(Vec3::X_POS, self.pitch)
+    }
+
+    pub fn handle_window_event(&mut self, event: &WindowEvent) {
+        match event {
+            WindowEvent::MouseInput {
+                button: MouseButton::Left,
+                state,
+                ..
+            } => {
+                self.dragging = *state == ElementState::Pressed;
+                self.spin = 0.0;
+            }
+            WindowEvent::MouseWheel { delta, .. } => {
+                let lines = match delta {
+                    MouseScrollDelta::LineDelta(_, y) => *y,
+                    MouseScrollDelta::PixelDelta(position) => position.y as f32 / PIXELS_PER_LINE,
+                };
+                let scale = ZOOM_PER_LINE.powf(lines);
+                match self.mode {
+                    ControllerMode::Fly => self.speed /= scale,
+                    ControllerMode::Orbit | ControllerMode::PanZoom => self.distance *= scale,
+                }
+                self.spin = 0.0;
+            }
+            WindowEvent::KeyboardInput {
+                input:
+                    KeyboardInput {
+                        virtual_keycode: Some(key),
+                        state,
+                        ..
+                    },
+                ..
+            } => {
+                self.handle_key(*key, *state == ElementState::Pressed);
+            }
+            WindowEvent::Focused(false) => {
+                self.dragging = false;
+                self.movement = Default::default();
+            }
+            _ => {}
+        }
+    }
+
+    pub fn handle_device_event(&mut self, event: &DeviceEvent) {
+        if let DeviceEvent::MouseMotion { delta: (x, y) } = *event {
+            if !self.dragging {
+                return;
+            }
+            let (x, y) = (x as f32, y as f32);
+            match self.mode {
+                ControllerMode::Orbit | ControllerMode::Fly => {
+                    self.yaw -= x * LOOK_RADIANS_PER_PIXEL;
+                    self.pitch = (self.pitch - y * LOOK_RADIANS_PER_PIXEL)
+                        .clamp(-std::f32::consts::FRAC_PI_2, std::f32::consts::FRAC_PI_2);
+                }
+                ControllerMode::PanZoom => {
+                    let rotation = self.rotation();
+                    let scale = self.distance * PAN_DISTANCE_PER_PIXEL;
+                    self.target = self.target
+                        + rotation.rotate(Vec3::X_NEG * (x * scale))
+                        + rotation.rotate(Vec3::Y_POS * (y * scale));
+                }
+            }
+        }
+    }
+
+    fn handle_key(&mut self, key: VirtualKeyCode, pressed: bool) {
+        let (axis, positive) = match key {
+            VirtualKeyCode::Key1 | VirtualKeyCode::Key2 | VirtualKeyCode::Key3 if pressed => {
+                self.mode = match key {
+                    VirtualKeyCode::Key1 => ControllerMode::Orbit,
+                    VirtualKeyCode::Key2 => ControllerMode::Fly,
+                    _ => ControllerMode::PanZoom,
+                };
+                self.spin = 0.0;
+                return;
+            }
+            VirtualKeyCode::D => (0, true),
+            VirtualKeyCode::A => (0, false),
+            VirtualKeyCode::E => (1, true),
+            VirtualKeyCode::Q => (1, false),
+            VirtualKeyCode::S => (2, true),
+            VirtualKeyCode::W => (2, false),
+            _ => return,
+        };
+        let held = &mut self.movement[axis];
+        if positive {
+            held.0 = pressed;
+        } else {
+            held.1 = pressed;
+        }
+        self.spin = 0.0;
+    }
+
+    pub fn update(&mut self, elapsed: Duration, transform: &mut camera::Transform) {
+        let dt = match self.last_update {
+            Some(last) => elapsed.checked_sub(last).unwrap_or_default().as_secs_f32(),
+            None => 0.0,
+        };
+        self.last_update = Some(elapsed);
+
+        self.yaw += self.spin * dt;
+        let rotation = self.rotation();
+        let offset = rotation.rotate(Vec3::Z_POS * self.distance);
+
+        match self.mode {
+            ControllerMode::Fly => {
+                let axis = |(positive, negative): (bool, bool)| positive as i32 - negative as i32;
+                let direction = Vec3::from([
+                    axis(self.movement[0]) as f32,
+                    axis(self.movement[1]) as f32,
+                    axis(self.movement[2]) as f32,
+                ]);
+                self.position = self.position + rotation.rotate(direction * (self.speed * dt));
+                // Keep the target in front of the camera, so switching back to orbit is seamless.
+                self.target = self.position + -offset;
+            }
+            ControllerMode::Orbit | ControllerMode::PanZoom => {
+                self.position = self.target + offset;
+            }
+        }
+
+        transform.position = self.position;
+        transform.rotation = rotation;
+    }
+}
@@ -1,7 +1,9 @@
+use std::collections::BTreeMap;
+
 use ash::vk;
 use serde::Deserialize;
 
-#[derive(Deserialize)]
+#[derive(Default, Deserialize)]
 pub struct Scene {
     #[serde(default)]
     pub programs: Vec<Program>,
@@ -13,6 +15,93 @@ pub struct Scene {
     pub buffers: Vec<File>,
     #[serde(default)]
     pub meshes: Vec<Mesh>,
+    #[serde(default)]
+    pub nodes: Vec<Node>,
+    #[serde(default)]
+    pub animations: Vec<Animation>,
+    #[serde(default)]
+    pub imports: Vec<Import>,
+    #[serde(default)]
+    pub camera: CameraController,
+    #[serde(default)]
+    pub cameras: Vec<Camera>,
+}
+
+/// A viewpoint that can be switched to. The camera controller takes over from its pose.
+#[derive(Deserialize)]
+pub struct Camera {
+    #[serde(default)]
+    pub name: Option<String>,
+    pub projection: CameraProjection,
+    #[serde(default)]
+    pub position: [f32; 3],
+    /// Looking down -Z when not rotated. Ignored when `look_at` is set.
+    #[serde(default)]
+    pub rotation: Option<[f32; 4]>,
+    #[serde(default)]
+    pub look_at: Option<[f32; 3]>,
+    /// Use reverse-Z depth, see `camera::PerspectiveProjection::reverse_z`.
+    #[serde(default)]
+    pub reverse_z: bool,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum CameraProjection {
+    /// `fov` is the vertical field of view in degrees. The far plane is infinitely far away
+    /// when `far` is not set.
+    Perspective {
+        fov: f32,
+        near: f32,
+        #[serde(default)]
+        far: Option<f32>,
+    },
+    /// The size of the visible box in front of the camera. The width follows the aspect ratio
+    /// when not set.
+    Orthographic {
+        #[serde(default)]
+        width: Option<f32>,
+        height: f32,
+        depth: f32,
+    },
+}
+
+/// The initial camera controller and pose. Angles are in degrees.
+#[derive(Deserialize)]
+#[serde(default)]
+pub struct CameraController {
+    pub mode: ControllerMode,
+    /// The point orbited around, and that the camera starts looking at.
+    pub target: [f32; 3],
+    pub distance: f32,
+    pub yaw: f32,
+    pub pitch: f32,
+    /// Fly mode speed in units per second, defaults to `distance`.
+    pub speed: Option<f32>,
+    /// Automatic orbit speed in degrees per second, until there is any input.
+    pub spin: f32,
+}
+
+impl Default for CameraController {
+    fn default() -> Self {
+        Self {
+            mode: ControllerMode::Orbit,
+            target: [0.0, 0.0, 0.0],
+            distance: 0.045,
+            yaw: 0.0,
+            pitch: -30.0,
+            speed: None,
+            spin: 17.0,
+        }
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ControllerMode {
+    Orbit,
+    Fly,
+    PanZoom,
 }
 
 #[derive(Deserialize)]
@@ -23,6 +112,54 @@ pub struct Program {
     pub descriptors: Vec<Descriptor>,
     pub vertex: String,
     pub fragment: String,
+    /// The fixed-function state of the program's materials, which they can override.
+    #[serde(default)]
+    pub render_state: RenderState,
+    /// Values set by each material, in a uniform buffer bound in the material's set.
+    #[serde(default)]
+    pub parameters: Option<ParameterBlock>,
+}
+
+/// A uniform block with the std140 layout, so that e.g. a `color` and a `float` parameter are
+/// declared as `layout(set = 1, binding = N) uniform material { vec4 color; float value; };`
+/// with the fields in the same order.
+#[derive(Clone, Deserialize)]
+pub struct ParameterBlock {
+    pub binding: u32,
+    pub stages: Vec<StageType>,
+    pub fields: Vec<Parameter>,
+}
+
+#[derive(Clone, Deserialize)]
+pub struct Parameter {
+    pub name: String,
+    #[serde(rename = "type")]
+    pub ty: ParameterType,
+    /// Used by materials that don't set the parameter. Defaults to zero.
+    #[serde(default)]
+    pub default: Option<ParameterValue>,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ParameterType {
+    Float,
+    Vec2,
+    Vec3,
+    Vec4,
+    /// Column-major, as 16 values.
+    Mat4,
+    Int,
+    /// An sRGB color with optional alpha, converted to a linear `vec4`.
+    Color,
+}
+
+/// A number, or a list of numbers for vector, matrix and color parameters.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(untagged)]
+pub enum ParameterValue {
+    Scalar(f64),
+    List(Vec<f64>),
 }
 
 #[derive(Deserialize)]
@@ -113,6 +250,320 @@ pub struct Material {
     pub id: u32,
     pub program: u32,
     pub textures: Vec<MaterialTexture>,
+    /// Overrides the fields set in the program's render state.
+    #[serde(default)]
+    pub render_state: RenderState,
+    /// Defaults to transparent if the material blends, otherwise opaque.
+    #[serde(default)]
+    pub queue: Option<RenderQueue>,
+    /// Materials with lower priorities are drawn first within their queue.
+    #[serde(default)]
+    pub priority: i32,
+    /// Values for the program's parameters, by name.
+    #[serde(default)]
+    pub parameters: BTreeMap<String, ParameterValue>,
+}
+
+/// Opaque draws are drawn first, sorted front to back and by state, then transparent draws back
+/// to front so they blend over what's behind them.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum RenderQueue {
+    Opaque,
+    Transparent,
+}
+
+/// Fixed-function pipeline state. Unset fields are inherited from the program, then default
+/// to [`Blend::DEFAULT`] blended, back-face culled, counter-clockwise, depth tested and written
+/// triangles. Materials without a blend are still drawn in the opaque queue.
+#[derive(Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct RenderState {
+    pub blend: Option<Blend>,
+    pub cull: Option<CullMode>,
+    pub front_face: Option<FrontFace>,
+    pub depth_test: Option<bool>,
+    pub depth_write: Option<bool>,
+    /// The comparison for standard Z cameras, reversed for reverse-Z cameras.
+    pub depth_compare: Option<CompareOp>,
+    pub polygon_mode: Option<PolygonMode>,
+    pub topology: Option<Topology>,
+    /// Offsets depth, e.g. for decals. Positive values move towards the camera.
+    pub depth_bias: Option<DepthBias>,
+}
+
+impl RenderState {
+    /// The queue materials with this state are drawn in by default.
+    pub fn queue(&self) -> RenderQueue {
+        match self.blend {
+            None | Some(Blend::Opaque) => RenderQueue::Opaque,
+            Some(_) => RenderQueue::Transparent,
+        }
+    }
+
+    /// Returns this state with the fields set in `overrides` replaced.
+    pub fn with(&self, overrides: &RenderState) -> RenderState {
+        RenderState {
+            blend: overrides.blend.or(self.blend),
+            cull: overrides.cull.or(self.cull),
+            front_face: overrides.front_face.or(self.front_face),
+            depth_test: overrides.depth_test.or(self.depth_test),
+            depth_write: overrides.depth_write.or(self.depth_write),
+            depth_compare: overrides.depth_compare.or(self.depth_compare),
+            polygon_mode: overrides.polygon_mode.or(self.polygon_mode),
+            topology: overrides.topology.or(self.topology),
+            depth_bias: overrides.depth_bias.or(self.depth_bias),
+        }
+    }
+}
+
+/// How fragment colors are combined with the color attachment.
+#[derive(Copy, Clone, Debug, PartialEq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Blend {
+    /// Replaces the destination.
+    Opaque,
+    /// Straight alpha: `src * src.a + dst * (1 - src.a)`.
+    Alpha,
+    /// `src * src.a + dst`.
+    Additive,
+    /// Premultiplied alpha: `src + dst * (1 - src.a)`.
+    Premultiplied,
+    Custom {
+        color: BlendEquation,
+        alpha: BlendEquation,
+    },
+}
+
+impl Blend {
+    /// The blending used before it was configurable, for programs and materials that don't set
+    /// one: `src * src + dst * (1 - src.a)`.
+    pub const DEFAULT: Blend = Blend::Custom {
+        color: BlendEquation {
+            src: BlendFactor::SrcColor,
+            dst: BlendFactor::OneMinusSrcAlpha,
+            op: BlendOp::Add,
+        },
+        alpha: BlendEquation {
+            src: BlendFactor::One,
+            dst: BlendFactor::OneMinusSrcAlpha,
+            op: BlendOp::Add,
+        },
+    };
+
+    /// The color and alpha equations, or `None` if blending is disabled.
+    pub fn equations(self) -> Option<(BlendEquation, BlendEquation)> {
+        use BlendFactor::*;
+        let equation = |src, dst| BlendEquation {
+            src,
+            dst,
+            op: BlendOp::Add,
+        };
+        match self {
+            Self::Opaque => None,
+            Self::Alpha => Some((
+                equation(SrcAlpha, OneMinusSrcAlpha),
+                equation(One, OneMinusSrcAlpha),
+            )),
+            Self::Additive => Some((equation(SrcAlpha, One), equation(Zero, One))),
+            Self::Premultiplied => Some((
+                equation(One, OneMinusSrcAlpha),
+                equation(One, OneMinusSrcAlpha),
+            )),
+            Self::Custom { color, alpha } => Some((color, alpha)),
+        }
+    }
+}
+
+/// `src * src_factor <op> dst * dst_factor`.
+#[derive(Copy, Clone, Debug, PartialEq, Deserialize)]
+pub struct BlendEquation {
+    pub src: BlendFactor,
+    pub dst: BlendFactor,
+    #[serde(default)]
+    pub op: BlendOp,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum BlendFactor {
+    Zero,
+    One,
+    SrcColor,
+    OneMinusSrcColor,
+    DstColor,
+    OneMinusDstColor,
+    SrcAlpha,
+    OneMinusSrcAlpha,
+    DstAlpha,
+    OneMinusDstAlpha,
+}
+
+impl From<BlendFactor> for vk::BlendFactor {
+    fn from(factor: BlendFactor) -> Self {
+        match factor {
+            BlendFactor::Zero => Self::ZERO,
+            BlendFactor::One => Self::ONE,
+            BlendFactor::SrcColor => Self::SRC_COLOR,
+            BlendFactor::OneMinusSrcColor => Self::ONE_MINUS_SRC_COLOR,
+            BlendFactor::DstColor => Self::DST_COLOR,
+            BlendFactor::OneMinusDstColor => Self::ONE_MINUS_DST_COLOR,
+            BlendFactor::SrcAlpha => Self::SRC_ALPHA,
+            BlendFactor::OneMinusSrcAlpha => Self::ONE_MINUS_SRC_ALPHA,
+            BlendFactor::DstAlpha => Self::DST_ALPHA,
+            BlendFactor::OneMinusDstAlpha => Self::ONE_MINUS_DST_ALPHA,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum BlendOp {
+    #[default]
+    Add,
+    Subtract,
+    ReverseSubtract,
+    Min,
+    Max,
+}
+
+impl From<BlendOp> for vk::BlendOp {
+    fn from(op: BlendOp) -> Self {
+        match op {
+            BlendOp::Add => Self::ADD,
+            BlendOp::Subtract => Self::SUBTRACT,
+            BlendOp::ReverseSubtract => Self::REVERSE_SUBTRACT,
+            BlendOp::Min => Self::MIN,
+            BlendOp::Max => Self::MAX,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum CullMode {
+    None,
+    Front,
+    Back,
+}
+
+impl From<CullMode> for vk::CullModeFlags {
+    fn from(mode: CullMode) -> Self {
+        match mode {
+            CullMode::None => Self::NONE,
+            CullMode::Front => Self::FRONT,
+            CullMode::Back => Self::BACK,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum FrontFace {
+    CounterClockwise,
+    Clockwise,
+}
+
+impl From<FrontFace> for vk::FrontFace {
+    fn from(face: FrontFace) -> Self {
+        match face {
+            FrontFace::CounterClockwise => Self::COUNTER_CLOCKWISE,
+            FrontFace::Clockwise => Self::CLOCKWISE,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum CompareOp {
+    Never,
+    Less,
+    Equal,
+    LessOrEqual,
+    Greater,
+    NotEqual,
+    GreaterOrEqual,
+    Always,
+}
+
+impl CompareOp {
+    /// The equivalent comparison when depth is reversed.
+    pub fn reversed(self) -> Self {
+        match self {
+            Self::Less => Self::Greater,
+            Self::LessOrEqual => Self::GreaterOrEqual,
+            Self::Greater => Self::Less,
+            Self::GreaterOrEqual => Self::LessOrEqual,
+            op => op,
+        }
+    }
+}
+
+impl From<CompareOp> for vk::CompareOp {
+    fn from(op: CompareOp) -> Self {
+        match op {
+            CompareOp::Never => Self::NEVER,
+            CompareOp::Less => Self::LESS,
+            CompareOp::Equal => Self::EQUAL,
+            CompareOp::LessOrEqual => Self::LESS_OR_EQUAL,
+            CompareOp::Greater => Self::GREATER,
+            CompareOp::NotEqual => Self::NOT_EQUAL,
+            CompareOp::GreaterOrEqual => Self::GREATER_OR_EQUAL,
+            CompareOp::Always => Self::ALWAYS,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum PolygonMode {
+    Fill,
+    Line,
+    Point,
+}
+
+impl From<PolygonMode> for vk::PolygonMode {
+    fn from(mode: PolygonMode) -> Self {
+        match mode {
+            PolygonMode::Fill => Self::FILL,
+            PolygonMode::Line => Self::LINE,
+            PolygonMode::Point => Self::POINT,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Topology {
+    PointList,
+    LineList,
+    LineStrip,
+    TriangleList,
+    TriangleStrip,
+    TriangleFan,
+}
+
+impl From<Topology> for vk::PrimitiveTopology {
+    fn from(topology: Topology) -> Self {
+        match topology {
+            Topology::PointList => Self::POINT_LIST,
+            Topology::LineList => Self::LINE_LIST,
+            Topology::LineStrip => Self::LINE_STRIP,
+            Topology::TriangleList => Self::TRIANGLE_LIST,
+            Topology::TriangleStrip => Self::TRIANGLE_STRIP,
+            Topology::TriangleFan => Self::TRIANGLE_FAN,
+        }
+    }
+}
+
+/// `constant * r + slope * max_slope`, clamped to `clamp` if it's not 0. `r` is the smallest
+/// resolvable depth difference.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct DepthBias {
+    pub constant: f32,
+    pub slope: f32,
+    pub clamp: f32,
 }
 
 #[derive(Deserialize)]
@@ -121,6 +572,48 @@ pub struct MaterialTexture {
     pub texture: u32,
 }
 
+/// A glTF file whose meshes are added to the scene.
+#[derive(Deserialize)]
+pub struct Import {
+    pub path: String,
+    /// glTF materials to render with programs from this scene, by name. Other materials use
+    /// the built-in glTF programs.
+    #[serde(default)]
+    pub materials: Vec<ImportMaterial>,
+}
+
+#[derive(Deserialize)]
+pub struct ImportMaterial {
+    pub name: String,
+    pub program: u32,
+    #[serde(default)]
+    pub locations: ImportTextureLocations,
+    /// Additional textures from this scene, e.g. environment maps.
+    #[serde(default)]
+    pub textures: Vec<MaterialTexture>,
+}
+
+/// Where the program expects each glTF material texture, or `null` to not bind it.
+#[derive(Deserialize)]
+#[serde(default)]
+pub struct ImportTextureLocations {
+    pub base_color: Option<u32>,
+    pub metallic_roughness: Option<u32>,
+    pub normal: Option<u32>,
+    pub emissive: Option<u32>,
+}
+
+impl Default for ImportTextureLocations {
+    fn default() -> Self {
+        Self {
+            base_color: Some(0),
+            metallic_roughness: Some(1),
+            normal: Some(2),
+            emissive: Some(3),
+        }
+    }
+}
+
 #[derive(Deserialize)]
 pub struct TextureFile {
     pub id: u32,
@@ -156,9 +649,74 @@ pub struct File {
     pub path: String,
 }
 
+/// A transform in the scene hierarchy, relative to its parent.
+#[derive(Deserialize)]
+pub struct Node {
+    pub id: u32,
+    #[serde(default)]
+    pub name: Option<String>,
+    #[serde(default)]
+    pub parent: Option<u32>,
+    #[serde(default)]
+    pub transform: Transform,
+    /// Meshes positioned by this node. Meshes not attached to any node are positioned by their
+    /// own transform alone.
+    #[serde(default)]
+    pub meshes: Vec<u32>,
+}
+
+#[derive(Deserialize)]
+pub struct Animation {
+    #[serde(default)]
+    pub name: Option<String>,
+    pub channels: Vec<AnimationChannel>,
+}
+
+/// Keyframes for one property of a node, in the same layout as glTF animation samplers.
+#[derive(Deserialize)]
+pub struct AnimationChannel {
+    pub node: u32,
+    pub path: AnimationPath,
+    #[serde(default)]
+    pub interpolation: Interpolation,
+    /// Keyframe times in seconds.
+    pub times: Vec<f32>,
+    /// Keyframe values, flattened. Cubic spline keyframes have an in-tangent, value and
+    /// out-tangent each.
+    pub values: Vec<f32>,
+}
+
+#[derive(Copy, Clone, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum AnimationPath {
+    Translation,
+    Rotation,
+    Scale,
+}
+
+impl AnimationPath {
+    pub fn components(self) -> usize {
+        match self {
+            Self::Translation | Self::Scale => 3,
+            Self::Rotation => 4,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Default, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Interpolation {
+    Step,
+    #[default]
+    Linear,
+    CubicSpline,
+}
+
 #[derive(Deserialize)]
 pub struct Mesh {
-    // pub id: u32,
+    /// Only needed to attach the mesh to a node.
+    #[serde(default)]
+    pub id: Option<u32>,
     // pub name: String,
     #[serde(default)]
     pub transform: Transform,
@@ -0,0 +1,954 @@
+//! Loads glTF 2.0 files (`.gltf` and `.glb`) into scene definitions.
+//!
+//! Each glTF node becomes a scene node, with a scene mesh attached for each primitive.
+//! Animations of node translation, rotation and scale are imported, others are ignored.
+//!
+//! Vertex attributes are bound by name to the bindings in `ATTRIBUTE_BINDINGS`, which match
+//! the layout used by the programs in `assets/scene.yaml`. Materials use the program given by
+//! the import, or else one of the built-in programs from `default_program()`, which only use
+//! the base color texture and factor.
+
+use std::collections::BTreeMap;
+use std::io::{Read, Seek};
+use std::path::Path;
+
+use serde::Deserialize;
+
+use crate::error::*;
+
+use super::definition;
+
+const ATTRIBUTE_BINDINGS: &[(&str, u32)] = &[
+    ("POSITION", 0),
+    ("NORMAL", 1),
+    ("TANGENT", 2),
+    ("TEXCOORD_0", 3),
+];
+
+const GLB_MAGIC: u32 = 0x4654_6c67; // "glTF"
+const GLB_CHUNK_JSON: u32 = 0x4e4f_534a; // "JSON"
+const GLB_CHUNK_BIN: u32 = 0x004e_4942; // "BIN\0"
+
+const COMPONENT_U8: u32 = 5121;
+const COMPONENT_U16: u32 = 5123;
+const COMPONENT_U32: u32 = 5125;
+const COMPONENT_F32: u32 = 5126;
+const MODE_TRIANGLES: u32 = 4;
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Document {
+    #[serde(default)]
+    scene: Option<usize>,
+    #[serde(default)]
+    scenes: Vec<SceneRoot>,
+    #[serde(default)]
+    nodes: Vec<Node>,
+    #[serde(default)]
+    meshes: Vec<Mesh>,
+    #[serde(default)]
+    materials: Vec<Material>,
+    #[serde(default)]
+    textures: Vec<Texture>,
+    #[serde(default)]
+    images: Vec<Image>,
+    #[serde(default)]
+    accessors: Vec<Accessor>,
+    #[serde(default)]
+    buffer_views: Vec<BufferView>,
+    #[serde(default)]
+    buffers: Vec<Buffer>,
+    #[serde(default)]
+    animations: Vec<Animation>,
+}
+
+#[derive(Deserialize)]
+struct SceneRoot {
+    #[serde(default)]
+    nodes: Vec<usize>,
+}
+
+#[derive(Deserialize)]
+struct Node {
+    #[serde(default)]
+    name: Option<String>,
+    #[serde(default)]
+    children: Vec<usize>,
+    #[serde(default)]
+    mesh: Option<usize>,
+    #[serde(default)]
+    matrix: Option<[f32; 16]>,
+    #[serde(default)]
+    translation: Option<[f32; 3]>,
+    #[serde(default)]
+    rotation: Option<[f32; 4]>,
+    #[serde(default)]
+    scale: Option<[f32; 3]>,
+}
+
+#[derive(Deserialize)]
+struct Mesh {
+    primitives: Vec<Primitive>,
+}
+
+#[derive(Deserialize)]
+struct Primitive {
+    attributes: BTreeMap<String, usize>,
+    #[serde(default)]
+    indices: Option<usize>,
+    #[serde(default)]
+    material: Option<usize>,
+    #[serde(default)]
+    mode: Option<u32>,
+}
+
+#[derive(Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Material {
+    #[serde(default)]
+    name: Option<String>,
+    #[serde(default)]
+    pbr_metallic_roughness: PbrMetallicRoughness,
+    #[serde(default)]
+    normal_texture: Option<TextureInfo>,
+    #[serde(default)]
+    emissive_texture: Option<TextureInfo>,
+    #[serde(default)]
+    alpha_mode: AlphaMode,
+    #[serde(default)]
+    double_sided: bool,
+}
+
+#[derive(Copy, Clone, PartialEq, Default, Deserialize)]
+#[serde(rename_all = "UPPERCASE")]
+enum AlphaMode {
+    #[default]
+    Opaque,
+    /// Alpha testing isn't supported by the default programs, so it's treated as opaque.
+    Mask,
+    Blend,
+}
+
+#[derive(Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct PbrMetallicRoughness {
+    #[serde(default)]
+    base_color_factor: Option<[f64; 4]>,
+    #[serde(default)]
+    base_color_texture: Option<TextureInfo>,
+    #[serde(default)]
+    metallic_factor: Option<f64>,
+    #[serde(default)]
+    roughness_factor: Option<f64>,
+    #[serde(default)]
+    metallic_roughness_texture: Option<TextureInfo>,
+}
+
+#[derive(Deserialize)]
+struct TextureInfo {
+    index: usize,
+}
+
+#[derive(Deserialize)]
+struct Texture {
+    #[serde(default)]
+    source: Option<usize>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Image {
+    #[serde(default)]
+    uri: Option<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Accessor {
+    #[serde(default)]
+    buffer_view: Option<usize>,
+    #[serde(default)]
+    byte_offset: u64,
+    component_type: u32,
+    count: u64,
+    #[serde(rename = "type")]
+    ty: String,
+    #[serde(default)]
+    sparse: Option<serde_json::Value>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct BufferView {
+    buffer: usize,
+    #[serde(default)]
+    byte_offset: u64,
+    byte_length: u64,
+    #[serde(default)]
+    byte_stride: Option<u64>,
+}
+
+#[derive(Deserialize)]
+struct Animation {
+    #[serde(default)]
+    name: Option<String>,
+    channels: Vec<AnimationChannel>,
+    samplers: Vec<AnimationSampler>,
+}
+
+#[derive(Deserialize)]
+struct AnimationChannel {
+    sampler: usize,
+    target: AnimationTarget,
+}
+
+#[derive(Deserialize)]
+struct AnimationTarget {
+    #[serde(default)]
+    node: Option<usize>,
+    path: String,
+}
+
+#[derive(Deserialize)]
+struct AnimationSampler {
+    input: usize,
+    output: usize,
+    #[serde(default)]
+    interpolation: Option<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Buffer {
+    #[serde(default)]
+    uri: Option<String>,
+}
+
+pub fn load(path: &Path) -> Result<definition::Scene> {
+    let mut scene = definition::Scene::default();
+    import(
+        &mut scene,
+        &definition::Import {
+            path: path.to_string_lossy().into_owned(),
+            materials: vec![],
+        },
+    )?;
+    Ok(scene)
+}
+
+/// Adds the contents of a glTF file to a scene, using ids after those already in the scene.
+pub fn import(scene: &mut definition::Scene, import: &definition::Import) -> Result<()> {
+    let path = Path::new(&import.path);
+    let mut data = vec![];
+    std::fs::File::open(path)?.read_to_end(&mut data)?;
+
+    let (json, bin_offset) = if data.starts_with(&GLB_MAGIC.to_le_bytes()) {
+        split_glb(&data)?
+    } else {
+        (strip_comments(&data), None)
+    };
+    let document: Document = serde_json::from_slice(&json)?;
+
+    Loader {
+        path,
+        import,
+        document: &document,
+        bin_offset,
+        scene,
+        buffers: vec![],
+        nodes: BTreeMap::new(),
+        textures: BTreeMap::new(),
+        materials: BTreeMap::new(),
+        default_programs: BTreeMap::new(),
+    }
+    .load()
+}
+
+struct Loader<'a> {
+    path: &'a Path,
+    import: &'a definition::Import,
+    document: &'a Document,
+    bin_offset: Option<u64>,
+    scene: &'a mut definition::Scene,
+    // glTF indices to definition ids.
+    buffers: Vec<u32>,
+    nodes: BTreeMap<usize, u32>,
+    textures: BTreeMap<(usize, bool), u32>,
+    materials: BTreeMap<Option<usize>, u32>,
+    // Built-in program ids, by whether they are textured.
+    default_programs: BTreeMap<bool, u32>,
+}
+
+impl Loader<'_> {
+    fn load(mut self) -> Result<()> {
+        let document = self.document;
+
+        for (index, buffer) in document.buffers.iter().enumerate() {
+            let path = match (&buffer.uri, self.bin_offset) {
+                (Some(uri), _) => self.resolve(uri)?,
+                (None, Some(_)) if index == 0 => self.import.path.clone(),
+                (None, _) => return Err(unsupported("buffer without a uri")),
+            };
+            let id = next_id(self.scene.buffers.iter().map(|b| b.id));
+            self.scene.buffers.push(definition::File { id, path });
+            self.buffers.push(id);
+        }
+
+        let roots = match document.scenes.get(document.scene.unwrap_or(0)) {
+            Some(scene) => scene.nodes.clone(),
+            None => {
+                // No scenes, so render every node that isn't a child of another node.
+                let children = document
+                    .nodes
+                    .iter()
+                    .flat_map(|n| n.children.iter().copied())
+                    .collect::<Vec<_>>();
+                (0..document.nodes.len())
+                    .filter(|i| !children.contains(i))
+                    .collect()
+            }
+        };
+        for root in roots {
+            self.load_node(root, None)?;
+        }
+
+        for animation in &document.animations {
+            self.load_animation(animation)?;
+        }
+
+        Ok(())
+    }
+
+    fn load_animation(&mut self, animation: &Animation) -> Result<()> {
+        let mut channels = vec![];
+        for channel in &animation.channels {
+            let path = match channel.target.path.as_str() {
+                "translation" => definition::AnimationPath::Translation,
+                "rotation" => definition::AnimationPath::Rotation,
+                "scale" => definition::AnimationPath::Scale,
+                _ => continue,
+            };
+            // Skip nodes that aren't in the loaded scene.
+            let node = match channel.target.node.and_then(|n| self.nodes.get(&n)) {
+                Some(&node) => node,
+                None => continue,
+            };
+            let sampler = animation
+                .samplers
+                .get(channel.sampler)
+                .ok_or_else(|| invalid("animation sampler", channel.sampler))?;
+            let interpolation = match sampler.interpolation.as_deref() {
+                None | Some("LINEAR") => definition::Interpolation::Linear,
+                Some("STEP") => definition::Interpolation::Step,
+                Some("CUBICSPLINE") => definition::Interpolation::CubicSpline,
+                Some(other) => {
+                    return Err(Error::Gltf(format!("invalid interpolation {:?}", other)))
+                }
+            };
+            channels.push(definition::AnimationChannel {
+                node,
+                path,
+                interpolation,
+                times: self.read_floats(sampler.input)?,
+                values: self.read_floats(sampler.output)?,
+            });
+        }
+
+        if !channels.is_empty() {
+            self.scene.animations.push(definition::Animation {
+                name: animation.name.clone(),
+                channels,
+            });
+        }
+        Ok(())
+    }
+
+    fn load_node(&mut self, index: usize, parent: Option<u32>) -> Result<()> {
+        let document = self.document;
+        let node = document
+            .nodes
+            .get(index)
+            .ok_or_else(|| invalid("node", index))?;
+
+        let transform = match node.matrix {
+            Some(m) => definition::Transform::Literal([
+                [m[0], m[1], m[2], m[3]],
+                [m[4], m[5], m[6], m[7]],
+                [m[8], m[9], m[10], m[11]],
+                [m[12], m[13], m[14], m[15]],
+            ]),
+            None => definition::Transform::TRS {
+                translation: node.translation,
+                rotation: node.rotation,
+                scale: node.scale,
+            },
+        };
+
+        let mut meshes = vec![];
+        if let Some(mesh) = node.mesh {
+            let mesh = document
+                .meshes
+                .get(mesh)
+                .ok_or_else(|| invalid("mesh", mesh))?;
+            for primitive in &mesh.primitives {
+                meshes.push(self.load_primitive(primitive)?);
+            }
+        }
+
+        let id = next_id(self.scene.nodes.iter().map(|n| n.id));
+        self.nodes.insert(index, id);
+        self.scene.nodes.push(definition::Node {
+            id,
+            name: node.name.clone(),
+            parent,
+            transform,
+            meshes,
+        });
+
+        for &child in &node.children {
+            self.load_node(child, Some(id))?;
+        }
+        Ok(())
+    }
+
+    fn load_primitive(&mut self, primitive: &Primitive) -> Result<u32> {
+        if primitive.mode.unwrap_or(MODE_TRIANGLES) != MODE_TRIANGLES {
+            return Err(unsupported("primitives other than triangle lists"));
+        }
+
+        let mut bindings = vec![];
+        for &(name, binding) in ATTRIBUTE_BINDINGS {
+            if let Some(&accessor) = primitive.attributes.get(name) {
+                bindings.push(definition::MeshBinding {
+                    binding,
+                    view: self.accessor_view(accessor)?,
+                });
+            }
+        }
+
+        let indices = primitive
+            .indices
+            .ok_or_else(|| unsupported("primitives without indices"))?;
+        let accessor = self.accessor(indices)?;
+        let format = match accessor.component_type {
+            COMPONENT_U16 => definition::MeshIndexFormat::U16,
+            COMPONENT_U32 => definition::MeshIndexFormat::U32,
+            COMPONENT_U8 => return Err(unsupported("8-bit indices")),
+            other => return Err(invalid("index component type", other as usize)),
+        };
+        let indices = definition::MeshIndices {
+            count: accessor.count as u32,
+            format,
+            view: self.accessor_view(indices)?,
+        };
+
+        let material = self.material(primitive.material)?;
+        self.check_vertex_input(material, &bindings)?;
+
+        let id = next_id(self.scene.meshes.iter().filter_map(|m| m.id));
+        self.scene.meshes.push(definition::Mesh {
+            id: Some(id),
+            transform: definition::Transform::Identity,
+            material,
+            bindings,
+            indices,
+        });
+        Ok(id)
+    }
+
+    /// Drawing with a vertex binding that isn't bound is undefined, so reject primitives missing
+    /// an attribute their material's program reads.
+    fn check_vertex_input(
+        &self,
+        material: u32,
+        bindings: &[definition::MeshBinding],
+    ) -> Result<()> {
+        let program = self
+            .scene
+            .materials
+            .iter()
+            .find(|m| m.id == material)
+            .and_then(|m| self.scene.programs.iter().find(|p| p.id == m.program));
+        let program = match program {
+            Some(program) => program,
+            None => return Ok(()),
+        };
+        for input in &program.vertex_input {
+            if bindings.iter().any(|b| b.binding == input.binding) {
+                continue;
+            }
+            let attribute = match ATTRIBUTE_BINDINGS
+                .iter()
+                .find(|&&(_, b)| b == input.binding)
+            {
+                Some((name, _)) => name.to_string(),
+                None => format!("binding {}", input.binding),
+            };
+            return Err(Error::Gltf(format!(
+                "primitive has no {} attribute, which program {} needs",
+                attribute, program.id
+            )));
+        }
+        Ok(())
+    }
+
+    fn material(&mut self, index: Option<usize>) -> Result<u32> {
+        if let Some(&id) = self.materials.get(&index) {
+            return Ok(id);
+        }
+
+        let default_material = Material::default();
+        let material = match index {
+            None => &default_material,
+            Some(index) => self
+                .document
+                .materials
+                .get(index)
+                .ok_or_else(|| invalid("material", index))?,
+        };
+        let import = self.import;
+        let mapping = material
+            .name
+            .as_ref()
+            .and_then(|name| import.materials.iter().find(|m| &m.name == name));
+
+        let default_locations = definition::ImportTextureLocations {
+            base_color: Some(0),
+            metallic_roughness: None,
+            normal: None,
+            emissive: None,
+        };
+        let locations = mapping.map_or(&default_locations, |m| &m.locations);
+
+        let pbr = &material.pbr_metallic_roughness;
+        let roles = [
+            (&pbr.base_color_texture, locations.base_color, true),
+            (
+                &pbr.metallic_roughness_texture,
+                locations.metallic_roughness,
+                false,
+            ),
+            (&material.normal_texture, locations.normal, false),
+            (&material.emissive_texture, locations.emissive, true),
+        ];
+
+        let mut textures = vec![];
+        for (info, location, srgb) in roles.iter() {
+            if let (Some(info), Some(location)) = (info, location) {
+                textures.push(definition::MaterialTexture {
+                    location: *location,
+                    texture: self.texture(info.index, *srgb)?,
+                });
+            }
+        }
+
+        let program = match mapping {
+            Some(mapping) => {
+                textures.extend(
+                    mapping
+                        .textures
+                        .iter()
+                        .map(|t| definition::MaterialTexture {
+                            location: t.location,
+                            texture: t.texture,
+                        }),
+                );
+                mapping.program
+            }
+            None => self.default_program(!textures.is_empty()),
+        };
+
+        // The factors set the program's parameters of the same name and type, if it has them.
+        let factors = [
+            (
+                "base_color_factor",
+                definition::ParameterType::Vec4,
+                pbr.base_color_factor
+                    .map(|factor| definition::ParameterValue::List(factor.to_vec())),
+            ),
+            (
+                "metallic_factor",
+                definition::ParameterType::Float,
+                pbr.metallic_factor.map(definition::ParameterValue::Scalar),
+            ),
+            (
+                "roughness_factor",
+                definition::ParameterType::Float,
+                pbr.roughness_factor.map(definition::ParameterValue::Scalar),
+            ),
+        ];
+        let block = self
+            .scene
+            .programs
+            .iter()
+            .find(|p| p.id == program)
+            .and_then(|p| p.parameters.as_ref());
+        let mut parameters = BTreeMap::new();
+        for (name, ty, value) in factors {
+            if let (Some(block), Some(value)) = (block, value) {
+                if block.fields.iter().any(|f| f.name == name && f.ty == ty) {
+                    parameters.insert(name.to_string(), value);
+                }
+            }
+        }
+
+        let id = next_id(self.scene.materials.iter().map(|m| m.id));
+        // Only what the glTF material asks for overrides the program's render state.
+        let mut render_state = definition::RenderState::default();
+        if material.alpha_mode == AlphaMode::Blend {
+            render_state.blend = Some(definition::Blend::Alpha);
+            render_state.depth_write = Some(false);
+        }
+        if material.double_sided {
+            render_state.cull = Some(definition::CullMode::None);
+        }
+        self.scene.materials.push(definition::Material {
+            id,
+            program,
+            textures,
+            render_state,
+            queue: None,
+            priority: 0,
+            parameters,
+        });
+        self.materials.insert(index, id);
+        Ok(id)
+    }
+
+    fn default_program(&mut self, textured: bool) -> u32 {
+        if let Some(&id) = self.default_programs.get(&textured) {
+            return id;
+        }
+        let id = next_id(self.scene.programs.iter().map(|p| p.id));
+        self.scene.programs.push(default_program(id, textured));
+        self.default_programs.insert(textured, id);
+        id
+    }
+
+    fn texture(&mut self, index: usize, srgb: bool) -> Result<u32> {
+        if let Some(&id) = self.textures.get(&(index, srgb)) {
+            return Ok(id);
+        }
+
+        let image = self
+            .document
+            .textures
+            .get(index)
+            .ok_or_else(|| invalid("texture", index))?
+            .source
+            .ok_or_else(|| unsupported("textures without a source image"))?;
+        let uri = self
+            .document
+            .images
+            .get(image)
+            .ok_or_else(|| invalid("image", image))?
+            .uri
+            .as_ref()
+            .ok_or_else(|| unsupported("images embedded in buffers"))?;
+        let format = match Path::new(uri).extension().and_then(|e| e.to_str()) {
+            Some("png") => definition::TextureFormat::Png,
+            Some("ktx2") => definition::TextureFormat::Ktx,
+            _ => return Err(unsupported("images other than png or ktx2")),
+        };
+
+        let id = next_id(self.scene.textures.iter().map(|t| t.id));
+        let path = self.resolve(uri)?;
+        self.scene.textures.push(definition::TextureFile {
+            id,
+            format,
+            space: if srgb {
+                definition::TextureColorSpace::Srgb
+            } else {
+                definition::TextureColorSpace::Linear
+            },
+            path,
+        });
+        self.textures.insert((index, srgb), id);
+        Ok(id)
+    }
+
+    fn accessor(&self, index: usize) -> Result<&Accessor> {
+        self.document
+            .accessors
+            .get(index)
+            .ok_or_else(|| invalid("accessor", index))
+    }
+
+    /// Reads a float accessor, for data that is needed when loading rather than on the GPU.
+    fn read_floats(&self, index: usize) -> Result<Vec<f32>> {
+        if self.accessor(index)?.component_type != COMPONENT_F32 {
+            return Err(unsupported("non-float animation data"));
+        }
+        let view = self.accessor_view(index)?;
+        let path = &self
+            .scene
+            .buffers
+            .iter()
+            .find(|b| b.id == view.buffer)
+            .ok_or_else(|| invalid("buffer", view.buffer as usize))?
+            .path;
+
+        let mut file = std::fs::File::open(path)?;
+        file.seek(std::io::SeekFrom::Start(view.offset))?;
+        let mut data = vec![0; view.size as usize];
+        file.read_exact(&mut data)?;
+        Ok(data
+            .chunks_exact(4)
+            .map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+            .collect())
+    }
+
+    fn accessor_view(&self, index: usize) -> Result<definition::BufferView> {
+        let accessor = self.accessor(index)?;
+        if accessor.sparse.is_some() {
+            return Err(unsupported("sparse accessors"));
+        }
+        let view_index = accessor
+            .buffer_view
+            .ok_or_else(|| unsupported("accessors without a buffer view"))?;
+        let view = self
+            .document
+            .buffer_views
+            .get(view_index)
+            .ok_or_else(|| invalid("buffer view", view_index))?;
+
+        let element_size =
+            component_size(accessor.component_type)? * component_count(&accessor.ty)?;
+        if matches!(view.byte_stride, Some(stride) if stride != element_size) {
+            return Err(unsupported("interleaved buffer views"));
+        }
+
+        let base = match self.bin_offset {
+            Some(offset) if view.buffer == 0 => offset,
+            _ => 0,
+        };
+        let size = accessor.count * element_size;
+        if accessor.byte_offset + size > view.byte_length {
+            return Err(invalid("accessor", index));
+        }
+
+        Ok(definition::BufferView {
+            buffer: *self
+                .buffers
+                .get(view.buffer)
+                .ok_or_else(|| invalid("buffer", view.buffer))?,
+            offset: base + view.byte_offset + accessor.byte_offset,
+            size,
+        })
+    }
+
+    /// Resolves a uri relative to the glTF file into a path for the scene definition.
+    fn resolve(&self, uri: &str) -> Result<String> {
+        if uri.starts_with("data:") {
+            return Err(unsupported("data uris"));
+        }
+        let dir = self.path.parent().unwrap_or_else(|| Path::new(""));
+        Ok(dir
+            .join(uri.replace("%20", " "))
+            .to_string_lossy()
+            .into_owned())
+    }
+}
+
+/// Returns the JSON chunk and the file offset of the binary chunk of a `.glb` file.
+fn split_glb(data: &[u8]) -> Result<(Vec<u8>, Option<u64>)> {
+    let read_u32 = |offset: usize| -> Result<u32> {
+        let bytes = data
+            .get(offset..offset + 4)
+            .ok_or_else(|| Error::Gltf("truncated glb file".to_string()))?;
+        Ok(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    };
+
+    if read_u32(4)? != 2 {
+        return Err(unsupported("glb versions other than 2"));
+    }
+
+    let mut json = None;
+    let mut bin_offset = None;
+    let mut offset = 12;
+    while offset < data.len() {
+        let length = read_u32(offset)? as usize;
+        let kind = read_u32(offset + 4)?;
+        let start = offset + 8;
+        let chunk = data
+            .get(start..start + length)
+            .ok_or_else(|| Error::Gltf("truncated glb file".to_string()))?;
+        match kind {
+            GLB_CHUNK_JSON => json = Some(chunk.to_vec()),
+            GLB_CHUNK_BIN => bin_offset = Some(start as u64),
+            _ => {}
+        }
+        offset = start + length;
+    }
+
+    let json = json.ok_or_else(|| Error::Gltf("glb file has no JSON chunk".to_string()))?;
+    Ok((json, bin_offset))
+}
+
+/// Removes `//` line comments, which some hand-edited files (including our assets) contain.
+fn strip_comments(data: &[u8]) -> Vec<u8> {
+    let mut result = Vec::with_capacity(data.len());
+    let mut in_string = false;
+    let mut escaped = false;
+    let mut iter = data.iter().copied().peekable();
+    while let Some(c) = iter.next() {
+        if in_string {
+            if escaped {
+                escaped = false;
+            } else if c == b'\\' {
+                escaped = true;
+            } else if c == b'"' {
+                in_string = false;
+            }
+        } else if c == b'"' {
+            in_string = true;
+        } else if c == b'/' && iter.peek() == Some(&b'/') {
+            while iter.next_if(|&c| c != b'\n').is_some() {}
+            continue;
+        }
+        result.push(c);
+    }
+    result
+}
+
+fn component_size(component_type: u32) -> Result<u64> {
+    match component_type {
+        5120 | 5121 => Ok(1),
+        5122 | 5123 => Ok(2),
+        5125 | 5126 => Ok(4),
+        other => Err(invalid("component type", other as usize)),
+    }
+}
+
+fn component_count(ty: &str) -> Result<u64> {
+    match ty {
+        "SCALAR" => Ok(1),
+        "VEC2" => Ok(2),
+        "VEC3" => Ok(3),
+        "VEC4" => Ok(4),
+        "MAT2" => Ok(4),
+        "MAT3" => Ok(9),
+        "MAT4" => Ok(16),
+        _ => Err(Error::Gltf(format!("invalid accessor type {:?}", ty))),
+    }
+}
+
+fn next_id(ids: impl Iterator<Item = u32>) -> u32 {
+    ids.max().unwrap_or(0) + 1
+}
+
+fn invalid(what: &str, index: usize) -> Error {
+    Error::Gltf(format!("invalid {} {}", what, index))
+}
+
+fn unsupported(what: &str) -> Error {
+    Error::Gltf(format!("{} are not supported", what))
+}
+
+/// A simple lit program for glTF materials, using the position, normal and (if textured) uv
+/// bindings.
+fn default_program(id: u32, textured: bool) -> definition::Program {
+    let mut vertex_input = vec![
+        vertex_binding(0, definition::AttributeFormat::Vec3, 12),
+        vertex_binding(1, definition::AttributeFormat::Vec3, 12),
+    ];
+    let mut descriptors = vec![];
+    let mut vertex = DEFAULT_VERTEX.to_string();
+    let mut fragment = DEFAULT_FRAGMENT.to_string();
+    if textured {
+        vertex_input.push(vertex_binding(3, definition::AttributeFormat::Vec2, 8));
+        descriptors.push(definition::Descriptor {
+            binding: 0,
+            ty: definition::DescriptorType::Texture,
+            stages: vec![definition::StageType::Fragment],
+        });
+        vertex = vertex.replace("#define UV 0", "#define UV 1");
+        fragment = fragment.replace("#define UV 0", "#define UV 1");
+    }
+
+    definition::Program {
+        id,
+        vertex_input,
+        descriptors,
+        vertex,
+        fragment,
+        render_state: Default::default(),
+        parameters: Some(definition::ParameterBlock {
+            binding: 1,
+            stages: vec![definition::StageType::Fragment],
+            fields: vec![definition::Parameter {
+                name: "base_color_factor".to_string(),
+                ty: definition::ParameterType::Vec4,
+                default: Some(definition::ParameterValue::List(vec![1.0; 4])),
+            }],
+        }),
+    }
+}
+
+fn vertex_binding(
+    binding: u32,
+    format: definition::AttributeFormat,
+    stride: u32,
+) -> definition::VertexInputBinding {
+    definition::VertexInputBinding {
+        binding,
+        stride,
+        attributes: vec![definition::VertexAttribute {
+            location: binding,
+            offset: 0,
+            format,
+        }],
+    }
+}
+
+const DEFAULT_VERTEX: &str = "\
+#version 460
+#define UV 0
+layout(set = 0, binding = 0) uniform view {
+  mat4 u_view;
+  mat4 u_proj;
+};
+layout(push_constant) uniform draw { mat4 u_model; };
+layout(location = 0) in vec3 a_position;
+layout(location = 1) in vec3 a_normal;
+#if UV
+layout(location = 3) in vec2 a_uv;
+layout(location = 1) out vec2 v_uv;
+#endif
+layout(location = 0) out vec3 v_normal;
+
+void main() {
+  gl_Position = u_proj * u_view * u_model * vec4(a_position, 1);
+  v_normal = mat3(u_model) * a_normal;
+#if UV
+  v_uv = a_uv;
+#endif
+}
+";
+
+const DEFAULT_FRAGMENT: &str = "\
+#version 460
+#define UV 0
+#if UV
+layout(set = 1, binding = 0) uniform sampler2D u_baseColor;
+layout(location = 1) in vec2 v_uv;
+#endif
+layout(set = 1, binding = 1) uniform material { vec4 base_color_factor; };
+layout(location = 0) in vec3 v_normal;
+layout(location = 0) out vec4 o_color;
+
+const vec3 light_direction = normalize(vec3(1, 1, 0));
+
+#include <output.glsl>
+
+void main() {
+#if UV
+  vec4 baseColor = texture(u_baseColor, v_uv);
+#else
+  vec4 baseColor = vec4(1);
+#endif
+  baseColor *= base_color_factor;
+  float light = 0.3 + 0.7 * max(0, dot(normalize(v_normal), light_direction));
+  o_color = vec4(encode_output(baseColor.rgb * light), baseColor.a);
+}
+";
@@ -1,12 +1,15 @@
-use ash::prelude::VkResult;
+use std::sync::Arc;
+
 use ash::vk;
 
+use crate::context::Context;
 use crate::device::{self, AsRawHandle};
 use crate::error::*;
 use crate::math::Mat4;
 use crate::resources;
 
 use super::definition;
+use super::parameters::ParameterLayout;
 
 pub struct MaterialProgram {
     pub cache: device::PipelineCache,
@@ -16,17 +19,20 @@ pub struct MaterialProgram {
     pub fs: resources::Shader,
     pub vertex_binding_descriptions: Vec<vk::VertexInputBindingDescription>,
     pub vertex_attribute_descriptions: Vec<vk::VertexInputAttributeDescription>,
+    pub render_state: definition::RenderState,
+    pub parameters: Option<ParameterLayout>,
 }
 
 impl MaterialProgram {
     pub fn create(
+        context: &Arc<Context>,
         definition: &definition::Program,
         compiler: &mut resources::Compiler,
         view_descriptors_layout: vk::DescriptorSetLayout,
     ) -> Result<Self> {
-        let cache = device::PipelineCache::create()?;
+        let cache = device::PipelineCache::create(context)?;
 
-        let mut layout_builder = device::DescriptorSetLayout::builder();
+        let mut layout_builder = device::DescriptorSetLayout::builder(context);
         for descriptor in &definition.descriptors {
             layout_builder = layout_builder.add_basic(
                 descriptor.binding,
@@ -38,9 +44,29 @@ impl MaterialProgram {
             );
         }
 
+        let parameters = match &definition.parameters {
+            Some(block) => {
+                if definition
+                    .descriptors
+                    .iter()
+                    .any(|d| d.binding == block.binding)
+                {
+                    return Err(Error::Definition(format!(
+                        "parameters use binding {} of another descriptor",
+                        block.binding
+                    )));
+                }
+                let layout = ParameterLayout::new(block)?;
+                layout_builder = layout_builder.add_uniform_buffer(layout.binding, layout.stages);
+                Some(layout)
+            }
+            None => None,
+        };
+
         let descriptors_layout = layout_builder.build()?;
 
         let pipeline_layout = device::PipelineLayout::create(
+            context,
             &[view_descriptors_layout, descriptors_layout.as_raw()],
             &[vk::PushConstantRange {
                 stage_flags: vk::ShaderStageFlags::VERTEX | vk::ShaderStageFlags::FRAGMENT,
@@ -89,16 +115,69 @@ impl MaterialProgram {
             fs,
             vertex_binding_descriptions,
             vertex_attribute_descriptions,
+            render_state: definition.render_state.clone(),
+            parameters,
         })
     }
 
+    /// Creates a pipeline with the program's render state, overridden by `material`'s.
+    /// The depth bias clamp is ignored if the device doesn't support it.
     pub fn create_material_pipeline(
         &self,
         render_pass: vk::RenderPass,
         samples: vk::SampleCountFlags,
-    ) -> VkResult<device::Pipeline> {
+        material: u32,
+        overrides: &definition::RenderState,
+        reverse_z: bool,
+    ) -> Result<device::Pipeline> {
+        let state = self.render_state.with(overrides);
+        let features = &self.cache.context().features;
+
+        let polygon_mode = state.polygon_mode.unwrap_or(definition::PolygonMode::Fill);
+        if polygon_mode != definition::PolygonMode::Fill
+            && features.fill_mode_non_solid == vk::FALSE
+        {
+            return Err(Error::Definition(format!(
+                "material {} uses polygon mode {:?}, but the device doesn't support \
+                 fillModeNonSolid",
+                material, polygon_mode
+            )));
+        }
+
+        // Depth is flipped for reverse-Z, so the comparison and bias are too.
+        let mut depth_compare = state
+            .depth_compare
+            .unwrap_or(definition::CompareOp::LessOrEqual);
+        let mut depth_bias = state.depth_bias.unwrap_or_default();
+        if reverse_z {
+            depth_compare = depth_compare.reversed();
+            depth_bias.constant = -depth_bias.constant;
+            depth_bias.slope = -depth_bias.slope;
+            depth_bias.clamp = -depth_bias.clamp;
+        }
+        if features.depth_bias_clamp == vk::FALSE {
+            depth_bias.clamp = 0.0;
+        }
+
+        let blend = state.blend.unwrap_or(definition::Blend::DEFAULT);
+        let color_blend_attachment = match blend.equations() {
+            None => vk::PipelineColorBlendAttachmentState::builder()
+                .color_write_mask(vk::ColorComponentFlags::all())
+                .build(),
+            Some((color, alpha)) => vk::PipelineColorBlendAttachmentState::builder()
+                .color_write_mask(vk::ColorComponentFlags::all())
+                .blend_enable(true)
+                .src_color_blend_factor(color.src.into())
+                .dst_color_blend_factor(color.dst.into())
+                .color_blend_op(color.op.into())
+                .src_alpha_blend_factor(alpha.src.into())
+                .dst_alpha_blend_factor(alpha.dst.into())
+                .alpha_blend_op(alpha.op.into())
+                .build(),
+        };
+
         let name = std::ffi::CString::new("main").unwrap();
-        self.cache.create_pipeline(
+        Ok(self.cache.create_pipeline(
             &vk::GraphicsPipelineCreateInfo::builder()
                 .stages(&[
                     vk::PipelineShaderStageCreateInfo::builder()
@@ -119,7 +198,12 @@ impl MaterialProgram {
                 )
                 .input_assembly_state(
                     &vk::PipelineInputAssemblyStateCreateInfo::builder()
-                        .topology(vk::PrimitiveTopology::TRIANGLE_LIST)
+                        .topology(
+                            state
+                                .topology
+                                .unwrap_or(definition::Topology::TriangleList)
+                                .into(),
+                        )
                         .build(),
                 )
                 .viewport_state(
@@ -131,15 +215,25 @@ impl MaterialProgram {
                 .rasterization_state(
                     &vk::PipelineRasterizationStateCreateInfo::builder()
                         .line_width(1.0)
-                        .front_face(vk::FrontFace::COUNTER_CLOCKWISE)
-                        .cull_mode(vk::CullModeFlags::BACK)
+                        .polygon_mode(polygon_mode.into())
+                        .front_face(
+                            state
+                                .front_face
+                                .unwrap_or(definition::FrontFace::CounterClockwise)
+                                .into(),
+                        )
+                        .cull_mode(state.cull.unwrap_or(definition::CullMode::Back).into())
+                        .depth_bias_enable(depth_bias != definition::DepthBias::default())
+                        .depth_bias_constant_factor(depth_bias.constant)
+                        .depth_bias_slope_factor(depth_bias.slope)
+                        .depth_bias_clamp(depth_bias.clamp)
                         .build(),
                 )
                 .depth_stencil_state(
                     &vk::PipelineDepthStencilStateCreateInfo::builder()
-                        .depth_test_enable(true)
-                        .depth_write_enable(true)
-                        .depth_compare_op(vk::CompareOp::LESS_OR_EQUAL)
+                        .depth_test_enable(state.depth_test.unwrap_or(true))
+                        .depth_write_enable(state.depth_write.unwrap_or(true))
+                        .depth_compare_op(depth_compare.into())
                         .build(),
                 )
                 .multisample_state(
@@ -149,16 +243,7 @@ impl MaterialProgram {
                 )
                 .color_blend_state(
                     &vk::PipelineColorBlendStateCreateInfo::builder()
-                        .attachments(&[vk::PipelineColorBlendAttachmentState::builder()
-                            .color_write_mask(vk::ColorComponentFlags::all())
-                            .blend_enable(true)
-                            .dst_color_blend_factor(vk::BlendFactor::ONE_MINUS_SRC_ALPHA)
-                            .src_color_blend_factor(vk::BlendFactor::SRC_COLOR)
-                            .color_blend_op(vk::BlendOp::ADD)
-                            .dst_alpha_blend_factor(vk::BlendFactor::ONE_MINUS_SRC_ALPHA)
-                            .src_alpha_blend_factor(vk::BlendFactor::ONE)
-                            .alpha_blend_op(vk::BlendOp::ADD)
-                            .build()])
+                        .attachments(&[color_blend_attachment])
                         .build(),
                 )
                 .dynamic_state(
@@ -169,12 +254,29 @@ impl MaterialProgram {
                 .render_pass(render_pass)
                 .subpass(0)
                 .build(),
-        )
+        )?)
     }
 }
 
 pub struct Material {
     pub program: u32,
-    pub pipeline: device::Pipeline,
+    pub queue: definition::RenderQueue,
+    pub priority: i32,
+    /// Only created for the depth modes used by the scene's cameras.
+    pub pipeline: Option<device::Pipeline>,
+    pub reverse_z_pipeline: Option<device::Pipeline>,
     pub descriptors: device::DescriptorSet,
+    /// Holds the buffer of the program's parameter values, if it has any, for as long as the
+    /// descriptor set refers to it.
+    pub _parameters: Option<device::Buffer>,
+}
+
+impl Material {
+    pub fn pipeline(&self, reverse_z: bool) -> Option<&device::Pipeline> {
+        if reverse_z {
+            self.reverse_z_pipeline.as_ref()
+        } else {
+            self.pipeline.as_ref()
+        }
+    }
 }
@@ -1,29 +1,68 @@
-use std::collections::BTreeMap;
+use std::collections::{btree_map, BTreeMap};
+use std::sync::Arc;
 use std::time::Duration;
 
-use ash::prelude::VkResult;
 use ash::vk;
 
+use crate::context::Context;
 use crate::device::{self, AsRawHandle};
 use crate::error::*;
 use crate::math::*;
+use crate::renderer::{ColorSpace, FRAMES_IN_FLIGHT};
 use crate::resources;
-use crate::scene::camera::Projection;
 use notify::Watcher;
 use std::io::{Read, Seek};
 use std::path::{Path, PathBuf};
 
+mod animation;
 mod camera;
+mod controller;
 mod definition;
+mod gltf;
 mod material;
+mod parameters;
 mod textures;
+mod workers;
+
+struct Node {
+    parent: Option<usize>,
+    /// Replaces the translation, rotation and scale, which can be animated.
+    matrix: Option<Mat4>,
+    translation: Vec3,
+    rotation: Quaternion,
+    scale: Vec3,
+    world: Mat4,
+}
+
+impl Node {
+    fn local(&self) -> Mat4 {
+        match self.matrix {
+            Some(matrix) => matrix,
+            None => {
+                Mat4::translate(self.translation)
+                    * Mat4::rotate(self.rotation)
+                    * Mat4::scale(self.scale)
+            }
+        }
+    }
+}
 
 struct Model {
+    node: Option<usize>,
+    /// Relative to the node, if any.
     transform: Mat4,
+    world: Mat4,
     material: u32,
     mesh: resources::MeshObject,
 }
 
+/// The view uniforms of one frame in flight, so they can be written while the GPU may still be
+/// reading those of the previous frame.
+struct View {
+    uniform_buffer: device::Buffer,
+    set: device::DescriptorSet,
+}
+
 #[derive(Copy, Clone)]
 struct ViewUniforms {
     pub view: Mat4,
@@ -31,21 +70,37 @@ struct ViewUniforms {
     pub camera_position: Vec3,
 }
 
+/// A loaded scene and the files it was loaded from, to watch for changes.
+type LoadedScene = (Scene, Vec<PathBuf>);
+
 pub struct SceneWatcher {
+    context: Arc<Context>,
     change_watcher: notify::RecommendedWatcher,
     change_receiver: std::sync::mpsc::Receiver<notify::DebouncedEvent>,
     render_pass: vk::RenderPass,
     samples: vk::SampleCountFlags,
+    color_space: ColorSpace,
     path: PathBuf,
     scene: Scene,
     watch_paths: Vec<PathBuf>,
+    /// Whether to print a memory report after each load.
+    memory_report: bool,
+    /// The scene being reloaded on a background thread, swapped in once it's complete.
+    loading: Option<std::thread::JoinHandle<Result<LoadedScene>>>,
+    /// Set when files change while `loading`, to reload again once it completes.
+    reload_pending: bool,
+    /// Replaced scenes, with the number of frames to begin before no frame in flight uses them.
+    retired: Vec<(Scene, usize)>,
 }
 
 impl SceneWatcher {
     pub fn create(
+        context: &Arc<Context>,
         render_pass: vk::RenderPass,
         samples: vk::SampleCountFlags,
+        color_space: ColorSpace,
         path: &Path,
+        memory_report: bool,
     ) -> Result<Self> {
         let (tx, change_receiver) = std::sync::mpsc::channel();
         let mut change_watcher =
@@ -54,60 +109,104 @@ impl SceneWatcher {
             .watch(path, notify::RecursiveMode::NonRecursive)
             .unwrap();
 
-        let (scene, watch_paths) = Scene::parse(render_pass, samples, &path)?;
+        let (scene, watch_paths) = Scene::parse(context, render_pass, samples, color_space, path)?;
+        if memory_report {
+            print!("{}", device::MemoryReport::collect(context));
+        }
 
         for p in &watch_paths {
             change_watcher
-                .watch(&p, notify::RecursiveMode::NonRecursive)
+                .watch(p, notify::RecursiveMode::NonRecursive)
                 .unwrap();
         }
 
         Ok(Self {
+            context: context.clone(),
             change_watcher,
             change_receiver,
             render_pass,
             samples,
+            color_space,
             path: PathBuf::from(path),
             scene,
             watch_paths,
+            memory_report,
+            loading: None,
+            reload_pending: false,
+            retired: Vec::new(),
         })
     }
 
     fn check_reload(&mut self) -> Result<()> {
-        if let Ok(change) = self.change_receiver.try_recv() {
-            if matches!(
+        while let Ok(change) = self.change_receiver.try_recv() {
+            if !matches!(
                 change,
                 notify::DebouncedEvent::NoticeWrite(..) | notify::DebouncedEvent::NoticeRemove(..)
             ) {
-                return Ok(());
+                println!("change: {:?}", change);
+                self.reload_pending = true;
             }
-            println!("change: {:?}", change);
-
-            while self.change_receiver.try_recv().is_ok() {}
+        }
 
-            match Scene::parse(self.render_pass, self.samples, &self.path) {
+        if let Some(loading) = &self.loading {
+            if !loading.is_finished() {
+                return Ok(());
+            }
+            let result = self
+                .loading
+                .take()
+                .unwrap()
+                .join()
+                .expect("scene loader panicked");
+            match result {
                 Err(err) => {
                     eprintln!("failed to parse: {:?}", err);
                 }
-                Ok((mut scene, watch_paths)) => {
-                    for p in &self.watch_paths {
-                        self.change_watcher.unwatch(&p).unwrap();
-                    }
-                    for p in &watch_paths {
-                        self.change_watcher
-                            .watch(&p, notify::RecursiveMode::NonRecursive)
-                            .unwrap();
-                    }
-
-                    // Preserve aspect ratio (should probably be recomputed each frame?)
-                    scene.camera.projection = self.scene.camera.projection;
-
-                    self.scene = scene;
-                    self.watch_paths = watch_paths;
-                }
+                Ok((scene, watch_paths)) => self.swap_scene(scene, watch_paths)?,
             }
         }
 
+        if self.reload_pending {
+            self.reload_pending = false;
+            let context = self.context.clone();
+            let (render_pass, samples, color_space) =
+                (self.render_pass, self.samples, self.color_space);
+            let path = self.path.clone();
+            self.loading = Some(std::thread::spawn(move || {
+                Scene::parse(&context, render_pass, samples, color_space, &path)
+            }));
+        }
+
+        Ok(())
+    }
+
+    fn swap_scene(&mut self, mut scene: Scene, watch_paths: Vec<PathBuf>) -> Result<()> {
+        for p in &self.watch_paths {
+            self.change_watcher.unwatch(p).unwrap();
+        }
+        for p in &watch_paths {
+            self.change_watcher
+                .watch(p, notify::RecursiveMode::NonRecursive)
+                .unwrap();
+        }
+
+        // Preserve aspect ratio (should probably be recomputed each frame?)
+        scene.set_aspect(self.scene.aspect);
+        // Keep the user's view rather than jumping back to the initial camera.
+        if self.scene.active_camera < scene.cameras.len() {
+            scene.active_camera = self.scene.active_camera;
+            scene.camera.projection = scene.cameras[scene.active_camera].camera.projection;
+            scene.camera.projection.set_aspect(scene.aspect);
+        }
+        std::mem::swap(&mut scene.controller, &mut self.scene.controller);
+
+        // Frames in flight may still be using the old scene, so keep it until they've completed.
+        let old = std::mem::replace(&mut self.scene, scene);
+        self.retired.push((old, FRAMES_IN_FLIGHT));
+        self.watch_paths = watch_paths;
+        if self.memory_report {
+            print!("{}", device::MemoryReport::collect(&self.context));
+        }
         Ok(())
     }
 
@@ -115,13 +214,50 @@ impl SceneWatcher {
         self.scene.resize(size);
     }
 
+    pub fn handle_window_event(&mut self, event: &winit::event::WindowEvent) {
+        use winit::event::{ElementState, KeyboardInput, VirtualKeyCode, WindowEvent};
+
+        if let WindowEvent::KeyboardInput {
+            input:
+                KeyboardInput {
+                    virtual_keycode: Some(VirtualKeyCode::M),
+                    state: ElementState::Pressed,
+                    ..
+                },
+            ..
+        } = event
+        {
+            print!("{}", device::MemoryReport::collect(&self.context));
+            return;
+        }
+        self.scene.handle_window_event(event);
+    }
+
+    pub fn handle_device_event(&mut self, event: &winit::event::DeviceEvent) {
+        self.scene.controller.handle_device_event(event);
+    }
+
     pub fn update(&mut self, elapsed: Duration) {
         self.check_reload().unwrap();
         self.scene.update(elapsed);
     }
 
-    pub fn render(&self, recorder: &device::CommandBufferRenderPassRecorder) -> Result<()> {
-        self.scene.render(recorder)
+    pub fn clear_depth(&self) -> f32 {
+        self.scene.camera.projection.clear_depth()
+    }
+
+    pub fn render(
+        &mut self,
+        recorder: &device::CommandBufferRenderPassRecorder,
+        frame: usize,
+    ) -> Result<()> {
+        // Beginning a frame waits for the frame that used its resources before, so once as many
+        // frames as are in flight have begun, none of them can be using a retired scene.
+        self.retired.retain_mut(|(_, frames)| {
+            *frames -= 1;
+            *frames > 0
+        });
+        self.scene.render(recorder, frame)
     }
 }
 
@@ -130,83 +266,175 @@ pub struct Scene {
     programs: BTreeMap<u32, material::MaterialProgram>,
     materials: BTreeMap<u32, material::Material>,
     textures: BTreeMap<u32, resources::Texture>,
-    memories: Vec<device::Memory>,
+    memories: Vec<device::Allocation>,
+    // Parents are always before their children.
+    nodes: Vec<Node>,
     models: Vec<Model>,
+    animations: Vec<animation::Animation>,
     descriptor_pool: device::DescriptorPool,
-    view_set: device::DescriptorSet,
-    view_uniform_buffer: device::Buffer,
-    camera: camera::Camera<camera::PerspectiveProjection>,
+    // One per frame in flight.
+    views: Vec<View>,
+    aspect: f32,
+    // The active camera, as moved by the controller.
+    camera: camera::Camera,
+    cameras: Vec<SceneCamera>,
+    active_camera: usize,
+    controller: controller::Controller,
+}
+
+struct SceneCamera {
+    name: Option<String>,
+    camera: camera::Camera,
+    target: Vec3,
 }
 
 impl Scene {
     pub fn parse(
+        context: &Arc<Context>,
         render_pass: vk::RenderPass,
         samples: vk::SampleCountFlags,
+        color_space: ColorSpace,
         path: &Path,
-    ) -> Result<(Self, Vec<PathBuf>)> {
-        let scene: definition::Scene = serde_yaml::from_reader(std::fs::File::open(path)?)?;
+    ) -> Result<LoadedScene> {
+        let mut scene: definition::Scene = match path.extension().and_then(|e| e.to_str()) {
+            Some("gltf") | Some("glb") => gltf::load(path)?,
+            _ => serde_yaml::from_reader(std::fs::File::open(path)?)?,
+        };
+
+        let mut paths = vec![];
+
+        for import in std::mem::take(&mut scene.imports) {
+            paths.push(PathBuf::from(&import.path));
+            gltf::import(&mut scene, &import)?;
+        }
 
-        let view_descriptors_layout = device::DescriptorSetLayout::builder()
+        let view_descriptors_layout = device::DescriptorSetLayout::builder(context)
             .add_uniform_buffer(0, vk::ShaderStageFlags::ALL)
             .build()?;
 
-        let mut compiler = resources::Compiler::new();
-
-        let mut programs = BTreeMap::new();
-        for p in &scene.programs {
-            programs.insert(
-                p.id,
-                material::MaterialProgram::create(
+        // Shaders are compiled, and textures and buffers read and uploaded, on worker threads.
+        let programs = workers::map(
+            &scene.programs,
+            || {
+                let mut compiler = resources::Compiler::new(context);
+                if let Some(name) = color_space.shader_macro() {
+                    compiler.define(name);
+                }
+                Ok(compiler)
+            },
+            |compiler, p| {
+                let program = material::MaterialProgram::create(
+                    context,
                     p,
-                    &mut compiler,
+                    compiler,
                     view_descriptors_layout.as_raw(),
-                )?,
-            );
-        }
-
+                )?;
+                Ok((p.id, program))
+            },
+            |_| Ok(()),
+        )?
+        .into_iter()
+        .collect::<BTreeMap<_, _>>();
+
+        // One set per material plus a view set per frame in flight.
+        let material_textures = scene
+            .materials
+            .iter()
+            .map(|m| m.textures.len() as u32)
+            .sum::<u32>();
+        let material_parameters = scene
+            .materials
+            .iter()
+            .filter(|m| programs[&m.program].parameters.is_some())
+            .count() as u32;
         let descriptor_pool = device::DescriptorPool::create(
-            1 + scene.textures.len() as u32,
+            context,
+            FRAMES_IN_FLIGHT as u32 + scene.materials.len() as u32,
             &[
                 vk::DescriptorPoolSize {
                     ty: vk::DescriptorType::UNIFORM_BUFFER,
-                    descriptor_count: 1,
+                    descriptor_count: FRAMES_IN_FLIGHT as u32 + material_parameters,
                 },
                 vk::DescriptorPoolSize {
                     ty: vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
-                    descriptor_count: scene.textures.len() as u32,
+                    descriptor_count: material_textures.max(1),
                 },
             ],
         )?;
 
-        let mut paths = vec![];
-
-        let mut textures = BTreeMap::new();
-        for t in &scene.textures {
-            paths.push(PathBuf::from(&t.path));
-
-            textures.insert(
-                t.id,
-                match t.format {
-                    definition::TextureFormat::Ktx => textures::load_ktx(&t.path),
+        paths.extend(scene.textures.iter().map(|t| PathBuf::from(&t.path)));
+        let textures = workers::map(
+            &scene.textures,
+            || Ok(()),
+            |_, t| {
+                let texture = match t.format {
+                    definition::TextureFormat::Ktx => textures::load_ktx(context, &t.path),
                     definition::TextureFormat::Png => textures::load_png(
+                        context,
                         &t.path,
                         matches!(t.space, definition::TextureColorSpace::Srgb),
                     ),
-                }?,
+                }?;
+                Ok((t.id, texture))
+            },
+            |_| Ok(()),
+        )?
+        .into_iter()
+        .collect::<BTreeMap<_, _>>();
+
+        let mut views = Vec::new();
+        for _ in 0..FRAMES_IN_FLIGHT {
+            let uniform_buffer = device::Buffer::create(
+                context,
+                device::size_of::<ViewUniforms>(),
+                vk::BufferUsageFlags::UNIFORM_BUFFER,
+            )?;
+            let set = descriptor_pool.allocate(view_descriptors_layout.as_raw())?;
+            set.update_buffer(
+                0,
+                vk::DescriptorType::UNIFORM_BUFFER,
+                uniform_buffer.as_raw(),
+                0,
+                device::size_of::<ViewUniforms>(),
             );
+            views.push(View {
+                uniform_buffer,
+                set,
+            });
         }
 
-        let view_set = descriptor_pool.allocate(view_descriptors_layout.as_raw())?;
-
-        let view_uniform_buffer = device::Buffer::create(
-            device::size_of::<ViewUniforms>(),
-            vk::BufferUsageFlags::UNIFORM_BUFFER,
-        )?;
+        // The depth compare op is baked into the pipelines, so create them for each depth mode
+        // a camera uses. The default camera uses standard Z.
+        let uses_reverse_z = scene.cameras.iter().any(|c| c.reverse_z);
+        let uses_standard_z =
+            scene.cameras.is_empty() || scene.cameras.iter().any(|c| !c.reverse_z);
+
+        if context.features.depth_bias_clamp == vk::FALSE
+            && scene.materials.iter().any(|m| {
+                let state = programs[&m.program].render_state.with(&m.render_state);
+                state.depth_bias.is_some_and(|bias| bias.clamp != 0.0)
+            })
+        {
+            eprintln!("depth bias clamp not supported, ignoring it");
+        }
 
         let mut materials = BTreeMap::new();
         for m in &scene.materials {
             let program = &programs[&m.program];
-            let pipeline = program.create_material_pipeline(render_pass, samples)?;
+            let create_pipeline = |used: bool, reverse_z: bool| -> Result<_> {
+                if !used {
+                    return Ok(None);
+                }
+                Ok(Some(program.create_material_pipeline(
+                    render_pass,
+                    samples,
+                    m.id,
+                    &m.render_state,
+                    reverse_z,
+                )?))
+            };
+            let pipeline = create_pipeline(uses_standard_z, false)?;
+            let reverse_z_pipeline = create_pipeline(uses_reverse_z, true)?;
             let descriptors = descriptor_pool.allocate(program.descriptors_layout.as_raw())?;
             for t in &m.textures {
                 let texture = &textures[&t.texture];
@@ -217,70 +445,173 @@ impl Scene {
                     vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
                 );
             }
+            let parameters = match &program.parameters {
+                Some(layout) => {
+                    let data = layout.pack(&m.parameters).map_err(|err| match err {
+                        Error::Definition(message) => {
+                            Error::Definition(format!("material {}: {}", m.id, message))
+                        }
+                        err => err,
+                    })?;
+                    let mut buffer = device::Buffer::create(
+                        context,
+                        data.len() as vk::DeviceSize,
+                        vk::BufferUsageFlags::UNIFORM_BUFFER,
+                    )?;
+                    buffer.write_slice(0, &data)?;
+                    descriptors.update_buffer(
+                        layout.binding,
+                        vk::DescriptorType::UNIFORM_BUFFER,
+                        buffer.as_raw(),
+                        0,
+                        data.len() as vk::DeviceSize,
+                    );
+                    Some(buffer)
+                }
+                None if !m.parameters.is_empty() => {
+                    return Err(Error::Definition(format!(
+                        "material {} sets parameters its program doesn't have",
+                        m.id
+                    )));
+                }
+                None => None,
+            };
             materials.insert(
                 m.id,
                 material::Material {
                     program: m.program,
+                    queue: m
+                        .queue
+                        .unwrap_or_else(|| program.render_state.with(&m.render_state).queue()),
+                    priority: m.priority,
                     pipeline,
+                    reverse_z_pipeline,
                     descriptors,
+                    _parameters: parameters,
                 },
             );
         }
 
-        let mut buffer_files = BTreeMap::new();
-        let mut memories = Vec::new();
+        let buffer_paths = scene
+            .buffers
+            .iter()
+            .map(|b| (b.id, b.path.as_str()))
+            .collect::<BTreeMap<_, _>>();
+        paths.extend(buffer_paths.values().map(PathBuf::from));
+
+        // Each worker opens the buffer files it reads from, so they can seek independently.
+        let meshes = workers::map(
+            &scene.meshes,
+            || Ok((resources::Uploader::new(context), BTreeMap::new())),
+            |(uploader, files), m| {
+                let mut memories = Vec::new();
+                let mut buffer_view = |view: &definition::BufferView,
+                                       usage: vk::BufferUsageFlags|
+                 -> Result<device::BufferObject> {
+                    let file = match files.entry(view.buffer) {
+                        btree_map::Entry::Occupied(entry) => entry.into_mut(),
+                        btree_map::Entry::Vacant(entry) => {
+                            let path = buffer_paths.get(&view.buffer).ok_or_else(|| {
+                                Error::Definition(format!("unknown buffer {}", view.buffer))
+                            })?;
+                            entry.insert(std::fs::File::open(path)?)
+                        }
+                    };
+                    file.seek(std::io::SeekFrom::Start(view.offset))?;
+                    let buffer = uploader.create_buffer(view.size, usage, |chunk| {
+                        file.read_exact(chunk)?;
+                        Ok(())
+                    })?;
+                    memories.push(buffer.memory);
+                    Ok(buffer.object)
+                };
+
+                let mut vertex_buffers = Vec::new();
+                for b in &m.bindings {
+                    vertex_buffers.push((
+                        b.binding,
+                        buffer_view(&b.view, vk::BufferUsageFlags::VERTEX_BUFFER)?,
+                    ));
+                }
+                let index_buffer =
+                    buffer_view(&m.indices.view, vk::BufferUsageFlags::INDEX_BUFFER)?;
+                let mesh = resources::MeshObject {
+                    vertex_buffers,
+                    index_buffer,
+                    index_type: m.indices.format.into(),
+                    index_count: m.indices.count,
+                };
+                Ok((mesh, memories))
+            },
+            |(mut uploader, _)| uploader.flush(),
+        )?;
 
-        for b in &scene.buffers {
-            buffer_files.insert(b.id, std::fs::File::open(&b.path)?);
-            paths.push(PathBuf::from(&b.path));
+        let (nodes, node_indices, mesh_nodes) = create_nodes(&scene.nodes)?;
+        if let Some(mesh) = mesh_nodes
+            .keys()
+            .find(|&&id| !scene.meshes.iter().any(|m| m.id == Some(id)))
+        {
+            return Err(Error::Definition(format!(
+                "a node attaches mesh {}, but no mesh has that id",
+                mesh
+            )));
         }
 
-        let mut buffer_view = |view: &definition::BufferView,
-                               usage: vk::BufferUsageFlags|
-         -> Result<device::BufferObject> {
-            let mut file = &buffer_files[&view.buffer];
-            let buffer = device::Buffer::create(view.size, usage)?;
-            let mut mapping = buffer.memory.map(0, view.size as usize)?;
-            file.seek(std::io::SeekFrom::Start(view.offset))?;
-            file.read_exact(mapping.slice(0, view.size as usize))?;
-            drop(mapping);
-            memories.push(buffer.memory);
-            Ok(buffer.object)
-        };
+        let animations = scene
+            .animations
+            .iter()
+            .map(|a| animation::Animation::create(a, &node_indices, &nodes))
+            .collect::<Result<Vec<_>>>()?;
 
         let mut models = Vec::new();
-        for m in &scene.meshes {
-            let mut vertex_buffers = Vec::new();
-            for b in &m.bindings {
-                vertex_buffers.push(buffer_view(&b.view, vk::BufferUsageFlags::VERTEX_BUFFER)?);
-            }
-            let index_buffer = buffer_view(&m.indices.view, vk::BufferUsageFlags::INDEX_BUFFER)?;
+        let mut memories = Vec::new();
+        for (m, (mesh, mesh_memories)) in scene.meshes.iter().zip(meshes) {
+            let transform = (&m.transform).into();
             models.push(Model {
-                transform: (&m.transform).into(),
+                node: m.id.and_then(|id| mesh_nodes.get(&id).copied()),
+                transform,
+                world: transform,
                 material: m.material,
-                mesh: resources::MeshObject {
-                    vertex_buffers,
-                    index_buffer,
-                    index_type: m.indices.format.into(),
-                    index_count: m.indices.count,
-                },
+                mesh,
             });
+            memories.extend(mesh_memories);
         }
 
-        view_set.update_buffer(
-            0,
-            vk::DescriptorType::UNIFORM_BUFFER,
-            view_uniform_buffer.as_raw(),
-            0,
-            device::size_of::<ViewUniforms>(),
-        );
+        let mut controller = controller::Controller::new(&scene.camera);
+
+        let cameras = scene
+            .cameras
+            .iter()
+            .map(|c| {
+                let position = Vec3::from(c.position);
+                let rotation = c.rotation.map_or(Quaternion::default(), Quaternion::from);
+                let target = match c.look_at {
+                    Some(target) => target.into(),
+                    None => position + rotation.rotate(Vec3::Z_NEG * scene.camera.distance),
+                };
+                SceneCamera {
+                    name: c.name.clone(),
+                    camera: camera::Camera {
+                        transform: camera::Transform { position, rotation },
+                        projection: c.into(),
+                    },
+                    target,
+                }
+            })
+            .collect::<Vec<_>>();
 
-        let camera = camera::Camera {
-            transform: Default::default(),
-            projection: camera::PerspectiveProjection {
-                near: 0.00001,
-                far: 100.0,
-                ..Default::default()
+        let camera = match cameras.first() {
+            Some(first) => {
+                controller.look_at(first.camera.transform.position, first.target);
+                first.camera
+            }
+            None => camera::Camera {
+                transform: Default::default(),
+                projection: camera::Projection::Perspective(camera::PerspectiveProjection {
+                    near: 0.00001,
+                    far: Some(100.0),
+                    ..Default::default()
+                }),
             },
         };
 
@@ -289,30 +620,95 @@ impl Scene {
             materials,
             textures,
             memories,
+            nodes,
             models,
+            animations,
             descriptor_pool,
-            view_set,
-            view_uniform_buffer,
+            views,
+            aspect: 1.0,
             camera,
+            cameras,
+            active_camera: 0,
+            controller,
         };
 
         Ok((scene, paths))
     }
 
     pub fn resize(&mut self, size: (u32, u32)) {
-        let aspect = size.0 as f32 / size.1 as f32;
-        self.camera.projection.aspect = aspect;
+        self.set_aspect(size.0 as f32 / size.1 as f32);
+    }
+
+    fn set_aspect(&mut self, aspect: f32) {
+        self.aspect = aspect;
+        self.camera.projection.set_aspect(aspect);
+    }
+
+    pub fn handle_window_event(&mut self, event: &winit::event::WindowEvent) {
+        use winit::event::{ElementState, KeyboardInput, VirtualKeyCode, WindowEvent};
+
+        if let WindowEvent::KeyboardInput {
+            input:
+                KeyboardInput {
+                    virtual_keycode: Some(VirtualKeyCode::C),
+                    state: ElementState::Pressed,
+                    ..
+                },
+            ..
+        } = event
+        {
+            self.next_camera();
+            return;
+        }
+        self.controller.handle_window_event(event);
+    }
+
+    /// Switches to the next camera defined in the scene.
+    fn next_camera(&mut self) {
+        if self.cameras.is_empty() {
+            return;
+        }
+        self.active_camera = (self.active_camera + 1) % self.cameras.len();
+        let next = &self.cameras[self.active_camera];
+        match &next.name {
+            Some(name) => println!("camera: {}", name),
+            None => println!("camera: {}", self.active_camera),
+        }
+        self.camera = next.camera;
+        self.camera.projection.set_aspect(self.aspect);
+        self.controller
+            .look_at(next.camera.transform.position, next.target);
     }
 
     pub fn update(&mut self, elapsed: Duration) {
-        let rotate_around = Quaternion::axis_angle(Vec3::Y_POS, elapsed.as_secs_f32() * 0.3);
-        let rotate_down = Quaternion::axis_angle(Vec3::X_NEG, std::f32::consts::FRAC_PI_6);
-        self.camera.transform.rotation = rotate_around * rotate_down;
-        self.camera.transform.position = rotate_around.rotate([0.0, 0.02, 0.04].into());
+        self.controller.update(elapsed, &mut self.camera.transform);
+
+        for animation in &self.animations {
+            animation.apply(elapsed, &mut self.nodes);
+        }
+
+        for index in 0..self.nodes.len() {
+            let node = &self.nodes[index];
+            let world = match node.parent {
+                Some(parent) => self.nodes[parent].world * node.local(),
+                None => node.local(),
+            };
+            self.nodes[index].world = world;
+        }
+        for model in &mut self.models {
+            if let Some(node) = model.node {
+                model.world = self.nodes[node].world * model.transform;
+            }
+        }
     }
 
-    pub fn render(&self, recorder: &device::CommandBufferRenderPassRecorder) -> Result<()> {
-        self.view_uniform_buffer.write(
+    pub fn render(
+        &mut self,
+        recorder: &device::CommandBufferRenderPassRecorder,
+        frame: usize,
+    ) -> Result<()> {
+        let view = &mut self.views[frame];
+        view.uniform_buffer.write(
             0,
             &ViewUniforms {
                 view: self.camera.transform.matrix(),
@@ -320,95 +716,227 @@ impl Scene {
                 camera_position: self.camera.transform.position,
             },
         )?;
-
-        // TODO: sort by program (pipeline_layout) / material (pipeline)
-        for model in &self.models {
-            let material = &self.materials[&model.material];
-            let program = &self.programs[&material.program];
-            recorder.bind_pipeline(material.pipeline.as_raw());
-            let pipeline_layout = program.pipeline_layout.as_raw();
-            recorder.bind_descriptor_set(pipeline_layout, 0, self.view_set.as_raw());
-
-            // for each material
-            recorder.bind_descriptor_set(pipeline_layout, 1, material.descriptors.as_raw());
-
-            // for each model
+        let view_set = view.set.as_raw();
+
+        let reverse_z = self.camera.projection.reverse_z();
+        let view_matrix = self.camera.transform.matrix();
+        let mut draws = self
+            .models
+            .iter()
+            .map(|model| {
+                let material = &self.materials[&model.material];
+                let program = &self.programs[&material.program];
+                let pipeline = material
+                    .pipeline(reverse_z)
+                    .expect("no pipeline for the camera depth mode");
+                // The view looks down -Z.
+                let [_, _, z, _]: [f32; 4] = (view_matrix * model.world).row(3).into();
+                Draw {
+                    model,
+                    queue: material.queue,
+                    priority: material.priority,
+                    pipeline_layout: program.pipeline_layout.as_raw(),
+                    pipeline: pipeline.as_raw(),
+                    descriptors: material.descriptors.as_raw(),
+                    depth: -z,
+                }
+            })
+            .collect::<Vec<_>>();
+        draws.sort_by(Draw::order);
+
+        // The recorder skips binding state that's already bound.
+        for draw in &draws {
+            recorder.bind_pipeline(draw.pipeline);
+            recorder.bind_descriptor_set(draw.pipeline_layout, 0, view_set);
+            recorder.bind_descriptor_set(draw.pipeline_layout, 1, draw.descriptors);
             recorder.push(
-                pipeline_layout,
+                draw.pipeline_layout,
                 vk::ShaderStageFlags::VERTEX | vk::ShaderStageFlags::FRAGMENT,
                 0,
-                &model.transform,
+                &draw.model.world,
             );
-            model.mesh.draw(&recorder);
+            draw.model.mesh.draw(recorder);
         }
 
         Ok(())
     }
 }
 
-#[allow(dead_code)]
-struct MeshBuilder<V: Copy> {
-    vertices: Vec<V>,
-    indices: Vec<u32>,
+/// A model to draw this frame, with the state it's sorted by.
+struct Draw<M> {
+    model: M,
+    queue: definition::RenderQueue,
+    priority: i32,
+    pipeline_layout: vk::PipelineLayout,
+    pipeline: vk::Pipeline,
+    descriptors: vk::DescriptorSet,
+    /// The view-space distance of the model's origin in front of the camera.
+    depth: f32,
 }
 
-#[allow(dead_code)]
-impl<V: Copy> MeshBuilder<V> {
-    pub fn new() -> Self {
-        Self {
-            vertices: vec![],
-            indices: vec![],
-        }
+impl<M> Draw<M> {
+    /// Opaque draws are grouped by pipeline layout, pipeline and descriptor set to minimize
+    /// state changes, then drawn front to back so hidden fragments fail the depth test early.
+    /// Transparent draws are drawn back to front.
+    fn order(&self, other: &Self) -> std::cmp::Ordering {
+        use vk::Handle;
+
+        (self.queue, self.priority)
+            .cmp(&(other.queue, other.priority))
+            .then_with(|| match self.queue {
+                definition::RenderQueue::Opaque => (
+                    self.pipeline_layout.as_raw(),
+                    self.pipeline.as_raw(),
+                    self.descriptors.as_raw(),
+                )
+                    .cmp(&(
+                        other.pipeline_layout.as_raw(),
+                        other.pipeline.as_raw(),
+                        other.descriptors.as_raw(),
+                    ))
+                    .then_with(|| self.depth.total_cmp(&other.depth)),
+                definition::RenderQueue::Transparent => other.depth.total_cmp(&self.depth),
+            })
     }
+}
 
-    pub fn quad(&mut self, tl: V, tr: V, bl: V, br: V) {
-        let index = self.vertices.len() as u32;
-        self.vertices.extend_from_slice(&[tl, tr, bl, br]);
-        self.indices
-            .extend_from_slice(&[index, index + 2, index + 1]); // TL, BL, TR
-        self.indices
-            .extend_from_slice(&[index + 1, index + 2, index + 3]); // TR, BL, BR
-    }
+/// Returns the nodes ordered with parents first, and the node index for each node id and each
+/// attached mesh id.
+#[allow(clippy::type_complexity)]
+fn create_nodes(
+    definitions: &[definition::Node],
+) -> Result<(Vec<Node>, BTreeMap<u32, usize>, BTreeMap<u32, usize>)> {
+    let describe = |n: &definition::Node| match &n.name {
+        Some(name) => format!("node {} ({:?})", n.id, name),
+        None => format!("node {}", n.id),
+    };
+
+    let mut nodes = Vec::new();
+    let mut indices = BTreeMap::new();
+    let mut mesh_nodes = BTreeMap::new();
+
+    let mut pending = definitions.iter().collect::<Vec<_>>();
+    while !pending.is_empty() {
+        let mut ready = Vec::new();
+        pending.retain(|n| match n.parent {
+            Some(parent) if !indices.contains_key(&parent) => true,
+            _ => {
+                ready.push(*n);
+                false
+            }
+        });
+        if ready.is_empty() {
+            return Err(Error::Definition(format!(
+                "{} has a missing or cyclic parent",
+                describe(pending[0]),
+            )));
+        }
 
-    pub fn build(&self) -> VkResult<resources::Mesh> {
-        resources::Mesh::create::<V>(&self.vertices, &self.indices)
+        for n in ready {
+            let index = nodes.len();
+            if indices.insert(n.id, index).is_some() {
+                return Err(Error::Definition(format!("duplicate {}", describe(n))));
+            }
+            for &mesh in &n.meshes {
+                if mesh_nodes.insert(mesh, index).is_some() {
+                    return Err(Error::Definition(format!(
+                        "mesh {} is attached to more than one node",
+                        mesh
+                    )));
+                }
+            }
+            let mut node = Node {
+                parent: n.parent.map(|parent| indices[&parent]),
+                matrix: None,
+                translation: Vec3::ZERO,
+                rotation: Quaternion::default(),
+                scale: Vec3::ONE,
+                world: Mat4::IDENTITY,
+            };
+            match &n.transform {
+                definition::Transform::Literal(_) => node.matrix = Some((&n.transform).into()),
+                definition::Transform::TRS {
+                    translation,
+                    rotation,
+                    scale,
+                } => {
+                    node.translation = translation.map_or(Vec3::ZERO, Vec3::from);
+                    node.rotation = rotation.map_or(Quaternion::default(), Quaternion::from);
+                    node.scale = scale.map_or(Vec3::ONE, Vec3::from);
+                }
+                definition::Transform::Identity => {}
+            }
+            node.world = node.local();
+            nodes.push(node);
+        }
     }
+
+    Ok((nodes, indices, mesh_nodes))
 }
 
-#[repr(C)]
-#[derive(Copy, Clone)]
-struct Vertex(pub Vec3, pub Vec2, pub Vec4);
-
-fn create_box_mesh() -> VkResult<resources::Mesh> {
-    // "out" (determining facing) and "up" (determining texture orientation).
-    const FACES: &[(Vec3, Vec3)] = &[
-        (Vec3::X_POS, Vec3::Y_POS),
-        (Vec3::X_NEG, Vec3::Y_POS),
-        (Vec3::Y_POS, Vec3::Z_POS),
-        (Vec3::Y_NEG, Vec3::Z_POS),
-        (Vec3::Z_POS, Vec3::Y_POS),
-        (Vec3::Z_NEG, Vec3::Y_POS),
-    ];
-
-    let mut builder = MeshBuilder::new();
-    for &(out, up) in FACES {
-        let right = Vec3::cross(up, out);
-        let bl = out + -up + -right;
-        let br = out + -up + right;
-        let tl = out + up + -right;
-        let tr = out + up + right;
-
-        fn vertex(pos: Vec3, u: f32, v: f32) -> Vertex {
-            Vertex(pos, [u, v].into(), Vec4::from((pos + Vec3::ONE) * 0.5))
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use definition::RenderQueue::{Opaque, Transparent};
+    use vk::Handle;
+
+    fn draw(
+        name: &'static str,
+        queue: definition::RenderQueue,
+        priority: i32,
+        state: u64,
+        depth: f32,
+    ) -> Draw<&'static str> {
+        Draw {
+            model: name,
+            queue,
+            priority,
+            pipeline_layout: vk::PipelineLayout::from_raw(1),
+            pipeline: vk::Pipeline::from_raw(state),
+            descriptors: vk::DescriptorSet::from_raw(state),
+            depth,
         }
+    }
+
+    fn sorted(mut draws: Vec<Draw<&'static str>>) -> Vec<&'static str> {
+        draws.sort_by(Draw::order);
+        draws.into_iter().map(|d| d.model).collect()
+    }
+
+    #[test]
+    fn sorts_by_queue_then_priority() {
+        assert_eq!(
+            sorted(vec![
+                draw("transparent", Transparent, -1, 1, 1.0),
+                draw("opaque late", Opaque, 1, 1, 1.0),
+                draw("opaque", Opaque, 0, 2, 9.0),
+            ]),
+            ["opaque", "opaque late", "transparent"],
+        );
+    }
 
-        builder.quad(
-            vertex(tl, 0.0, 0.0),
-            vertex(tr, 1.0, 0.0),
-            vertex(bl, 0.0, 1.0),
-            vertex(br, 1.0, 1.0),
+    #[test]
+    fn sorts_opaque_by_state_then_front_to_back() {
+        assert_eq!(
+            sorted(vec![
+                draw("b far", Opaque, 0, 2, 5.0),
+                draw("a far", Opaque, 0, 1, 5.0),
+                draw("b near", Opaque, 0, 2, 1.0),
+                draw("a near", Opaque, 0, 1, 1.0),
+            ]),
+            ["a near", "a far", "b near", "b far"],
         );
     }
 
-    builder.build()
+    #[test]
+    fn sorts_transparent_back_to_front_across_states() {
+        assert_eq!(
+            sorted(vec![
+                draw("near", Transparent, 0, 1, 1.0),
+                draw("far", Transparent, 0, 2, 5.0),
+                draw("middle", Transparent, 0, 1, 3.0),
+            ]),
+            ["far", "middle", "near"],
+        );
+    }
 }
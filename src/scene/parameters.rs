@@ -0,0 +1,268 @@
+//! Packs material parameters into uniform blocks with the std140 layout.
+
+use std::collections::BTreeMap;
+
+use ash::vk;
+
+use crate::error::*;
+
+use super::definition::{ParameterBlock, ParameterType, ParameterValue};
+
+/// Where each of a program's parameters is in its uniform block.
+pub struct ParameterLayout {
+    pub binding: u32,
+    pub stages: vk::ShaderStageFlags,
+    fields: Vec<Field>,
+    /// The size of the block, rounded up to the alignment of a `vec4`.
+    pub size: u32,
+}
+
+struct Field {
+    name: String,
+    ty: ParameterType,
+    offset: u32,
+    default: Vec<u8>,
+}
+
+impl ParameterLayout {
+    pub fn new(block: &ParameterBlock) -> Result<Self> {
+        let mut fields = Vec::<Field>::with_capacity(block.fields.len());
+        let mut offset = 0u32;
+        for parameter in &block.fields {
+            if fields.iter().any(|f| f.name == parameter.name) {
+                return Err(Error::Definition(format!(
+                    "duplicate parameter {:?}",
+                    parameter.name
+                )));
+            }
+            let (size, align) = size_align(parameter.ty);
+            offset = offset.next_multiple_of(align);
+            let default = match &parameter.default {
+                Some(value) => encode(&parameter.name, parameter.ty, value)?,
+                None => vec![0; size as usize],
+            };
+            fields.push(Field {
+                name: parameter.name.clone(),
+                ty: parameter.ty,
+                offset,
+                default,
+            });
+            offset += size;
+        }
+
+        Ok(Self {
+            binding: block.binding,
+            stages: block
+                .stages
+                .iter()
+                .fold(vk::ShaderStageFlags::empty(), |a, &s| a | s.into()),
+            fields,
+            size: offset.next_multiple_of(16).max(16),
+        })
+    }
+
+    /// Packs a material's values, using the defaults for the parameters it doesn't set.
+    pub fn pack(&self, values: &BTreeMap<String, ParameterValue>) -> Result<Vec<u8>> {
+        if let Some(name) = values
+            .keys()
+            .find(|name| !self.fields.iter().any(|f| &f.name == *name))
+        {
+            return Err(Error::Definition(format!("unknown parameter {:?}", name)));
+        }
+
+        let mut data = vec![0; self.size as usize];
+        for field in &self.fields {
+            let encoded;
+            let bytes = match values.get(&field.name) {
+                Some(value) => {
+                    encoded = encode(&field.name, field.ty, value)?;
+                    &encoded
+                }
+                None => &field.default,
+            };
+            let offset = field.offset as usize;
+            data[offset..offset + bytes.len()].copy_from_slice(bytes);
+        }
+        Ok(data)
+    }
+}
+
+/// The size and alignment of a type in a std140 uniform block.
+fn size_align(ty: ParameterType) -> (u32, u32) {
+    match ty {
+        ParameterType::Float | ParameterType::Int => (4, 4),
+        ParameterType::Vec2 => (8, 8),
+        ParameterType::Vec3 => (12, 16),
+        ParameterType::Vec4 | ParameterType::Color => (16, 16),
+        ParameterType::Mat4 => (64, 16),
+    }
+}
+
+fn encode(name: &str, ty: ParameterType, value: &ParameterValue) -> Result<Vec<u8>> {
+    let values = match value {
+        ParameterValue::Scalar(value) => std::slice::from_ref(value),
+        ParameterValue::List(values) => values.as_slice(),
+    };
+    let invalid = || {
+        Error::Definition(format!(
+            "invalid value {:?} for {:?} parameter {:?}",
+            values, ty, name
+        ))
+    };
+
+    let floats = match (ty, values.len()) {
+        (ParameterType::Int, 1) => {
+            let value = values[0];
+            if value.fract() != 0.0 || value < i32::MIN as f64 || value > i32::MAX as f64 {
+                return Err(invalid());
+            }
+            return Ok((value as i32).to_ne_bytes().to_vec());
+        }
+        (ParameterType::Float, 1)
+        | (ParameterType::Vec2, 2)
+        | (ParameterType::Vec3, 3)
+        | (ParameterType::Vec4, 4)
+        | (ParameterType::Mat4, 16) => values.iter().map(|&v| v as f32).collect(),
+        (ParameterType::Color, 3) | (ParameterType::Color, 4) => {
+            let mut color = values[..3]
+                .iter()
+                .map(|&v| srgb_to_linear(v as f32))
+                .collect::<Vec<_>>();
+            color.push(values.get(3).map_or(1.0, |&a| a as f32));
+            color
+        }
+        _ => return Err(invalid()),
+    };
+    Ok(floats.iter().flat_map(|f| f.to_ne_bytes()).collect())
+}
+
+fn srgb_to_linear(value: f32) -> f32 {
+    if value <= 0.04045 {
+        value / 12.92
+    } else {
+        ((value + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::convert::TryInto;
+
+    use super::*;
+    use crate::scene::definition::Parameter;
+
+    fn layout(fields: &[(&str, ParameterType)]) -> ParameterLayout {
+        ParameterLayout::new(&ParameterBlock {
+            binding: 1,
+            stages: vec![],
+            fields: fields
+                .iter()
+                .map(|&(name, ty)| Parameter {
+                    name: name.to_string(),
+                    ty,
+                    default: None,
+                })
+                .collect(),
+        })
+        .unwrap()
+    }
+
+    fn pack(layout: &ParameterLayout, values: &[(&str, ParameterValue)]) -> Vec<f32> {
+        let values = values
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.clone()))
+            .collect();
+        layout
+            .pack(&values)
+            .unwrap()
+            .chunks(4)
+            .map(|bytes| f32::from_ne_bytes(bytes.try_into().unwrap()))
+            .collect()
+    }
+
+    fn offsets(layout: &ParameterLayout) -> Vec<u32> {
+        layout.fields.iter().map(|f| f.offset).collect()
+    }
+
+    #[test]
+    fn packs_a_float_after_a_vec3_into_its_last_component() {
+        let layout = layout(&[("a", ParameterType::Vec3), ("b", ParameterType::Float)]);
+        assert_eq!(offsets(&layout), [0, 12]);
+        assert_eq!(layout.size, 16);
+        assert_eq!(
+            pack(
+                &layout,
+                &[
+                    ("a", ParameterValue::List(vec![1.0, 2.0, 3.0])),
+                    ("b", ParameterValue::Scalar(4.0)),
+                ],
+            ),
+            [1.0, 2.0, 3.0, 4.0],
+        );
+    }
+
+    #[test]
+    fn aligns_mat4_to_a_vec4() {
+        let layout = layout(&[("a", ParameterType::Float), ("m", ParameterType::Mat4)]);
+        assert_eq!(offsets(&layout), [0, 16]);
+        assert_eq!(layout.size, 80);
+        let identity = [
+            1.0f32, 0.0, 0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 0.0, 1.0,
+        ];
+        let value = ParameterValue::List(identity.iter().map(|&v| f64::from(v)).collect());
+        let packed = pack(&layout, &[("m", value)]);
+        assert_eq!(packed[..4], [0.0; 4]);
+        assert_eq!(packed[4..], identity);
+    }
+
+    #[test]
+    fn converts_colors_from_srgb_to_linear() {
+        let layout = layout(&[
+            ("rgb", ParameterType::Color),
+            ("rgba", ParameterType::Color),
+        ]);
+        let packed = pack(
+            &layout,
+            &[
+                ("rgb", ParameterValue::List(vec![0.0, 0.5, 1.0])),
+                ("rgba", ParameterValue::List(vec![0.04, 1.0, 1.0, 0.5])),
+            ],
+        );
+        let expected = [0.0, 0.21404, 1.0, 1.0, 0.04 / 12.92, 1.0, 1.0, 0.5];
+        for (value, expected) in packed.iter().zip(expected) {
+            assert!((value - expected).abs() < 1e-5, "{:?}", packed);
+        }
+    }
+
+    #[test]
+    fn rounds_the_size_up_to_a_vec4() {
+        let float = ParameterType::Float;
+        assert_eq!(layout(&[]).size, 16);
+        assert_eq!(layout(&[("a", float)]).size, 16);
+        assert_eq!(layout(&[("a", ParameterType::Vec4), ("b", float)]).size, 32);
+        let layout = layout(&[("a", ParameterType::Vec2), ("b", ParameterType::Vec3)]);
+        assert_eq!(offsets(&layout), [0, 16]);
+        assert_eq!(layout.size, 32);
+    }
+
+    #[test]
+    fn uses_defaults_and_rejects_unknown_parameters() {
+        let mut block = ParameterBlock {
+            binding: 1,
+            stages: vec![],
+            fields: vec![Parameter {
+                name: "a".to_string(),
+                ty: ParameterType::Int,
+                default: Some(ParameterValue::Scalar(7.0)),
+            }],
+        };
+        let layout = ParameterLayout::new(&block).unwrap();
+        let packed = layout.pack(&BTreeMap::new()).unwrap();
+        assert_eq!(packed[..4], 7i32.to_ne_bytes());
+        let unknown = BTreeMap::from([("b".to_string(), ParameterValue::Scalar(1.0))]);
+        assert!(layout.pack(&unknown).is_err());
+
+        block.fields[0].default = Some(ParameterValue::Scalar(0.5));
+        assert!(ParameterLayout::new(&block).is_err());
+    }
+}
@@ -1,9 +1,10 @@
+use crate::context::*;
 use crate::device;
 use crate::device::{AsRawHandle, MemoryTypeMask};
-use crate::globals::*;
 use crate::resources;
 use std::fs;
 use std::io::{Read, Seek};
+use std::sync::Arc;
 
 // http://github.khronos.org/KTX-Specification/
 
@@ -36,7 +37,7 @@ struct LevelIndex {
     pub uncompressed_byte_length: u64,
 }
 
-pub fn load_ktx(path: &str) -> Result<resources::Texture> {
+pub fn load_ktx(context: &Arc<Context>, path: &str) -> Result<resources::Texture> {
     let mut file = fs::File::open(path)?;
 
     let mut header: Header = unsafe { std::mem::zeroed() };
@@ -76,6 +77,7 @@ pub fn load_ktx(path: &str) -> Result<resources::Texture> {
 
     let image_object = match header.faces {
         1 => device::ImageObject::create_2d(
+            context,
             (header.width, header.height),
             header.levels,
             header.format,
@@ -83,6 +85,7 @@ pub fn load_ktx(path: &str) -> Result<resources::Texture> {
             vk::ImageUsageFlags::SAMPLED | vk::ImageUsageFlags::TRANSFER_DST,
         ),
         6 => device::ImageObject::create_cube(
+            context,
             (header.width, header.height),
             header.levels,
             header.format,
@@ -91,84 +94,86 @@ pub fn load_ktx(path: &str) -> Result<resources::Texture> {
         _ => return Err(Error::Ktx),
     }?;
 
-    let data_buffer = device::Buffer::create(level_data_size, vk::BufferUsageFlags::TRANSFER_SRC)?;
-    let mut data_mapping = data_buffer.memory.map(0, level_data_size as usize)?;
+    let mut data_buffer =
+        device::Buffer::create(context, level_data_size, vk::BufferUsageFlags::TRANSFER_SRC)?;
+    let mut data_mapping = data_buffer
+        .memory
+        .slice_mut::<u8>(0, level_data_size as usize)?;
     file.seek(std::io::SeekFrom::Start(level_data_start))?;
-    file.read_exact(data_mapping.slice(0, level_data_size as usize))?;
+    file.read_exact(&mut data_mapping)?;
     drop(data_mapping);
 
     // Allocates and binds memory
-    let image = device::Image::create(image_object, MemoryTypeMask::any())?;
-
-    // Copy from level staging buffer to each image mip level
-    let recording = device::CommandBuffer::create()?;
-    // Transition to be a transfer target
-    recording.image_transition(
-        vk::PipelineStageFlags::HOST,
-        vk::PipelineStageFlags::TRANSFER,
-        &[vk::ImageMemoryBarrier::builder()
-            .image(image.object.as_raw())
-            .src_access_mask(vk::AccessFlags::empty())
-            .dst_access_mask(vk::AccessFlags::TRANSFER_READ)
-            .old_layout(vk::ImageLayout::UNDEFINED)
-            .new_layout(vk::ImageLayout::TRANSFER_DST_OPTIMAL)
-            .subresource_range(
-                vk::ImageSubresourceRange::builder()
-                    .aspect_mask(vk::ImageAspectFlags::COLOR)
-                    .layer_count(vk::REMAINING_ARRAY_LAYERS)
-                    .level_count(vk::REMAINING_MIP_LEVELS)
-                    .build(),
-            )
-            .build()],
-    );
-
-    for (level, indices) in level_indices.iter().enumerate() {
-        // for layer in header.layers.max(1) {
-        //     for face in header.faces {
-        recording.copy_buffer_to_image(
-            data_buffer.as_raw(),
-            image.object.as_raw(),
-            vk::ImageLayout::TRANSFER_DST_OPTIMAL,
-            &[vk::BufferImageCopy::builder()
-                .buffer_offset(indices.byte_offset - level_data_start)
-                .image_subresource(
-                    vk::ImageSubresourceLayers::builder()
-                        .layer_count(header.layers.max(1) * header.faces)
-                        .mip_level(level as u32)
-                        .aspect_mask(vk::ImageAspectFlags::COLOR)
-                        .build(),
-                )
-                .image_extent(vk::Extent3D {
-                    width: 1.max(header.width >> level),
-                    height: 1.max(header.height >> level),
-                    depth: 1.max(header.depth >> level),
-                })
-                .build()],
-        );
-        // }
-        // }
-    }
-    recording.image_transition(
-        vk::PipelineStageFlags::TRANSFER,
-        vk::PipelineStageFlags::FRAGMENT_SHADER,
-        &[vk::ImageMemoryBarrier::builder()
-            .image(image.object.as_raw())
-            .src_access_mask(vk::AccessFlags::TRANSFER_READ)
+    let image = device::Image::create(
+        image_object,
+        MemoryTypeMask::any(),
+        device::MemoryPurpose::Texture,
+    )?;
+
+    let all_levels = vk::ImageSubresourceRange::builder()
+        .aspect_mask(vk::ImageAspectFlags::COLOR)
+        .layer_count(vk::REMAINING_ARRAY_LAYERS)
+        .level_count(vk::REMAINING_MIP_LEVELS)
+        .build();
+
+    // Copy from level staging buffer to each image mip level on the transfer queue, then hand
+    // it over to the graphics queue to be sampled.
+    image.upload(
+        vk::ImageMemoryBarrier::builder()
+            .src_access_mask(vk::AccessFlags::TRANSFER_WRITE)
             .dst_access_mask(vk::AccessFlags::SHADER_READ)
             .old_layout(vk::ImageLayout::TRANSFER_DST_OPTIMAL)
             .new_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
-            .subresource_range(
-                vk::ImageSubresourceRange::builder()
-                    .aspect_mask(vk::ImageAspectFlags::COLOR)
-                    .layer_count(vk::REMAINING_ARRAY_LAYERS)
-                    .level_count(vk::REMAINING_MIP_LEVELS)
-                    .build(),
-            )
-            .build()],
-    );
-    recording.end()?.submit()?;
+            .subresource_range(all_levels)
+            .build(),
+        vk::PipelineStageFlags::FRAGMENT_SHADER,
+        |recording| {
+            // Transition to be a transfer target
+            recording.image_transition(
+                vk::PipelineStageFlags::HOST,
+                vk::PipelineStageFlags::TRANSFER,
+                &[vk::ImageMemoryBarrier::builder()
+                    .image(image.object.as_raw())
+                    .src_access_mask(vk::AccessFlags::empty())
+                    .dst_access_mask(vk::AccessFlags::TRANSFER_WRITE)
+                    .old_layout(vk::ImageLayout::UNDEFINED)
+                    .new_layout(vk::ImageLayout::TRANSFER_DST_OPTIMAL)
+                    .subresource_range(all_levels)
+                    .build()],
+            );
+
+            for (level, indices) in level_indices.iter().enumerate() {
+                // for layer in header.layers.max(1) {
+                //     for face in header.faces {
+                recording.copy_buffer_to_image(
+                    data_buffer.as_raw(),
+                    image.object.as_raw(),
+                    vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                    &[vk::BufferImageCopy::builder()
+                        .buffer_offset(indices.byte_offset - level_data_start)
+                        .image_subresource(
+                            vk::ImageSubresourceLayers::builder()
+                                .layer_count(header.layers.max(1) * header.faces)
+                                .mip_level(level as u32)
+                                .aspect_mask(vk::ImageAspectFlags::COLOR)
+                                .build(),
+                        )
+                        .image_extent(vk::Extent3D {
+                            width: 1.max(header.width >> level),
+                            height: 1.max(header.height >> level),
+                            depth: 1.max(header.depth >> level),
+                        })
+                        .build()],
+                );
+                // }
+                // }
+            }
+        },
+        |_| {},
+    )?;
 
     let image_view = device::ImageView::create(
+        context,
         image.object.as_raw(),
         if header.faces == 6 {
             vk::ImageViewType::CUBE
@@ -178,7 +183,7 @@ pub fn load_ktx(path: &str) -> Result<resources::Texture> {
         header.format,
         vk::ImageAspectFlags::COLOR,
     )?;
-    let sampler = device::Sampler::linear()?;
+    let sampler = device::Sampler::linear(context)?;
 
     let texture = resources::Texture {
         width: header.width,
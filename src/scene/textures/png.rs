@@ -1,29 +1,31 @@
+use crate::context::*;
 use crate::device;
 use crate::device::AsRawHandle;
-use crate::globals::*;
 use crate::resources;
 use std::fs;
+use std::sync::Arc;
 
-pub fn load_png(path: &str, srgb: bool) -> Result<resources::Texture> {
+pub fn load_png(context: &Arc<Context>, path: &str, srgb: bool) -> Result<resources::Texture> {
     let (info, mut reader) = png::Decoder::new(std::fs::File::open(&path)?).read_info()?;
 
     let texture_buffer = match info.color_type {
         // https://github.com/image-rs/image-png/issues/239
-        png::ColorType::RGB => expand_rgb(&info, &mut reader)?,
+        png::ColorType::RGB => expand_rgb(context, &info, &mut reader)?,
         png::ColorType::RGBA => {
             let texture_buffer_size = reader.output_buffer_size();
-            let texture_buffer = device::Buffer::create(
+            let mut texture_buffer = device::Buffer::create(
+                context,
                 texture_buffer_size as vk::DeviceSize,
                 vk::BufferUsageFlags::TRANSFER_SRC,
             )?;
-            let mut mapping = texture_buffer.memory.map(0, texture_buffer_size)?;
-            reader.next_frame(mapping.slice(0, texture_buffer_size))?;
+            reader.next_frame(&mut texture_buffer.memory.slice_mut(0, texture_buffer_size)?)?;
             texture_buffer
         }
         _ => unimplemented!("png::ColorType::{:?}", info.color_type),
     };
 
     let texture = resources::Texture::create(
+        context,
         info.width,
         info.height,
         if srgb {
@@ -38,20 +40,24 @@ pub fn load_png(path: &str, srgb: bool) -> Result<resources::Texture> {
 }
 
 fn expand_rgb(
+    context: &Arc<Context>,
     info: &png::OutputInfo,
     reader: &mut png::Reader<fs::File>,
 ) -> Result<device::Buffer> {
     let texture_buffer_size = (info.width * info.height * 4) as usize;
-    let texture_buffer = device::Buffer::create(
+    let mut texture_buffer = device::Buffer::create(
+        context,
         texture_buffer_size as vk::DeviceSize,
         vk::BufferUsageFlags::TRANSFER_SRC,
     )?;
-    let mut mapping = texture_buffer.memory.map(0, texture_buffer_size)?;
+    let mut mapping = texture_buffer
+        .memory
+        .slice_mut::<u8>(0, texture_buffer_size)?;
     println!(
         "expanding RGB -> RGBA: {}x{} = {:#x} bytes",
         info.width, info.height, texture_buffer_size
     );
-    let mut output: *mut u8 = mapping.slice(0, texture_buffer_size).as_mut_ptr();
+    let mut output: *mut u8 = mapping.as_mut_ptr();
     while let Some(row) = reader.next_row()? {
         let mut input = row.as_ptr();
         for _ in 0..info.width {
@@ -70,5 +76,6 @@ fn expand_rgb(
             }
         }
     }
+    drop(mapping);
     Ok(texture_buffer)
 }
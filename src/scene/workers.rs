@@ -0,0 +1,74 @@
+//! Runs scene loading jobs on a pool of worker threads.
+//!
+//! Each worker has its own state, e.g. a shader compiler or an uploader with a staging buffer,
+//! which is created on the worker thread so it doesn't need to be `Send`.
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+
+use crate::error::*;
+
+/// The number of worker threads, leaving one core for the render thread.
+fn worker_count(jobs: usize) -> usize {
+    let cores = std::thread::available_parallelism().map_or(1, |n| n.get());
+    (cores - 1).clamp(1, jobs)
+}
+
+/// Calls `job` for each of `items` on the worker threads, returning the results in order.
+///
+/// Each worker calls `init` to create its state before its first job, and `finish` with that
+/// state after its last, e.g. to submit pending uploads. Stops at the first error.
+pub fn map<I, S, T>(
+    items: &[I],
+    init: impl Fn() -> Result<S> + Sync,
+    job: impl Fn(&mut S, &I) -> Result<T> + Sync,
+    finish: impl Fn(S) -> Result<()> + Sync,
+) -> Result<Vec<T>>
+where
+    I: Sync,
+    T: Send,
+{
+    if items.is_empty() {
+        return Ok(Vec::new());
+    }
+    let next = AtomicUsize::new(0);
+    let results = Mutex::new((0..items.len()).map(|_| None).collect::<Vec<_>>());
+    let failed = Mutex::new(None);
+
+    let work = || -> Result<()> {
+        let mut state = init()?;
+        loop {
+            if failed.lock().unwrap().is_some() {
+                break;
+            }
+            let index = next.fetch_add(1, Ordering::Relaxed);
+            let item = match items.get(index) {
+                Some(item) => item,
+                None => break,
+            };
+            let result = job(&mut state, item)?;
+            results.lock().unwrap()[index] = Some(result);
+        }
+        finish(state)
+    };
+
+    std::thread::scope(|scope| {
+        for _ in 0..worker_count(items.len()) {
+            scope.spawn(|| {
+                if let Err(err) = work() {
+                    failed.lock().unwrap().get_or_insert(err);
+                }
+            });
+        }
+    });
+
+    if let Some(err) = failed.into_inner().unwrap() {
+        return Err(err);
+    }
+    Ok(results
+        .into_inner()
+        .unwrap()
+        .into_iter()
+        .map(|result| result.expect("job was not run"))
+        .collect())
+}
@@ -0,0 +1,167 @@
+//! Golden image regression tests.
+//!
+//! Renders every `.scene`/`.yaml` file under `assets/` with the headless renderer at a fixed
+//! size, held at time 0 from the initial camera with `--still`, then compares the result against
+//! the reference image checked in to `tests/golden/`.
+//!
+//! - `GOLDEN_TOLERANCE` sets the maximum allowed per-channel difference for a pixel (default 2).
+//! - `GOLDEN_UPDATE=1` writes the reference images from the current output.
+//!
+//! A missing reference image is a failure. Outputs and diff images (mismatched pixels in red over
+//! a faded reference) are written to `target/golden/`.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+const SIZE: (u32, u32) = (256, 256);
+const DEFAULT_TOLERANCE: u8 = 2;
+
+struct Image {
+    width: u32,
+    height: u32,
+    pixels: Vec<u8>,
+}
+
+fn load_png(path: &Path) -> Image {
+    let file = fs::File::open(path).unwrap_or_else(|err| panic!("{:?}: {}", path, err));
+    let (info, mut reader) = png::Decoder::new(file).read_info().unwrap();
+    assert!(
+        info.color_type == png::ColorType::RGBA && info.bit_depth == png::BitDepth::Eight,
+        "{:?}: expected 8-bit RGBA, got {:?} {:?}",
+        path,
+        info.bit_depth,
+        info.color_type,
+    );
+    let mut pixels = vec![0; reader.output_buffer_size()];
+    reader.next_frame(&mut pixels).unwrap();
+    Image {
+        width: info.width,
+        height: info.height,
+        pixels,
+    }
+}
+
+fn save_png(path: &Path, image: &Image) {
+    let file = std::io::BufWriter::new(fs::File::create(path).unwrap());
+    let mut encoder = png::Encoder::new(file, image.width, image.height);
+    encoder.set_color(png::ColorType::RGBA);
+    encoder.set_depth(png::BitDepth::Eight);
+    let mut writer = encoder.write_header().unwrap();
+    writer.write_image_data(&image.pixels).unwrap();
+}
+
+/// Returns the number of pixels with any channel differing by more than `tolerance`,
+/// and an image highlighting them.
+fn compare(actual: &Image, expected: &Image, tolerance: u8) -> (usize, Image) {
+    let mut mismatched = 0;
+    let mut diff = Vec::with_capacity(expected.pixels.len());
+    for (a, e) in actual
+        .pixels
+        .chunks_exact(4)
+        .zip(expected.pixels.chunks_exact(4))
+    {
+        if a.iter()
+            .zip(e)
+            .any(|(&a, &e)| a.max(e) - a.min(e) > tolerance)
+        {
+            mismatched += 1;
+            diff.extend_from_slice(&[0xff, 0, 0, 0xff]);
+        } else {
+            diff.extend_from_slice(&[e[0] / 4, e[1] / 4, e[2] / 4, 0xff]);
+        }
+    }
+    let diff = Image {
+        width: expected.width,
+        height: expected.height,
+        pixels: diff,
+    };
+    (mismatched, diff)
+}
+
+fn scenes() -> Vec<PathBuf> {
+    let mut scenes = fs::read_dir("assets")
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| {
+            matches!(
+                path.extension().and_then(|e| e.to_str()),
+                Some("scene") | Some("yaml")
+            )
+        })
+        .collect::<Vec<_>>();
+    scenes.sort();
+    scenes
+}
+
+#[test]
+fn golden_images() {
+    let tolerance = std::env::var("GOLDEN_TOLERANCE")
+        .map(|value| value.parse::<u8>().expect("GOLDEN_TOLERANCE"))
+        .unwrap_or(DEFAULT_TOLERANCE);
+    let update = std::env::var("GOLDEN_UPDATE").ok().as_deref() == Some("1");
+
+    let output_dir = Path::new("target/golden");
+    fs::create_dir_all(output_dir).unwrap();
+    if update {
+        fs::create_dir_all("tests/golden").unwrap();
+    }
+
+    let mut failures = vec![];
+    for scene in scenes() {
+        let name = scene.file_stem().unwrap().to_str().unwrap().to_string();
+        let output_path = output_dir.join(format!("{}.png", name));
+        let reference_path = Path::new("tests/golden").join(format!("{}.png", name));
+
+        let status = Command::new(env!("CARGO_BIN_EXE_rust-vk"))
+            .arg(&scene)
+            .arg("--headless")
+            .arg("--still")
+            .args(["--frames", "1"])
+            .arg("--size")
+            .arg(format!("{}x{}", SIZE.0, SIZE.1))
+            .arg("--screenshot")
+            .arg(&output_path)
+            .status()
+            .unwrap();
+        if !status.success() {
+            failures.push(format!("{}: render failed with {}", name, status));
+            continue;
+        }
+
+        if update {
+            fs::copy(&output_path, &reference_path).unwrap();
+            eprintln!("{}: wrote reference {:?}", name, reference_path);
+            continue;
+        }
+        if !reference_path.exists() {
+            failures.push(format!(
+                "{}: no reference image {:?}, run with GOLDEN_UPDATE=1 to create it",
+                name, reference_path,
+            ));
+            continue;
+        }
+
+        let actual = load_png(&output_path);
+        let expected = load_png(&reference_path);
+        if (actual.width, actual.height) != (expected.width, expected.height) {
+            failures.push(format!(
+                "{}: size {}x{} does not match reference {}x{}",
+                name, actual.width, actual.height, expected.width, expected.height,
+            ));
+            continue;
+        }
+
+        let (mismatched, diff) = compare(&actual, &expected, tolerance);
+        if mismatched != 0 {
+            let diff_path = output_dir.join(format!("{}.diff.png", name));
+            save_png(&diff_path, &diff);
+            failures.push(format!(
+                "{}: {} pixels differ by more than {}, see {:?}",
+                name, mismatched, tolerance, diff_path,
+            ));
+        }
+    }
+
+    assert!(failures.is_empty(), "\n{}", failures.join("\n"));
+}